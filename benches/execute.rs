@@ -0,0 +1,82 @@
+//! Measures how many instructions per second the emulator executes, run with
+//! `cargo bench --bench execute`
+
+use komodo::{Assembler, AssemblyCache, Machine, StopReason};
+use std::{hint::black_box, time::Instant};
+
+/// A loop of data processing, multiply, single and block transfer instructions
+const PROGRAM: &str = "
+ITERATIONS = 200000
+
+.data
+buffer:
+    .space 64
+
+.text
+    ldr r4, =buffer
+    ldr r5, =ITERATIONS
+loop:
+    add r0, r0, r5, lsl #2
+    eor r1, r1, r0, ror #3
+    str r0, [r4, #4]
+    ldr r2, [r4, #4]
+    ldrb r3, [r4, #1]
+    stmia r4, {r0-r3}
+    ldmia r4, {r0-r3}
+    mul r6, r2, r3
+    movs r7, r6, lsr r1
+    subs r5, r5, #1
+    bne loop
+    swi #2
+";
+
+const RUNS: usize = 5;
+
+fn main() {
+    let cs = komodo::new_capstone();
+    let (memory, _) = AssemblyCache::new(None)
+        .assemble(PROGRAM, &Assembler::Builtin)
+        .unwrap();
+    let mut machine = Machine::new(&cs, memory).unwrap();
+
+    // the engine before pre-decoding looked up the capstone instruction at pc
+    // and read its operands from the detail every time it executed it
+    let instrs = komodo::disassemble_text(&cs, &machine.memory).unwrap();
+    let text_base = instrs.as_ref()[0].address() as u32;
+    measure("capstone operands", &mut machine, |machine| {
+        let insn = &instrs.as_ref()[(machine.pc() - text_base) as usize / 4];
+        black_box(komodo::from_capstone(&cs, insn).1.unwrap());
+        machine.step(&|| '\0', &mut |_| {}).unwrap() == StopReason::Step
+    });
+    measure("pre-decoded", &mut machine, |machine| {
+        machine.step(&|| '\0', &mut |_| {}).unwrap() == StopReason::Step
+    });
+}
+
+/// Prints the instructions per second of the fastest of [`RUNS`] runs, `step`
+/// executes an instruction and returns `false` once the program has ended
+fn measure(name: &str, machine: &mut Machine, mut step: impl FnMut(&mut Machine) -> bool) {
+    let mut best = f64::MAX;
+    let mut executed = 0u64;
+    for _ in 0..RUNS {
+        machine.reset();
+        executed = 0;
+
+        let start = Instant::now();
+        loop {
+            executed += 1;
+            if !step(machine) {
+                break;
+            }
+        }
+        best = best.min(start.elapsed().as_secs_f64());
+    }
+
+    println!(
+        "{}: {} instructions in {:.3}s, {:.2} million instructions per second",
+        name,
+        executed,
+        best,
+        executed as f64 / best / 1e6
+    );
+}
@@ -0,0 +1,186 @@
+use crate::StatusFlags;
+
+/// Barrel shifter operations, A5.1 from DDI01001 spec
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shift {
+    /// Logical shift left
+    Lsl,
+    /// Logical shift right
+    Lsr,
+    /// Arithmetic shift right
+    Asr,
+    /// Rotate right
+    Ror,
+    /// Rotate right with extend, the carry flag is shifted into bit 31
+    Rrx,
+}
+
+/// Data processing opcodes, A3.4, p115 from DDI01001 spec
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    And,
+    Eor,
+    Sub,
+    Rsb,
+    Add,
+    Adc,
+    Sbc,
+    Rsc,
+    Tst,
+    Teq,
+    Cmp,
+    Cmn,
+    Orr,
+    Mov,
+    Bic,
+    Mvn,
+}
+
+impl TryFrom<&str> for Opcode {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        use Opcode::*;
+        Ok(match value {
+            "and" => And,
+            "eor" => Eor,
+            "sub" => Sub,
+            "rsb" => Rsb,
+            "add" => Add,
+            "adc" => Adc,
+            "sbc" => Sbc,
+            "rsc" => Rsc,
+            "tst" => Tst,
+            "teq" => Teq,
+            "cmp" => Cmp,
+            "cmn" => Cmn,
+            "orr" => Orr,
+            "mov" => Mov,
+            "bic" => Bic,
+            "mvn" => Mvn,
+            _ => return Err(format!("Unrecognised data processing opcode {}", value)),
+        })
+    }
+}
+
+impl Opcode {
+    /// `tst`, `teq`, `cmp` and `cmn` only update the flags
+    pub fn is_comparison(self) -> bool {
+        matches!(self, Opcode::Tst | Opcode::Teq | Opcode::Cmp | Opcode::Cmn)
+    }
+}
+
+/// Shifts `value` by `amount`, returning the result and the shifter carry out.
+///
+/// A shift of 0 leaves the value and carry unchanged, and shifts of 32 or more
+/// follow the register shift rules. `Rrx` always shifts by exactly one bit.
+// A5.1.5 to A5.1.13, p447 from DDI01001 spec
+pub fn shift(kind: Shift, value: u32, amount: u32, carry_in: bool) -> (u32, bool) {
+    let bit = |n: u32| value & (1 << n) != 0;
+
+    if kind == Shift::Rrx {
+        return (((carry_in as u32) << 31) | (value >> 1), bit(0));
+    }
+
+    if amount == 0 {
+        return (value, carry_in);
+    }
+
+    match kind {
+        Shift::Lsl => match amount {
+            1..=31 => (value << amount, bit(32 - amount)),
+            32 => (0, bit(0)),
+            _ => (0, false),
+        },
+        Shift::Lsr => match amount {
+            1..=31 => (value >> amount, bit(amount - 1)),
+            32 => (0, bit(31)),
+            _ => (0, false),
+        },
+        Shift::Asr => match amount {
+            1..=31 => (((value as i32) >> amount) as u32, bit(amount - 1)),
+            _ => (((value as i32) >> 31) as u32, bit(31)),
+        },
+        Shift::Ror => match amount % 32 {
+            0 => (value, bit(31)),
+            n => (value.rotate_right(n), bit(n - 1)),
+        },
+        Shift::Rrx => unreachable!(),
+    }
+}
+
+/// Value and carry out of an 8 bit immediate rotated right by twice `rotate_imm`.
+/// The carry flag is unchanged when there is no rotation.
+// A5.1.3, p446 from DDI01001 spec
+pub fn rotated_immediate(immed_8: u32, rotate_imm: u32, carry_in: bool) -> (u32, bool) {
+    let value = immed_8.rotate_right(2 * rotate_imm);
+    if rotate_imm == 0 {
+        (value, carry_in)
+    } else {
+        (value, value & (1 << 31) != 0)
+    }
+}
+
+/// `a + b + carry_in`, returning the result, unsigned carry out and signed overflow
+pub fn add_with_carry(a: u32, b: u32, carry_in: bool) -> (u32, bool, bool) {
+    let unsigned_sum = a as u64 + b as u64 + carry_in as u64;
+    let signed_sum = a as i32 as i64 + b as i32 as i64 + carry_in as i64;
+    let result = unsigned_sum as u32;
+
+    (
+        result,
+        unsigned_sum != result as u64,
+        signed_sum != result as i32 as i64,
+    )
+}
+
+/// Executes a data processing opcode on `rn` and the shifter operand, returning
+/// the result and the flags an `S` suffixed instruction would set.
+///
+/// Arithmetic opcodes set C and V from the adder, logical opcodes set C from the
+/// shifter and leave V unchanged. Subtraction is addition of the inverted operand
+/// with the carry set, so C is the inverse of borrow.
+// A4.1, p151 from DDI01001 spec
+pub fn data_processing(
+    opcode: Opcode,
+    rn: u32,
+    (shifter_operand, shifter_carry_out): (u32, bool),
+    flags: &StatusFlags,
+) -> (u32, StatusFlags) {
+    use Opcode::*;
+
+    let (value, carry, overflow) = match opcode {
+        Add | Cmn => add_with_carry(rn, shifter_operand, false),
+        Adc => add_with_carry(rn, shifter_operand, flags.carry),
+        Sub | Cmp => add_with_carry(rn, !shifter_operand, true),
+        Sbc => add_with_carry(rn, !shifter_operand, flags.carry),
+        Rsb => add_with_carry(shifter_operand, !rn, true),
+        Rsc => add_with_carry(shifter_operand, !rn, flags.carry),
+
+        And | Tst => (rn & shifter_operand, shifter_carry_out, flags.overflow),
+        Eor | Teq => (rn ^ shifter_operand, shifter_carry_out, flags.overflow),
+        Orr => (rn | shifter_operand, shifter_carry_out, flags.overflow),
+        Bic => (rn & !shifter_operand, shifter_carry_out, flags.overflow),
+        Mov => (shifter_operand, shifter_carry_out, flags.overflow),
+        Mvn => (!shifter_operand, shifter_carry_out, flags.overflow),
+    };
+
+    let mut new_flags = flags.clone();
+    new_flags.negative = value & (1 << 31) != 0;
+    new_flags.zero = value == 0;
+    new_flags.carry = carry;
+    new_flags.overflow = overflow;
+
+    (value, new_flags)
+}
+
+/// 64 bit product of `rm` and `rs` added to `accumulate`, used by `umull`,
+/// `umlal`, `smull` and `smlal`. The result wraps on overflow.
+pub fn multiply_long(rm: u32, rs: u32, signed: bool, accumulate: u64) -> u64 {
+    let product = if signed {
+        (rm as i32 as i64).wrapping_mul(rs as i32 as i64) as u64
+    } else {
+        rm as u64 * rs as u64
+    };
+    product.wrapping_add(accumulate)
+}
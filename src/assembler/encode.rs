@@ -0,0 +1,1084 @@
+use super::expr::{Context, Expr};
+
+/// Encoding of `nop`, `mov r0, r0`
+const NOP: u32 = 0x01a0_0000;
+
+/// How an operand is shifted before use, the amount is 0 to 32
+#[derive(Debug, Clone)]
+pub enum Shift {
+    Imm(u32, Expr),
+    Reg(u32, u32),
+    Rrx,
+}
+
+/// The flexible second operand of data processing instructions
+#[derive(Debug, Clone)]
+pub enum Operand2 {
+    Imm(Expr),
+    Reg(u32, Option<Shift>),
+}
+
+#[derive(Debug, Clone)]
+pub enum Offset {
+    Imm(Expr),
+    Reg {
+        subtract: bool,
+        rm: u32,
+        shift: Option<Shift>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub enum Address {
+    /// `[rn, offset]{!}` when `pre`, otherwise `[rn], offset`
+    Offset {
+        rn: u32,
+        offset: Offset,
+        pre: bool,
+        writeback: bool,
+    },
+    /// `=expr`, replaced by a `mov`, `mvn` or a literal pool load before encoding
+    Literal(Expr),
+    /// An address loaded relative to `pc`
+    Label(Expr),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Size {
+    Word,
+    Byte,
+    Half,
+    SignedByte,
+    SignedHalf,
+}
+
+#[derive(Debug, Clone)]
+pub enum Kind {
+    DataProcessing {
+        opcode: u32,
+        s: bool,
+        rd: u32,
+        rn: u32,
+        op2: Operand2,
+    },
+    Multiply {
+        accumulate: bool,
+        s: bool,
+        rd: u32,
+        rm: u32,
+        rs: u32,
+        rn: u32,
+    },
+    MultiplyLong {
+        signed: bool,
+        accumulate: bool,
+        s: bool,
+        rdlo: u32,
+        rdhi: u32,
+        rm: u32,
+        rs: u32,
+    },
+    Transfer {
+        load: bool,
+        size: Size,
+        translate: bool,
+        rd: u32,
+        address: Address,
+    },
+    Block {
+        load: bool,
+        before: bool,
+        up: bool,
+        user: bool,
+        writeback: bool,
+        rn: u32,
+        list: u16,
+    },
+    Swap {
+        byte: bool,
+        rd: u32,
+        rm: u32,
+        rn: u32,
+    },
+    Branch {
+        link: bool,
+        target: Expr,
+    },
+    BranchExchange {
+        rm: u32,
+    },
+    Swi(Expr),
+    Mrs {
+        spsr: bool,
+        rd: u32,
+    },
+    Msr {
+        spsr: bool,
+        mask: u32,
+        op2: Operand2,
+    },
+    Adr {
+        rd: u32,
+        target: Expr,
+    },
+    Nop,
+}
+
+/// A parsed instruction, operands are encoded once every label has an address
+#[derive(Debug, Clone)]
+pub struct Instruction {
+    pub cond: u32,
+    pub kind: Kind,
+}
+
+const MOV: u32 = 0b1101;
+const MVN: u32 = 0b1111;
+
+const DATA_PROCESSING: [&str; 16] = [
+    "and", "eor", "sub", "rsb", "add", "adc", "sbc", "rsc", "tst", "teq", "cmp", "cmn", "orr",
+    "mov", "bic", "mvn",
+];
+
+const SHIFTS: [&str; 4] = ["lsl", "lsr", "asr", "ror"];
+
+const CONDITIONS: [&str; 17] = [
+    "eq", "ne", "cs", "cc", "mi", "pl", "vs", "vc", "hi", "ls", "ge", "lt", "gt", "le", "al", "hs",
+    "lo",
+];
+
+const S: &[&str] = &["", "s"];
+const NONE: &[&str] = &[""];
+const BLOCK: &[&str] = &["", "ia", "ib", "da", "db", "fd", "ed", "fa", "ea"];
+
+/// Mnemonics without their condition, and the suffixes each accepts
+const MNEMONICS: [(&str, &[&str]); 38] = [
+    ("and", S),
+    ("eor", S),
+    ("sub", S),
+    ("rsb", S),
+    ("add", S),
+    ("adc", S),
+    ("sbc", S),
+    ("rsc", S),
+    ("tst", NONE),
+    ("teq", NONE),
+    ("cmp", NONE),
+    ("cmn", NONE),
+    ("orr", S),
+    ("mov", S),
+    ("bic", S),
+    ("mvn", S),
+    ("lsl", S),
+    ("lsr", S),
+    ("asr", S),
+    ("ror", S),
+    ("rrx", S),
+    ("mul", S),
+    ("mla", S),
+    ("umull", S),
+    ("umlal", S),
+    ("smull", S),
+    ("smlal", S),
+    ("ldr", &["", "b", "h", "sb", "sh", "t", "bt"]),
+    ("str", &["", "b", "h", "t", "bt"]),
+    ("ldm", BLOCK),
+    ("stm", BLOCK),
+    ("push", NONE),
+    ("pop", NONE),
+    ("swp", &["", "b"]),
+    ("b", NONE),
+    ("bl", NONE),
+    ("bx", NONE),
+    ("swi", NONE),
+];
+
+/// Mnemonics added by the assembler rather than the architecture
+const PSEUDO: [&str; 5] = ["svc", "mrs", "msr", "adr", "nop"];
+
+fn condition(name: &str) -> Option<u32> {
+    match name {
+        "" => Some(0b1110),
+        "hs" => Some(0b0010),
+        "lo" => Some(0b0011),
+        _ => CONDITIONS[..15]
+            .iter()
+            .position(|cond| *cond == name)
+            .map(|i| i as u32),
+    }
+}
+
+/// Splits `addeqs` or `addseq` into the base mnemonic, suffix and condition
+fn split_mnemonic(mnemonic: &str) -> Option<(&'static str, &'static str, u32)> {
+    let bases = MNEMONICS
+        .iter()
+        .copied()
+        .chain(PSEUDO.iter().map(|base| (*base, NONE)));
+
+    for (base, suffixes) in bases {
+        let Some(rest) = mnemonic.strip_prefix(base) else {
+            continue;
+        };
+        for suffix in suffixes {
+            for cond in CONDITIONS.iter().chain(&[""]) {
+                if rest == format!("{suffix}{cond}") || rest == format!("{cond}{suffix}") {
+                    return Some((base, suffix, condition(cond).unwrap()));
+                }
+            }
+        }
+    }
+    None
+}
+
+/// `r0`-`r15` and the APCS names
+pub fn register(name: &str) -> Option<u32> {
+    let name = name.trim().to_ascii_lowercase();
+    match name.as_str() {
+        "sb" => return Some(9),
+        "sl" => return Some(10),
+        "fp" => return Some(11),
+        "ip" => return Some(12),
+        "sp" => return Some(13),
+        "lr" => return Some(14),
+        "pc" => return Some(15),
+        _ => {}
+    }
+
+    let (prefix, number) = name.split_at_checked(1)?;
+    let n: u32 = number.parse().ok()?;
+    if number.starts_with('+') {
+        return None;
+    }
+    match prefix {
+        "r" if n <= 15 => Some(n),
+        "a" if (1..=4).contains(&n) => Some(n - 1),
+        "v" if (1..=8).contains(&n) => Some(n + 3),
+        _ => None,
+    }
+}
+
+fn expect_register(operand: &str) -> Result<u32, String> {
+    register(operand).ok_or(format!("ARM register expected -- `{}`", operand.trim()))
+}
+
+/// `#expr`, the `#` is optional
+fn immediate(operand: &str) -> Result<Expr, String> {
+    let operand = operand.trim();
+    let operand = operand
+        .strip_prefix('#')
+        .or(operand.strip_prefix('$'))
+        .unwrap_or(operand);
+    Expr::parse(operand)
+}
+
+/// Splits operands on the commas outside of brackets, braces, strings and
+/// character literals
+pub fn split_operands(text: &str) -> Vec<&str> {
+    let mut operands = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                let mut escaped = false;
+                for (_, c) in chars.by_ref() {
+                    match c {
+                        _ if escaped => escaped = false,
+                        '\\' => escaped = true,
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            // `'a'` or `'\n'`, the closing quote is optional
+            '\'' => {
+                if chars.next().is_some_and(|(_, c)| c == '\\') {
+                    chars.next();
+                }
+                chars.next_if(|(_, c)| *c == '\'');
+            }
+            '[' | '{' | '(' => depth += 1,
+            ']' | '}' | ')' => depth -= 1,
+            ',' if depth == 0 => {
+                operands.push(text[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    let last = text[start..].trim();
+    if !last.is_empty() || !operands.is_empty() {
+        operands.push(last);
+    }
+    operands
+}
+
+fn expect_operands(operands: &[&str], counts: &[usize]) -> Result<(), String> {
+    if counts.contains(&operands.len()) {
+        Ok(())
+    } else if operands.len() < counts[0] {
+        Err("missing operand".to_string())
+    } else {
+        Err(format!("unexpected operand `{}`", operands.last().unwrap()))
+    }
+}
+
+/// `lsl #2`, `asr r1` or `rrx`
+fn shift(operand: &str) -> Result<Shift, String> {
+    let operand = operand.trim();
+    let lower = operand.to_ascii_lowercase();
+    if lower == "rrx" {
+        return Ok(Shift::Rrx);
+    }
+
+    let (name, _) = lower
+        .split_at_checked(3)
+        .ok_or(format!("shift expression expected -- `{}`", operand))?;
+    let kind = match name {
+        "asl" => 0,
+        _ => SHIFTS
+            .iter()
+            .position(|shift| *shift == name)
+            .ok_or(format!("shift expression expected -- `{}`", operand))? as u32,
+    };
+    let amount = operand[3..].trim();
+    if amount.is_empty() {
+        return Err(format!("missing shift amount -- `{}`", operand));
+    }
+    match register(amount) {
+        Some(rs) => Ok(Shift::Reg(kind, rs)),
+        None => Ok(Shift::Imm(kind, immediate(amount)?)),
+    }
+}
+
+/// `#imm` or `rm{, shift}` from the operands starting at `operands[0]`
+fn operand2(operands: &[&str]) -> Result<Operand2, String> {
+    match operands {
+        [operand] if register(operand).is_none() => Ok(Operand2::Imm(immediate(operand)?)),
+        [rm] => Ok(Operand2::Reg(expect_register(rm)?, None)),
+        [rm, shift_op] => Ok(Operand2::Reg(expect_register(rm)?, Some(shift(shift_op)?))),
+        [] => Err("missing operand".to_string()),
+        [.., last] => Err(format!("unexpected operand `{}`", last)),
+    }
+}
+
+/// `{r0-r3, lr}`, the `^` suffix is returned as the second element
+fn register_list(operand: &str) -> Result<(u16, bool), String> {
+    let operand = operand.trim();
+    let (operand, user) = match operand.strip_suffix('^') {
+        Some(operand) => (operand.trim_end(), true),
+        None => (operand, false),
+    };
+    let inner = operand
+        .strip_prefix('{')
+        .and_then(|operand| operand.strip_suffix('}'))
+        .ok_or(format!("expected register list -- `{}`", operand))?;
+
+    let mut list = 0;
+    for item in inner.split(',') {
+        let (first, last) = match item.split_once('-') {
+            Some((first, last)) => (expect_register(first)?, expect_register(last)?),
+            None => {
+                let reg = expect_register(item)?;
+                (reg, reg)
+            }
+        };
+        if first > last {
+            return Err(format!("bad range in register list -- `{}`", item.trim()));
+        }
+        for reg in first..=last {
+            list |= 1 << reg;
+        }
+    }
+    Ok((list, user))
+}
+
+/// `[rn, offset]{!}`, `[rn], offset`, `=expr` or `label`
+fn address(operands: &[&str]) -> Result<Address, String> {
+    let Some(first) = operands.first() else {
+        return Err("missing operand".to_string());
+    };
+
+    if let Some(literal) = first.strip_prefix('=') {
+        expect_operands(operands, &[1])?;
+        return Ok(Address::Literal(Expr::parse(literal)?));
+    }
+    if !first.starts_with('[') {
+        expect_operands(operands, &[1])?;
+        return Ok(Address::Label(Expr::parse(first)?));
+    }
+
+    let (inner, writeback) = match first.strip_suffix('!') {
+        Some(first) => (first.trim_end(), true),
+        None => (*first, false),
+    };
+    let inner = inner
+        .strip_prefix('[')
+        .and_then(|inner| inner.strip_suffix(']'))
+        .ok_or(format!("missing `]` -- `{}`", first))?;
+    let inner = split_operands(inner);
+    let rn = expect_register(inner.first().ok_or("missing base register")?)?;
+
+    if inner.len() > 1 {
+        expect_operands(operands, &[1])?;
+        Ok(Address::Offset {
+            rn,
+            offset: offset(&inner[1..])?,
+            pre: true,
+            writeback,
+        })
+    } else if operands.len() > 1 {
+        if writeback {
+            return Err("writeback used with post-indexed addressing".to_string());
+        }
+        Ok(Address::Offset {
+            rn,
+            offset: offset(&operands[1..])?,
+            pre: false,
+            writeback: false,
+        })
+    } else {
+        Ok(Address::Offset {
+            rn,
+            offset: Offset::Imm(Expr::Number(0)),
+            pre: true,
+            writeback,
+        })
+    }
+}
+
+/// `#imm`, `{+|-}rm` or `{+|-}rm, shift`
+fn offset(operands: &[&str]) -> Result<Offset, String> {
+    let first = operands[0].trim();
+    let (subtract, reg) = match first.strip_prefix('-') {
+        Some(reg) => (true, reg),
+        None => (false, first.strip_prefix('+').unwrap_or(first)),
+    };
+
+    match (register(reg), operands) {
+        (Some(rm), [_]) => Ok(Offset::Reg {
+            subtract,
+            rm,
+            shift: None,
+        }),
+        (Some(rm), [_, shift_op]) => Ok(Offset::Reg {
+            subtract,
+            rm,
+            shift: Some(shift(shift_op)?),
+        }),
+        (None, [imm]) => Ok(Offset::Imm(immediate(imm)?)),
+        (_, [.., last]) => Err(format!("unexpected operand `{}`", last)),
+        (_, []) => unreachable!(),
+    }
+}
+
+/// `cpsr`, `spsr_fc` or `apsr_nzcvq`, returns whether it is the SPSR and the field mask
+fn status_register(operand: &str) -> Result<(bool, u32), String> {
+    let lower = operand.trim().to_ascii_lowercase();
+    let (name, fields) = lower.split_once('_').unwrap_or((&lower, ""));
+    let spsr = match name {
+        "cpsr" | "apsr" => false,
+        "spsr" => true,
+        _ => return Err(format!("bad status register -- `{}`", operand.trim())),
+    };
+
+    let mask = match (name, fields) {
+        (_, "" | "all") => 0b1001,
+        (_, "flg") => 0b1000,
+        (_, "ctl") => 0b0001,
+        ("apsr", "nzcvq") => 0b1000,
+        ("apsr", "g") => 0b0100,
+        ("apsr", "nzcvqg") => 0b1100,
+        _ => {
+            let mut mask = 0;
+            for field in fields.chars() {
+                let bit = match field {
+                    'c' => 0b0001,
+                    'x' => 0b0010,
+                    's' => 0b0100,
+                    'f' => 0b1000,
+                    _ => 0,
+                };
+                if bit == 0 || mask & bit != 0 {
+                    return Err(format!("bad status register -- `{}`", operand.trim()));
+                }
+                mask |= bit;
+            }
+            mask
+        }
+    };
+    Ok((spsr, mask))
+}
+
+impl Instruction {
+    /// Parses an instruction, `mnemonic` is lowercase
+    pub fn parse(mnemonic: &str, operands: &[&str]) -> Result<Instruction, String> {
+        let (base, suffix, cond) =
+            split_mnemonic(mnemonic).ok_or(format!("bad instruction `{}`", mnemonic))?;
+        let s = suffix == "s";
+
+        let reg = |i: usize| expect_register(operands[i]);
+
+        let kind = match base {
+            _ if DATA_PROCESSING.contains(&base) => {
+                let opcode = DATA_PROCESSING.iter().position(|op| *op == base).unwrap() as u32;
+                match opcode {
+                    // tst, teq, cmp and cmn always set the flags
+                    0b1000..=0b1011 => {
+                        expect_operands(operands, &[2, 3])?;
+                        Kind::DataProcessing {
+                            opcode,
+                            s: true,
+                            rd: 0,
+                            rn: reg(0)?,
+                            op2: operand2(&operands[1..])?,
+                        }
+                    }
+                    MOV | MVN => {
+                        expect_operands(operands, &[2, 3])?;
+                        Kind::DataProcessing {
+                            opcode,
+                            s,
+                            rd: reg(0)?,
+                            rn: 0,
+                            op2: operand2(&operands[1..])?,
+                        }
+                    }
+                    _ => {
+                        expect_operands(operands, &[2, 3, 4])?;
+                        // `add r0, #1` is `add r0, r0, #1`
+                        let (rn, op2) = match operands.len() {
+                            2 => (reg(0)?, operand2(&operands[1..])?),
+                            _ if register(operands[1]).is_none() => {
+                                return Err(format!("ARM register expected -- `{}`", operands[1]));
+                            }
+                            _ => (reg(1)?, operand2(&operands[2..])?),
+                        };
+                        Kind::DataProcessing {
+                            opcode,
+                            s,
+                            rd: reg(0)?,
+                            rn,
+                            op2,
+                        }
+                    }
+                }
+            }
+            "lsl" | "lsr" | "asr" | "ror" => {
+                expect_operands(operands, &[2, 3])?;
+                let kind = SHIFTS.iter().position(|shift| *shift == base).unwrap() as u32;
+                let (rm, amount) = match operands.len() {
+                    2 => (reg(0)?, operands[1]),
+                    _ => (reg(1)?, operands[2]),
+                };
+                let shift = match register(amount) {
+                    Some(rs) => Shift::Reg(kind, rs),
+                    None => Shift::Imm(kind, immediate(amount)?),
+                };
+                Kind::DataProcessing {
+                    opcode: MOV,
+                    s,
+                    rd: reg(0)?,
+                    rn: 0,
+                    op2: Operand2::Reg(rm, Some(shift)),
+                }
+            }
+            "rrx" => {
+                expect_operands(operands, &[2])?;
+                Kind::DataProcessing {
+                    opcode: MOV,
+                    s,
+                    rd: reg(0)?,
+                    rn: 0,
+                    op2: Operand2::Reg(reg(1)?, Some(Shift::Rrx)),
+                }
+            }
+            "mul" => {
+                expect_operands(operands, &[3])?;
+                Kind::Multiply {
+                    accumulate: false,
+                    s,
+                    rd: reg(0)?,
+                    rm: reg(1)?,
+                    rs: reg(2)?,
+                    rn: 0,
+                }
+            }
+            "mla" => {
+                expect_operands(operands, &[4])?;
+                Kind::Multiply {
+                    accumulate: true,
+                    s,
+                    rd: reg(0)?,
+                    rm: reg(1)?,
+                    rs: reg(2)?,
+                    rn: reg(3)?,
+                }
+            }
+            "umull" | "umlal" | "smull" | "smlal" => {
+                expect_operands(operands, &[4])?;
+                Kind::MultiplyLong {
+                    signed: base.starts_with('s'),
+                    accumulate: base.ends_with("lal"),
+                    s,
+                    rdlo: reg(0)?,
+                    rdhi: reg(1)?,
+                    rm: reg(2)?,
+                    rs: reg(3)?,
+                }
+            }
+            "ldr" | "str" => {
+                expect_operands(operands, &[2, 3, 4])?;
+                let size = match suffix {
+                    "b" | "bt" => Size::Byte,
+                    "h" => Size::Half,
+                    "sb" => Size::SignedByte,
+                    "sh" => Size::SignedHalf,
+                    _ => Size::Word,
+                };
+                let translate = suffix.ends_with('t');
+                let mut address = address(&operands[1..])?;
+                if translate {
+                    // `ldrt r0, [r1]` is post-indexed
+                    address = match address {
+                        Address::Offset {
+                            rn,
+                            offset: Offset::Imm(Expr::Number(0)),
+                            pre: true,
+                            writeback: false,
+                        } => Address::Offset {
+                            rn,
+                            offset: Offset::Imm(Expr::Number(0)),
+                            pre: false,
+                            writeback: false,
+                        },
+                        Address::Offset { pre: false, .. } => address,
+                        _ => {
+                            return Err(
+                                "this instruction requires a post-indexed address".to_string()
+                            );
+                        }
+                    }
+                }
+                if matches!(address, Address::Literal(_)) && (base == "str" || size != Size::Word) {
+                    return Err("invalid pseudo operation".to_string());
+                }
+                Kind::Transfer {
+                    load: base == "ldr",
+                    size,
+                    translate,
+                    rd: reg(0)?,
+                    address,
+                }
+            }
+            "ldm" | "stm" => {
+                expect_operands(operands, &[2])?;
+                let load = base == "ldm";
+                let mode = match (load, suffix) {
+                    (_, "" | "ia") | (true, "fd") | (false, "ea") => "ia",
+                    (_, "ib") | (true, "ed") | (false, "fa") => "ib",
+                    (_, "da") | (true, "fa") | (false, "ed") => "da",
+                    _ => "db",
+                };
+                let (rn, writeback) = match operands[0].trim().strip_suffix('!') {
+                    Some(rn) => (expect_register(rn)?, true),
+                    None => (reg(0)?, false),
+                };
+                let (list, user) = register_list(operands[1])?;
+                Kind::Block {
+                    load,
+                    before: mode.ends_with('b'),
+                    up: mode.starts_with('i'),
+                    user,
+                    writeback,
+                    rn,
+                    list,
+                }
+            }
+            "push" | "pop" => {
+                expect_operands(operands, &[1])?;
+                let (list, user) = register_list(operands[0])?;
+                if user {
+                    return Err("`^` is not allowed here".to_string());
+                }
+                Kind::Block {
+                    load: base == "pop",
+                    before: base == "push",
+                    up: base == "pop",
+                    user: false,
+                    writeback: true,
+                    rn: 13,
+                    list,
+                }
+            }
+            "swp" => {
+                expect_operands(operands, &[3])?;
+                let rn = operands[2]
+                    .trim()
+                    .strip_prefix('[')
+                    .and_then(|rn| rn.strip_suffix(']'))
+                    .ok_or(format!("expected `[rn]` -- `{}`", operands[2]))?;
+                Kind::Swap {
+                    byte: suffix == "b",
+                    rd: reg(0)?,
+                    rm: reg(1)?,
+                    rn: expect_register(rn)?,
+                }
+            }
+            "b" | "bl" => {
+                expect_operands(operands, &[1])?;
+                Kind::Branch {
+                    link: base == "bl",
+                    target: Expr::parse(operands[0])?,
+                }
+            }
+            "bx" => {
+                expect_operands(operands, &[1])?;
+                Kind::BranchExchange { rm: reg(0)? }
+            }
+            "swi" | "svc" => {
+                expect_operands(operands, &[1])?;
+                Kind::Swi(immediate(operands[0])?)
+            }
+            "mrs" => {
+                expect_operands(operands, &[2])?;
+                let (spsr, _) = status_register(operands[1])?;
+                Kind::Mrs { spsr, rd: reg(0)? }
+            }
+            "msr" => {
+                expect_operands(operands, &[2])?;
+                let (spsr, mask) = status_register(operands[0])?;
+                Kind::Msr {
+                    spsr,
+                    mask,
+                    op2: operand2(&operands[1..])?,
+                }
+            }
+            "adr" => {
+                expect_operands(operands, &[2])?;
+                Kind::Adr {
+                    rd: reg(0)?,
+                    target: Expr::parse(operands[1])?,
+                }
+            }
+            "nop" => {
+                expect_operands(operands, &[0])?;
+                Kind::Nop
+            }
+            _ => unreachable!("Unrecognised mnemonic {}", base),
+        };
+
+        Ok(Instruction { cond, kind })
+    }
+
+    /// The `mov` or `mvn` that loads `value` when it is a valid immediate
+    pub fn load_constant(cond: u32, rd: u32, value: u32) -> Option<Instruction> {
+        let opcode = if encode_immediate(value).is_some() {
+            MOV
+        } else if encode_immediate(!value).is_some() {
+            MVN
+        } else {
+            return None;
+        };
+        let value = if opcode == MOV { value } else { !value };
+
+        Some(Instruction {
+            cond,
+            kind: Kind::DataProcessing {
+                opcode,
+                s: false,
+                rd,
+                rn: 0,
+                op2: Operand2::Imm(Expr::Number(value as i64)),
+            },
+        })
+    }
+
+    /// Encodes the instruction placed at `address`
+    pub fn encode(&self, address: u32, context: &impl Context) -> Result<u32, String> {
+        let eval = |expr: &Expr| expr.eval(context);
+        let cond = self.cond << 28;
+        let pc = address.wrapping_add(8) as i64;
+
+        let word = match &self.kind {
+            Kind::DataProcessing {
+                opcode,
+                s,
+                rd,
+                rn,
+                op2,
+            } => {
+                let (opcode, op2) = match op2 {
+                    Operand2::Imm(expr) => {
+                        let (opcode, imm) = data_immediate(*opcode, value32(eval(expr)?)?)?;
+                        (opcode, imm | 1 << 25)
+                    }
+                    Operand2::Reg(rm, shift) => (*opcode, shifted_register(*rm, shift, context)?),
+                };
+                opcode << 21 | (*s as u32) << 20 | rn << 16 | rd << 12 | op2
+            }
+            Kind::Multiply {
+                accumulate,
+                s,
+                rd,
+                rm,
+                rs,
+                rn,
+            } => {
+                (*accumulate as u32) << 21
+                    | (*s as u32) << 20
+                    | rd << 16
+                    | rn << 12
+                    | rs << 8
+                    | 0b1001 << 4
+                    | rm
+            }
+            Kind::MultiplyLong {
+                signed,
+                accumulate,
+                s,
+                rdlo,
+                rdhi,
+                rm,
+                rs,
+            } => {
+                1 << 23
+                    | (*signed as u32) << 22
+                    | (*accumulate as u32) << 21
+                    | (*s as u32) << 20
+                    | rdhi << 16
+                    | rdlo << 12
+                    | rs << 8
+                    | 0b1001 << 4
+                    | rm
+            }
+            Kind::Transfer {
+                load,
+                size,
+                translate,
+                rd,
+                address: transfer_address,
+            } => {
+                let (rn, offset, pre, writeback) = match transfer_address {
+                    Address::Offset {
+                        rn,
+                        offset,
+                        pre,
+                        writeback,
+                    } => (*rn, offset.clone(), *pre, *writeback || *translate),
+                    Address::Label(expr) => {
+                        (15, Offset::Imm(Expr::Number(eval(expr)? - pc)), true, false)
+                    }
+                    Address::Literal(_) => {
+                        unreachable!("Literal loads are resolved before encoding")
+                    }
+                };
+                let halfword = !matches!(size, Size::Word | Size::Byte);
+
+                let (up, offset) = match offset {
+                    Offset::Imm(expr) => {
+                        let value = eval(&expr)?;
+                        let limit = if halfword { 0xff } else { 0xfff };
+                        if value.unsigned_abs() > limit {
+                            return Err(format!("bad immediate value for offset ({})", value));
+                        }
+                        let up = value >= 0;
+                        let value = value.unsigned_abs() as u32;
+                        if halfword {
+                            (up, 1 << 22 | (value & 0xf0) << 4 | value & 0xf)
+                        } else {
+                            (up, value)
+                        }
+                    }
+                    Offset::Reg {
+                        subtract,
+                        rm,
+                        shift,
+                    } => {
+                        if halfword && shift.is_some() {
+                            return Err("shifts are not allowed here".to_string());
+                        }
+                        if matches!(shift, Some(Shift::Reg(..))) {
+                            return Err("shift by register not allowed here".to_string());
+                        }
+                        let offset = shifted_register(rm, &shift, context)?;
+                        if halfword {
+                            (!subtract, offset)
+                        } else {
+                            (!subtract, 1 << 25 | offset)
+                        }
+                    }
+                };
+
+                let fields = (pre as u32) << 24
+                    | (up as u32) << 23
+                    | (writeback as u32) << 21
+                    | (*load as u32) << 20
+                    | rn << 16
+                    | rd << 12
+                    | offset;
+                match size {
+                    Size::Word => 1 << 26 | fields,
+                    Size::Byte => 1 << 26 | 1 << 22 | fields,
+                    Size::Half => fields | 0b1011 << 4,
+                    Size::SignedByte => fields | 0b1101 << 4,
+                    Size::SignedHalf => fields | 0b1111 << 4,
+                }
+            }
+            Kind::Block {
+                load,
+                before,
+                up,
+                user,
+                writeback,
+                rn,
+                list,
+            } => {
+                // a single register push or pop is a `str` or `ldr`, as `as` emits
+                if *rn == 13 && *writeback && !user && list.count_ones() == 1 {
+                    let rd = list.trailing_zeros();
+                    match (load, before, up) {
+                        (false, true, false) => return Ok(cond | 0x052d_0004 | rd << 12),
+                        (true, false, true) => return Ok(cond | 0x049d_0004 | rd << 12),
+                        _ => {}
+                    }
+                }
+                if *list == 0 {
+                    return Err("empty register list".to_string());
+                }
+                0b100 << 25
+                    | (*before as u32) << 24
+                    | (*up as u32) << 23
+                    | (*user as u32) << 22
+                    | (*writeback as u32) << 21
+                    | (*load as u32) << 20
+                    | rn << 16
+                    | *list as u32
+            }
+            Kind::Swap { byte, rd, rm, rn } => {
+                0b10 << 23 | (*byte as u32) << 22 | rn << 16 | rd << 12 | 0b1001 << 4 | rm
+            }
+            Kind::Branch { link, target } => {
+                let offset = eval(target)? - pc;
+                if offset % 4 != 0 {
+                    return Err("branch target is not word aligned".to_string());
+                }
+                if !(-(1 << 25)..(1 << 25)).contains(&offset) {
+                    return Err("branch out of range".to_string());
+                }
+                0b101 << 25 | (*link as u32) << 24 | (offset >> 2) as u32 & 0x00ff_ffff
+            }
+            Kind::BranchExchange { rm } => 0x012f_ff10 | rm,
+            Kind::Swi(expr) => {
+                let value = eval(expr)?;
+                if !(0..=0x00ff_ffff).contains(&value) {
+                    return Err(format!("invalid swi expression ({})", value));
+                }
+                0b1111 << 24 | value as u32
+            }
+            Kind::Mrs { spsr, rd } => 0b10 << 23 | (*spsr as u32) << 22 | 0xf << 16 | rd << 12,
+            Kind::Msr { spsr, mask, op2 } => {
+                let op2 = match op2 {
+                    Operand2::Imm(expr) => {
+                        1 << 25
+                            | encode_immediate(value32(eval(expr)?)?)
+                                .ok_or("invalid constant after fixup")?
+                    }
+                    Operand2::Reg(rm, None) => *rm,
+                    Operand2::Reg(..) => return Err("shifts are not allowed here".to_string()),
+                };
+                0b10 << 23 | (*spsr as u32) << 22 | 0b10 << 20 | mask << 16 | 0xf << 12 | op2
+            }
+            Kind::Adr { rd, target } => {
+                let offset = eval(target)? - pc;
+                let (opcode, value) = if offset < 0 {
+                    (0b0010, offset.unsigned_abs() as u32)
+                } else {
+                    (0b0100, offset as u32)
+                };
+                let imm = encode_immediate(value)
+                    .ok_or(format!("invalid constant ({:x}) after fixup", offset))?;
+                1 << 25 | opcode << 21 | 15 << 16 | rd << 12 | imm
+            }
+            Kind::Nop => NOP,
+        };
+
+        Ok(cond | word)
+    }
+}
+
+/// Accepts both signed and unsigned 32 bit values
+fn value32(value: i64) -> Result<u32, String> {
+    if (-(1 << 31)..(1 << 32)).contains(&value) {
+        Ok(value as u32)
+    } else {
+        Err(format!("value {:#x} does not fit in 32 bits", value))
+    }
+}
+
+/// An 8 bit value rotated right by an even amount, in the encoding's `rotate_imm`
+/// and `immed_8` fields. The smallest rotation is used, as `as` does.
+pub fn encode_immediate(value: u32) -> Option<u32> {
+    (0..32)
+        .step_by(2)
+        .map(|rotation| (rotation, value.rotate_left(rotation)))
+        .find(|(_, rotated)| *rotated <= 0xff)
+        .map(|(rotation, rotated)| rotation << 7 | rotated)
+}
+
+/// Encodes the immediate of a data processing instruction, switching to the
+/// complementary opcode when only the negated or inverted value can be encoded,
+/// e.g. `mov r0, #-1` is `mvn r0, #0`
+fn data_immediate(opcode: u32, value: u32) -> Result<(u32, u32), String> {
+    if let Some(imm) = encode_immediate(value) {
+        return Ok((opcode, imm));
+    }
+
+    let alternative = match opcode {
+        // and and bic
+        0b0000 => Some((0b1110, !value)),
+        0b1110 => Some((0b0000, !value)),
+        // sub and add
+        0b0010 => Some((0b0100, value.wrapping_neg())),
+        0b0100 => Some((0b0010, value.wrapping_neg())),
+        // adc and sbc
+        0b0101 => Some((0b0110, !value)),
+        0b0110 => Some((0b0101, !value)),
+        // cmp and cmn
+        0b1010 => Some((0b1011, value.wrapping_neg())),
+        0b1011 => Some((0b1010, value.wrapping_neg())),
+        // mov and mvn
+        MOV => Some((MVN, !value)),
+        MVN => Some((MOV, !value)),
+        _ => None,
+    };
+
+    alternative
+        .and_then(|(opcode, value)| Some((opcode, encode_immediate(value)?)))
+        .ok_or(format!("invalid constant ({:x}) after fixup", value))
+}
+
+/// `rm` with an optional shift, in the low 12 bits of the encoding
+fn shifted_register(rm: u32, shift: &Option<Shift>, context: &impl Context) -> Result<u32, String> {
+    Ok(match shift {
+        None => rm,
+        Some(Shift::Rrx) => 0b11 << 5 | rm,
+        Some(Shift::Reg(kind, rs)) => rs << 8 | kind << 5 | 1 << 4 | rm,
+        Some(Shift::Imm(kind, expr)) => {
+            let amount = expr.eval(context)?;
+            let max = match kind {
+                // lsr and asr shift by up to 32, encoded as 0
+                1 | 2 => 32,
+                _ => 31,
+            };
+            if !(0..=max).contains(&amount) {
+                return Err(format!("shift out of range ({})", amount));
+            }
+            match amount {
+                // a shift by 0 is always encoded as `lsl #0`
+                0 => rm,
+                _ => (amount as u32 & 0x1f) << 7 | kind << 5 | rm,
+            }
+        }
+    })
+}
@@ -0,0 +1,232 @@
+/// An assembler expression, `#4 * (end - start)` or `label + 8`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Number(i64),
+    Symbol(String),
+    /// `.`, the address of the current statement
+    Dot,
+    Unary(char, Box<Expr>),
+    Binary(Box<Expr>, &'static str, Box<Expr>),
+}
+
+/// Resolves symbols while evaluating an [`Expr`]
+pub trait Context {
+    fn symbol(&self, name: &str) -> Result<i64, String>;
+    fn dot(&self) -> Result<i64, String>;
+}
+
+impl Expr {
+    pub fn eval(&self, context: &impl Context) -> Result<i64, String> {
+        Ok(match self {
+            Expr::Number(n) => *n,
+            Expr::Symbol(name) => context.symbol(name)?,
+            Expr::Dot => context.dot()?,
+            Expr::Unary(op, expr) => {
+                let value = expr.eval(context)?;
+                match op {
+                    '-' => value.wrapping_neg(),
+                    '~' => !value,
+                    '!' => (value == 0) as i64,
+                    _ => value,
+                }
+            }
+            Expr::Binary(lhs, op, rhs) => {
+                let lhs = lhs.eval(context)?;
+                let rhs = rhs.eval(context)?;
+                match *op {
+                    "*" => lhs.wrapping_mul(rhs),
+                    "/" | "%" if rhs == 0 => return Err("division by zero".to_string()),
+                    "/" => lhs.wrapping_div(rhs),
+                    "%" => lhs.wrapping_rem(rhs),
+                    "+" => lhs.wrapping_add(rhs),
+                    "-" => lhs.wrapping_sub(rhs),
+                    "<<" => lhs.wrapping_shl(rhs as u32),
+                    ">>" => lhs.wrapping_shr(rhs as u32),
+                    "&" => lhs & rhs,
+                    "^" => lhs ^ rhs,
+                    "|" => lhs | rhs,
+                    _ => unreachable!("Unrecognised operator {}", op),
+                }
+            }
+        })
+    }
+
+    /// Parses a whole operand, trailing characters are an error
+    pub fn parse(text: &str) -> Result<Expr, String> {
+        let mut parser = Parser {
+            chars: text.chars().collect(),
+            pos: 0,
+        };
+        let expr = parser.binary(0)?;
+        parser.skip_whitespace();
+        match parser.peek() {
+            None => Ok(expr),
+            Some(_) => Err(format!(
+                "junk `{}` after expression",
+                &text[parser.byte_pos(text)..]
+            )),
+        }
+    }
+}
+
+/// Binary operators from lowest to highest precedence
+const PRECEDENCE: [&[&str]; 6] = [
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn byte_pos(&self, text: &str) -> usize {
+        text.char_indices()
+            .nth(self.pos)
+            .map_or(text.len(), |(i, _)| i)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        self.skip_whitespace();
+        let matches = op
+            .chars()
+            .enumerate()
+            .all(|(i, c)| self.chars.get(self.pos + i) == Some(&c));
+        if matches {
+            self.pos += op.len();
+        }
+        matches
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+
+        let mut lhs = self.binary(level + 1)?;
+        'outer: loop {
+            for op in PRECEDENCE[level] {
+                if self.eat(op) {
+                    let rhs = self.binary(level + 1)?;
+                    lhs = Expr::Binary(Box::new(lhs), op, Box::new(rhs));
+                    continue 'outer;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        for op in ['-', '+', '~', '!'] {
+            if self.eat(&op.to_string()) {
+                return Ok(Expr::Unary(op, Box::new(self.unary()?)));
+            }
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        self.skip_whitespace();
+        let Some(c) = self.peek() else {
+            return Err("missing expression".to_string());
+        };
+
+        if self.eat("(") {
+            let expr = self.binary(0)?;
+            if !self.eat(")") {
+                return Err("missing `)`".to_string());
+            }
+            return Ok(expr);
+        }
+
+        if c == '\'' {
+            self.pos += 1;
+            let (value, len) = char_literal(&self.chars[self.pos..])?;
+            self.pos += len;
+            // the closing quote is optional, as in GAS
+            if self.peek() == Some('\'') {
+                self.pos += 1;
+            }
+            return Ok(Expr::Number(value as i64));
+        }
+
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_alphanumeric() || matches!(c, '_' | '.' | '$'))
+        {
+            self.pos += 1;
+        }
+        let token: String = self.chars[start..self.pos].iter().collect();
+
+        if token.is_empty() {
+            return Err(format!("unexpected `{}` in expression", c));
+        }
+        if token == "." {
+            return Ok(Expr::Dot);
+        }
+        if c.is_ascii_digit() {
+            return parse_number(&token)
+                .map(Expr::Number)
+                .ok_or(format!("bad number `{}`", token));
+        }
+        Ok(Expr::Symbol(token))
+    }
+}
+
+/// `0x` hexadecimal, `0b` binary, a leading `0` for octal, otherwise decimal
+pub fn parse_number(token: &str) -> Option<i64> {
+    let token = token.to_ascii_lowercase();
+    let (digits, radix) = if let Some(hex) = token.strip_prefix("0x") {
+        (hex, 16)
+    } else if let Some(bin) = token.strip_prefix("0b") {
+        (bin, 2)
+    } else if token.len() > 1 && token.starts_with('0') {
+        (&token[1..], 8)
+    } else {
+        (token.as_str(), 10)
+    };
+    u64::from_str_radix(digits, radix).ok().map(|n| n as i64)
+}
+
+/// Value of the character at the start of `chars` and the number of chars it
+/// used, handling backslash escapes
+pub fn char_literal(chars: &[char]) -> Result<(u8, usize), String> {
+    match chars {
+        ['\\', 'n', ..] => Ok((b'\n', 2)),
+        ['\\', 't', ..] => Ok((b'\t', 2)),
+        ['\\', 'r', ..] => Ok((b'\r', 2)),
+        ['\\', 'b', ..] => Ok((8, 2)),
+        ['\\', 'f', ..] => Ok((12, 2)),
+        ['\\', 'x', rest @ ..] => {
+            let digits: String = rest.iter().take_while(|c| c.is_ascii_hexdigit()).collect();
+            let value =
+                u32::from_str_radix(&digits, 16).map_err(|_| "bad `\\x` escape".to_string())?;
+            Ok((value as u8, 2 + digits.len()))
+        }
+        ['\\', rest @ ..] if rest.first().is_some_and(|c| c.is_digit(8)) => {
+            let digits: String = rest.iter().take(3).take_while(|c| c.is_digit(8)).collect();
+            let value = u32::from_str_radix(&digits, 8).unwrap();
+            Ok((value as u8, 1 + digits.len()))
+        }
+        ['\\', c, ..] => Ok((*c as u8, 2)),
+        [c, ..] if c.is_ascii() => Ok((*c as u8, 1)),
+        [c, ..] => Err(format!("non ASCII character `{}`", c)),
+        [] => Err("missing character".to_string()),
+    }
+}
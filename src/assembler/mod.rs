@@ -0,0 +1,729 @@
+//! An ARMv4 assembler for the subset of GAS syntax used by the course, so that
+//! programs can be run without GNU binutils installed. It produces the same
+//! sections, line table and labels as assembling with `as` and loading the
+//! object file with [`Memory::from_elf`].
+
+mod encode;
+mod expr;
+
+use crate::{
+    DebugInfo, LineTable, Memory, Symbol, SymbolTable, TEXT_BASE,
+    memory::{SECTION_ALIGN, align_up},
+};
+use encode::{Address, Instruction, Kind, split_operands};
+use expr::{Context, Expr, char_literal};
+use std::collections::HashMap;
+
+/// Directives that need the GNU assembler
+const UNSUPPORTED: [&str; 14] = [
+    "macro", "endm", "rept", "endr", "irp", "irpc", "if", "ifdef", "ifndef", "else", "endif",
+    "include", "incbin", "thumb",
+];
+
+/// Directives that do not change the output
+const IGNORED: [&str; 17] = [
+    "global",
+    "globl",
+    "extern",
+    "weak",
+    "local",
+    "hidden",
+    "type",
+    "size",
+    "file",
+    "ident",
+    "arm",
+    "syntax",
+    "cpu",
+    "arch",
+    "fpu",
+    "eabi_attribute",
+    "func",
+];
+
+/// Assembles `source`, errors are formatted as `as` formats them, with `file_name`
+/// as the name of the source file
+pub fn assemble(source: &str, file_name: &str) -> Result<(Memory, DebugInfo), String> {
+    let mut assembler = Assembler::new();
+    for (line, text) in statements(source) {
+        match assembler.statement(line, &text) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => assembler.errors.push((line, e)),
+        }
+    }
+    for section in 0..assembler.sections.len() {
+        assembler.dump_literals(section);
+    }
+
+    let result = match assembler.errors.is_empty() {
+        true => assembler.encode(),
+        false => Err(assembler.errors),
+    };
+
+    result.map_err(|errors| {
+        let mut message = format!("{}: Assembler messages:\n", file_name);
+        for (line, e) in errors {
+            message += &format!("{}:{}: Error: {}\n", file_name, line, e);
+        }
+        message
+    })
+}
+
+/// Splits `source` into `(line, statement)` pairs, removing `@`, `//`, `#` and
+/// `/* */` comments. Statements are separated by newlines and `;`, strings
+/// can span several lines.
+fn statements(source: &str) -> Vec<(usize, String)> {
+    let mut statements = Vec::new();
+    let mut statement = String::new();
+    let mut line = 1;
+    let mut start_line = 1;
+    let mut chars = source.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if statement.trim().is_empty() {
+            start_line = line;
+        }
+        match c {
+            '\n' => {
+                statements.push((start_line, std::mem::take(&mut statement)));
+                line += 1;
+            }
+            ';' => statements.push((start_line, std::mem::take(&mut statement))),
+            // `#` only starts a comment at the beginning of a line
+            '#' if statement.trim().is_empty() => while chars.next_if(|c| *c != '\n').is_some() {},
+            '@' => while chars.next_if(|c| *c != '\n').is_some() {},
+            '/' if chars.next_if_eq(&'/').is_some() => {
+                while chars.next_if(|c| *c != '\n').is_some() {}
+            }
+            '/' if chars.next_if_eq(&'*').is_some() => {
+                statement.push(' ');
+                while let Some(c) = chars.next() {
+                    match c {
+                        '*' if chars.next_if_eq(&'/').is_some() => break,
+                        '\n' => line += 1,
+                        _ => {}
+                    }
+                }
+            }
+            '"' => {
+                statement.push(c);
+                let mut escaped = false;
+                for c in chars.by_ref() {
+                    statement.push(c);
+                    match c {
+                        '\n' => line += 1,
+                        _ if escaped => escaped = false,
+                        '\\' => escaped = true,
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            // `'a'` or `'\n'`, the closing quote is optional
+            '\'' => {
+                statement.push(c);
+                if let Some(c) = chars.next() {
+                    statement.push(c);
+                    if c == '\\'
+                        && let Some(c) = chars.next()
+                    {
+                        statement.push(c);
+                    }
+                }
+                if let Some(c) = chars.next_if_eq(&'\'') {
+                    statement.push(c);
+                }
+            }
+            _ => statement.push(c),
+        }
+    }
+    statements.push((start_line, statement));
+
+    statements
+        .into_iter()
+        .filter(|(_, statement)| !statement.trim().is_empty())
+        .collect()
+}
+
+/// What a statement places in its section
+enum Content {
+    Instruction(Instruction),
+    /// `.word`, `.hword` and `.byte`, each value is `size` bytes
+    Values {
+        size: u32,
+        values: Vec<Expr>,
+    },
+    Bytes(Vec<u8>),
+}
+
+struct Statement {
+    line: usize,
+    section: usize,
+    offset: u32,
+    content: Content,
+}
+
+struct Section {
+    name: String,
+    size: u32,
+    align: u32,
+    /// `ldr rX, =expr` constants waiting for the next `.ltorg` or the end of
+    /// the section, with the label their entry is given
+    literals: Vec<(Expr, String)>,
+}
+
+/// A symbol defined with `=`, `.equ` or `.set`
+struct Equate {
+    expr: Expr,
+    /// Where it was defined, the value of `.` in the expression
+    section: usize,
+    offset: u32,
+}
+
+struct Assembler {
+    sections: Vec<Section>,
+    current: usize,
+    statements: Vec<Statement>,
+    labels: HashMap<String, (usize, u32)>,
+    /// Labels in the order they were defined
+    label_names: Vec<String>,
+    equates: HashMap<String, Equate>,
+    /// Number of times each numeric local label, `1:`, has been defined
+    local_labels: HashMap<String, u32>,
+    literal_count: u32,
+    errors: Vec<(usize, String)>,
+}
+
+/// Resolves symbols, before layout only equates of constants can be evaluated
+struct Symbols<'a> {
+    assembler: &'a Assembler,
+    bases: Option<&'a [u32]>,
+    dot: Option<u32>,
+    depth: u32,
+}
+
+impl Context for Symbols<'_> {
+    fn symbol(&self, name: &str) -> Result<i64, String> {
+        if let Some(equate) = self.assembler.equates.get(name) {
+            if self.depth > 32 {
+                return Err(format!("symbol `{}` is defined in terms of itself", name));
+            }
+            let dot = self
+                .bases
+                .map(|bases| bases[equate.section] + equate.offset);
+            return equate.expr.eval(&Symbols {
+                dot,
+                depth: self.depth + 1,
+                ..*self
+            });
+        }
+
+        match (self.assembler.labels.get(name), self.bases) {
+            (Some((section, offset)), Some(bases)) => Ok((bases[*section] + offset) as i64),
+            (Some(_), None) => Err(format!("`{}` is not a constant", name)),
+            (None, _) => Err(format!("undefined symbol `{}`", name)),
+        }
+    }
+
+    fn dot(&self) -> Result<i64, String> {
+        self.dot
+            .map(i64::from)
+            .ok_or("`.` is not a constant".to_string())
+    }
+}
+
+impl Assembler {
+    fn new() -> Assembler {
+        let mut assembler = Assembler {
+            sections: Vec::new(),
+            current: 0,
+            statements: Vec::new(),
+            labels: HashMap::new(),
+            label_names: Vec::new(),
+            equates: HashMap::new(),
+            local_labels: HashMap::new(),
+            literal_count: 0,
+            errors: Vec::new(),
+        };
+        // always present, as in the object files `as` produces
+        for name in [".text", ".data", ".bss"] {
+            assembler.section(name);
+        }
+        assembler.current = 0;
+        assembler
+    }
+
+    /// Switches to the section called `name`, creating it if needed
+    fn section(&mut self, name: &str) {
+        self.current = match self.sections.iter().position(|s| s.name == name) {
+            Some(i) => i,
+            None => {
+                self.sections.push(Section {
+                    name: name.to_string(),
+                    size: 0,
+                    align: 4,
+                    literals: Vec::new(),
+                });
+                self.sections.len() - 1
+            }
+        };
+    }
+
+    fn constant(&self, expr: &Expr) -> Result<i64, String> {
+        expr.eval(&Symbols {
+            assembler: self,
+            bases: None,
+            dot: None,
+            depth: 0,
+        })
+    }
+
+    fn emit(&mut self, line: usize, content: Content) {
+        let size = match &content {
+            Content::Instruction(_) => 4,
+            Content::Values { size, values } => size * values.len() as u32,
+            Content::Bytes(bytes) => bytes.len() as u32,
+        };
+        let section = &mut self.sections[self.current];
+        self.statements.push(Statement {
+            line,
+            section: self.current,
+            offset: section.size,
+            content,
+        });
+        section.size += size;
+    }
+
+    /// Pads the current section to a multiple of `align`. Code is padded with `nop`s.
+    fn align(&mut self, line: usize, align: u32, fill: Option<u8>) {
+        let section = &mut self.sections[self.current];
+        section.align = section.align.max(align);
+        let len = (align_up(section.size, align) - section.size) as usize;
+        if len == 0 {
+            return;
+        }
+
+        let bytes = match fill {
+            Some(fill) => vec![fill; len],
+            None if section.name == ".text" => {
+                let unaligned = len % 4;
+                let mut bytes = vec![0; unaligned];
+                for _ in 0..len / 4 {
+                    bytes.extend(0xe1a0_0000_u32.to_le_bytes());
+                }
+                bytes
+            }
+            None => vec![0; len],
+        };
+        self.emit(line, Content::Bytes(bytes));
+    }
+
+    fn define_label(&mut self, name: &str) -> Result<(), String> {
+        if self.labels.contains_key(name) || self.equates.contains_key(name) {
+            return Err(format!("symbol `{}` is already defined", name));
+        }
+        let section = self.current;
+        self.labels
+            .insert(name.to_string(), (section, self.sections[section].size));
+        self.label_names.push(name.to_string());
+        Ok(())
+    }
+
+    fn define_equate(&mut self, name: &str, expr: &str, redefine: bool) -> Result<(), String> {
+        if !is_symbol(name) {
+            return Err(format!("bad symbol name `{}`", name));
+        }
+        if self.labels.contains_key(name) || (!redefine && self.equates.contains_key(name)) {
+            return Err(format!("symbol `{}` is already defined", name));
+        }
+        let equate = Equate {
+            expr: Expr::parse(expr)?,
+            section: self.current,
+            offset: self.sections[self.current].size,
+        };
+        self.equates.insert(name.to_string(), equate);
+        Ok(())
+    }
+
+    /// Places the pending literals of `section` at its end
+    fn dump_literals(&mut self, section: usize) {
+        if self.sections[section].literals.is_empty() {
+            return;
+        }
+
+        let current = self.current;
+        self.current = section;
+        let line = self.statements.last().map_or(0, |statement| statement.line);
+        self.align(line, 4, Some(0));
+        for (expr, label) in std::mem::take(&mut self.sections[section].literals) {
+            self.define_label(&label).unwrap();
+            self.emit(
+                line,
+                Content::Values {
+                    size: 4,
+                    values: vec![expr],
+                },
+            );
+        }
+        self.current = current;
+    }
+
+    /// Label of the literal pool entry for `expr`, entries are shared by equal
+    /// expressions
+    fn literal(&mut self, expr: Expr) -> String {
+        let literals = &mut self.sections[self.current].literals;
+        if let Some((_, label)) = literals.iter().find(|(e, _)| *e == expr) {
+            return label.clone();
+        }
+        let label = format!(".Lliteral{}", self.literal_count);
+        self.literal_count += 1;
+        literals.push((expr, label.clone()));
+        label
+    }
+
+    /// Renames numeric local labels, `1:`, `1b` and `1f`, to unique names
+    fn local_label(&mut self, name: &str, reference: Option<char>) -> String {
+        let count = self.local_labels.get(name).copied().unwrap_or(0);
+        let instance = match reference {
+            Some('b') => count.wrapping_sub(1),
+            Some(_) => count,
+            None => {
+                self.local_labels.insert(name.to_string(), count + 1);
+                count
+            }
+        };
+        format!(".L{}.{}", name, instance)
+    }
+
+    fn rename_local_references(&mut self, text: &str) -> String {
+        let mut out = String::new();
+        let mut word = String::new();
+        let mut quote = false;
+        let flush = |out: &mut String, word: &mut String, assembler: &mut Assembler| {
+            let digits = word.trim_end_matches(['b', 'f']);
+            if word.len() == digits.len() + 1
+                && !digits.is_empty()
+                && digits.chars().all(|c| c.is_ascii_digit())
+            {
+                let reference = word.chars().last();
+                *out += &assembler.local_label(digits, reference);
+            } else {
+                *out += word;
+            }
+            word.clear();
+        };
+
+        let mut prev = '\0';
+        for c in text.chars() {
+            if quote {
+                out.push(c);
+                quote = !(c == '"' && prev != '\\');
+            } else if c.is_alphanumeric() || matches!(c, '_' | '.' | '$') {
+                word.push(c);
+            } else {
+                flush(&mut out, &mut word, self);
+                quote = c == '"';
+                out.push(c);
+            }
+            prev = c;
+        }
+        flush(&mut out, &mut word, self);
+        out
+    }
+
+    /// Handles one statement, returns `false` after `.end`
+    fn statement(&mut self, line: usize, text: &str) -> Result<bool, String> {
+        let mut text = text.trim();
+
+        // labels, including numeric local labels
+        while let Some((name, rest)) = text.split_once(':')
+            && (is_symbol(name) || (!name.is_empty() && name.chars().all(|c| c.is_ascii_digit())))
+        {
+            let name = match is_symbol(name) {
+                true => name.to_string(),
+                false => self.local_label(name, None),
+            };
+            self.define_label(&name)?;
+            text = rest.trim();
+        }
+        if text.is_empty() {
+            return Ok(true);
+        }
+
+        let text = self.rename_local_references(text);
+        let (head, rest) = text.split_once(char::is_whitespace).unwrap_or((&text, ""));
+        let rest = rest.trim();
+
+        // `name = expr`
+        if let Some((name, expr)) = text.split_once('=')
+            && is_symbol(name.trim())
+            && !expr.starts_with('=')
+        {
+            self.define_equate(name.trim(), expr, true)?;
+            return Ok(true);
+        }
+
+        match head.strip_prefix('.') {
+            Some(directive) => self.directive(line, &directive.to_ascii_lowercase(), rest),
+            None => {
+                self.instruction(line, &head.to_ascii_lowercase(), rest)?;
+                Ok(true)
+            }
+        }
+    }
+
+    fn directive(&mut self, line: usize, directive: &str, rest: &str) -> Result<bool, String> {
+        let operands = split_operands(rest);
+        let exprs = || -> Result<Vec<Expr>, String> {
+            operands
+                .iter()
+                .map(|operand| Expr::parse(operand))
+                .collect()
+        };
+        let constant = |i: usize| -> Result<Option<i64>, String> {
+            operands
+                .get(i)
+                .map(|operand| self.constant(&Expr::parse(operand)?))
+                .transpose()
+        };
+
+        match directive {
+            "text" | "data" | "bss" => self.section(&format!(".{}", directive)),
+            "section" => {
+                let name = operands.first().ok_or("missing section name")?;
+                self.section(name.trim_matches('"'));
+            }
+            "word" | "long" | "int" | "4byte" => self.emit_values(line, 4, exprs()?),
+            "hword" | "short" | "2byte" => self.emit_values(line, 2, exprs()?),
+            "byte" => self.emit_values(line, 1, exprs()?),
+            "ascii" | "asciz" | "string" => {
+                for operand in &operands {
+                    let mut bytes = string_literal(operand)?;
+                    if directive != "ascii" {
+                        bytes.push(0);
+                    }
+                    self.emit(line, Content::Bytes(bytes));
+                }
+            }
+            "space" | "skip" | "zero" => {
+                let size = constant(0)?.ok_or("missing size")?;
+                let fill = constant(1)?.unwrap_or(0);
+                if !(0..=0x0100_0000).contains(&size) {
+                    return Err(format!("bad size ({}) in .{}", size, directive));
+                }
+                self.emit(line, Content::Bytes(vec![fill as u8; size as usize]));
+            }
+            "fill" => {
+                let repeat = constant(0)?.ok_or("missing repeat count")?;
+                let size = constant(1)?.unwrap_or(1).clamp(0, 8) as usize;
+                let value = constant(2)?.unwrap_or(0);
+                if !(0..=0x0100_0000).contains(&repeat) {
+                    return Err(format!("bad repeat count ({}) in .fill", repeat));
+                }
+                let bytes = (0..repeat)
+                    .flat_map(|_| value.to_le_bytes().into_iter().take(size.min(4)))
+                    .chain(std::iter::repeat_n(
+                        0,
+                        size.saturating_sub(4) * repeat as usize,
+                    ))
+                    .collect();
+                self.emit(line, Content::Bytes(bytes));
+            }
+            // `.align n` aligns to 2^n on ARM
+            "align" | "p2align" | "balign" => {
+                let value = constant(0)?.unwrap_or(2);
+                let align = match directive {
+                    "balign" => value,
+                    _ if (0..=16).contains(&value) => 1 << value,
+                    _ => return Err(format!("alignment too large: {}", value)),
+                };
+                if align <= 0 || align & (align - 1) != 0 {
+                    return Err("alignment not a power of 2".to_string());
+                }
+                let fill = constant(1)?.map(|fill| fill as u8);
+                self.align(line, align as u32, fill);
+            }
+            "equ" | "set" | "equiv" => {
+                let [name, expr] = operands[..] else {
+                    return Err(format!("expected `.{} name, expression`", directive));
+                };
+                self.define_equate(name, expr, directive != "equiv")?;
+            }
+            "ltorg" | "pool" => self.dump_literals(self.current),
+            "end" => return Ok(false),
+            "code" => {
+                if constant(0)? != Some(32) {
+                    return Err(
+                        "only ARM code, `.code 32`, is supported, use the GNU assembler for Thumb"
+                            .to_string(),
+                    );
+                }
+            }
+            _ if IGNORED.contains(&directive) || directive.starts_with("cfi_") => {}
+            _ if UNSUPPORTED.contains(&directive) => {
+                return Err(format!(
+                    "`.{}` is not supported by the built-in assembler, use the GNU assembler",
+                    directive
+                ));
+            }
+            _ => return Err(format!("unknown pseudo-op: `.{}`", directive)),
+        }
+
+        Ok(true)
+    }
+
+    fn emit_values(&mut self, line: usize, size: u32, values: Vec<Expr>) {
+        self.emit(line, Content::Values { size, values });
+    }
+
+    fn instruction(&mut self, line: usize, mnemonic: &str, rest: &str) -> Result<(), String> {
+        let operands = split_operands(rest);
+        let mut instruction = Instruction::parse(mnemonic, &operands)?;
+
+        // `ldr rX, =expr` is a `mov` or `mvn` when possible, otherwise a load
+        // from the literal pool
+        if let Kind::Transfer {
+            rd,
+            address: address @ Address::Literal(_),
+            ..
+        } = &mut instruction.kind
+        {
+            let Address::Literal(expr) = address else {
+                unreachable!()
+            };
+            let constant = self
+                .constant(expr)
+                .ok()
+                .and_then(|value| Instruction::load_constant(instruction.cond, *rd, value as u32));
+            match constant {
+                Some(constant) => instruction = constant,
+                None => {
+                    let label = self.literal(expr.clone());
+                    *address = Address::Label(Expr::Symbol(label));
+                }
+            }
+        }
+
+        self.align(line, 4, Some(0));
+        self.emit(line, Content::Instruction(instruction));
+        Ok(())
+    }
+
+    /// Lays out the sections like [`Memory::from_elf`] and encodes every statement
+    fn encode(self) -> Result<(Memory, DebugInfo), Vec<(usize, String)>> {
+        let mut bases = Vec::new();
+        let mut next_base = TEXT_BASE;
+        for section in &self.sections {
+            let base = align_up(next_base, section.align);
+            next_base = align_up(base + section.size, SECTION_ALIGN);
+            bases.push(base);
+        }
+
+        let mut contents: Vec<Vec<u8>> = self
+            .sections
+            .iter()
+            .map(|section| vec![0; section.size as usize])
+            .collect();
+        let mut lines: Vec<(std::ops::Range<u32>, u32)> = Vec::new();
+        let mut errors = Vec::new();
+
+        for statement in &self.statements {
+            let address = bases[statement.section] + statement.offset;
+            let symbols = Symbols {
+                assembler: &self,
+                bases: Some(&bases),
+                dot: Some(address),
+                depth: 0,
+            };
+            let bytes: Result<Vec<u8>, String> = match &statement.content {
+                Content::Instruction(instruction) => {
+                    match lines.last_mut() {
+                        Some((range, line))
+                            if *line == statement.line as u32 && range.end == address =>
+                        {
+                            range.end += 4;
+                        }
+                        _ => lines.push((address..address + 4, statement.line as u32)),
+                    }
+                    instruction
+                        .encode(address, &symbols)
+                        .map(|word| word.to_le_bytes().to_vec())
+                }
+                Content::Values { size, values } => values
+                    .iter()
+                    .map(|expr| {
+                        let value = expr.eval(&symbols)?;
+                        Ok(value.to_le_bytes()[..*size as usize].to_vec())
+                    })
+                    .collect::<Result<Vec<_>, String>>()
+                    .map(|values| values.concat()),
+                Content::Bytes(bytes) => Ok(bytes.clone()),
+            };
+
+            match bytes {
+                Ok(bytes) => {
+                    let start = statement.offset as usize;
+                    contents[statement.section][start..start + bytes.len()].copy_from_slice(&bytes);
+                }
+                Err(e) => errors.push((statement.line, e)),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let mut memory = Memory::new();
+        for ((section, base), bytes) in self.sections.iter().zip(&bases).zip(contents) {
+            memory.map(&section.name, *base, bytes);
+        }
+        memory.map_stack();
+
+        // `.L` labels are local to the assembler and left out of the symbol table
+        let symbols = self
+            .label_names
+            .iter()
+            .filter(|name| !name.starts_with(".L"))
+            .map(|name| {
+                let (section, offset) = self.labels[name];
+                Symbol {
+                    name: name.clone(),
+                    address: bases[section] + offset,
+                }
+            })
+            .collect();
+
+        let debug_info = DebugInfo {
+            lines: LineTable::from_ranges(lines),
+            symbols: SymbolTable::new(symbols),
+        };
+        Ok((memory, debug_info))
+    }
+}
+
+fn is_symbol(name: &str) -> bool {
+    name.chars()
+        .next()
+        .is_some_and(|c| c.is_alphabetic() || matches!(c, '_' | '.' | '$'))
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | '.' | '$'))
+}
+
+/// The bytes of a `"quoted string"`, with escapes replaced
+fn string_literal(operand: &str) -> Result<Vec<u8>, String> {
+    let inner = operand
+        .strip_prefix('"')
+        .and_then(|operand| operand.strip_suffix('"'))
+        .ok_or(format!("expected string -- `{}`", operand))?;
+
+    let chars: Vec<char> = inner.chars().collect();
+    let mut bytes = Vec::new();
+    let mut pos = 0;
+    while pos < chars.len() {
+        let (byte, len) = char_literal(&chars[pos..])?;
+        bytes.push(byte);
+        pos += len;
+    }
+    Ok(bytes)
+}
@@ -1,9 +1,17 @@
 use capstone::Instructions;
-use komodo::Registers;
+use komodo::{
+    Assembler, Board, DebugInfo, Machine, Memory, StopReason, TRACE_LIMIT, Trace, TraceFormat,
+    Watch,
+};
 use std::{
+    cell::Cell,
+    collections::HashSet,
     env,
     ffi::OsString,
-    io::{self, BufRead, Write},
+    fs::File,
+    io::{self, BufRead, BufWriter, Read, Write},
+    path::PathBuf,
+    process,
 };
 use tempfile::{self, NamedTempFile};
 
@@ -14,56 +22,222 @@ fn main() {
         print!("{}", str);
         io::stdout().flush().unwrap();
     };
-    let input_path = read_input_path(&mut input_file);
-    let (data_section, text_section, instrs) = komodo::disassemble(&cs, input_path, print);
-
-    print_disasm(&instrs);
-
-    let mut regs = Registers::new();
-    komodo::run_program(
-        &cs,
-        data_section,
-        text_section,
-        &mut regs,
-        instrs,
-        &mut print,
-    );
-
-    eprintln!("{:?}", regs);
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}");
+            eprintln!(
+                "Usage: cli [--assembler builtin|gnu] [--as PATH] [--bare-metal] [--trace PATH] [--watch WATCH]... [FILE]"
+            );
+            process::exit(2);
+        }
+    };
+    let assembler = match Assembler::from_name(&args.assembler, args.assembler_path.as_deref()) {
+        Ok(assembler) => assembler,
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(1);
+        }
+    };
+    if let Assembler::Gnu(toolchain) = &assembler {
+        eprintln!("Assembling with {toolchain}");
+    }
+
+    let input_path = read_input_path(args.input_path, &mut input_file);
+    let (memory, instrs, debug_info) = match komodo::disassemble(&cs, input_path, &assembler) {
+        Ok(program) => program,
+        Err(e) => {
+            eprint!("{e}");
+            process::exit(1);
+        }
+    };
+
+    let thumb_cs = komodo::new_thumb_capstone();
+    let instrs = komodo::disassemble_mapped(&cs, &thumb_cs, &memory, &debug_info.symbols)
+        .unwrap_or_else(|_| vec![instrs]);
+    print_disasm(&instrs, &memory, &debug_info);
+
+    // reading past the end of stdin stops the run after the `swi`
+    let end_of_input = Cell::new(false);
+    let read_char = || {
+        let mut buf = [0];
+        match io::stdin().read_exact(&mut buf) {
+            Ok(()) => buf[0] as char,
+            Err(_) => {
+                end_of_input.set(true);
+                '\0'
+            }
+        }
+    };
+
+    let mut machine = match Machine::new(&cs, memory) {
+        Ok(machine) => machine,
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(1);
+        }
+    };
+    // the UART of the lab board prints to stdout like `swi 0`
+    for (base, device) in Board::new().devices() {
+        machine.map_peripheral(base, device);
+    }
+    if args.bare_metal {
+        machine.bare_metal = true;
+        machine.reset();
+    }
+    if args.trace_path.is_some() {
+        machine.trace = Some(Trace::with_limit(TRACE_LIMIT));
+    }
+    machine.watches = args.watches;
+    let result = loop {
+        match machine.run_until(&HashSet::new(), &read_char, &mut print, || {
+            end_of_input.get()
+        }) {
+            Ok(StopReason::Watchpoint { index, address }) => {
+                let line = debug_info
+                    .lines
+                    .line(address)
+                    .map_or(String::new(), |line| format!(" on line {line}"));
+                eprintln!(
+                    "Watchpoint {} at {:#010x}{}",
+                    machine.watches[index], address, line
+                );
+            }
+            result => break result,
+        }
+    };
+
+    eprintln!("{:?}", machine.regs);
+    eprintln!("{}", machine.cycles);
+
+    if let (Some(path), Some(trace)) = (&args.trace_path, &machine.trace) {
+        let written = File::create(path).and_then(|file| {
+            let mut out = BufWriter::new(file);
+            trace.write(TraceFormat::from_path(path), &mut out)?;
+            out.flush()
+        });
+        if let Err(e) = written {
+            eprintln!("Cannot write trace to {}: {e}", path.display());
+        }
+    }
+
+    if end_of_input.get() {
+        eprintln!("Error: the program read past the end of the input");
+        process::exit(1);
+    }
+    if let Err(e) = result {
+        match debug_info.lines.line(e.address) {
+            Some(line) => eprintln!("Error on line {line}: {e}"),
+            None => eprintln!("Error: {e}"),
+        }
+        process::exit(1);
+    }
 }
 
-fn print_disasm<'a>(instrs: &Instructions<'a>) {
-    for i in instrs.iter() {
-        let mut bytes: Vec<u8> = Vec::new();
-        for &b in i.bytes().iter().rev() {
-            bytes.push(b);
+/// `instrs` holds a run of instructions for each ARM or Thumb region of `.text`
+fn print_disasm(instrs: &[Instructions], memory: &Memory, debug_info: &DebugInfo) {
+    for i in instrs.iter().flat_map(|instrs| instrs.iter()) {
+        for name in debug_info.symbols.labels_at(i.address() as u32) {
+            eprintln!("{name}:");
         }
-        let encoding = i32::from_be_bytes(bytes.clone().try_into().unwrap());
+
+        let encoding = i
+            .bytes()
+            .iter()
+            .rev()
+            .fold(0, |word, &b| word << 8 | b as u32);
+
+        let line = debug_info
+            .lines
+            .line(i.address() as u32)
+            .map_or(String::new(), |line| line.to_string());
 
         let str = format!(
-            "{:x}:\t{}\t\t{}\t{}",
+            "{}\t{:x}:\t{}\t\t{}",
+            line,
             i.address(),
             encoding,
-            i.mnemonic().unwrap(),
-            i.op_str().unwrap()
+            komodo::symbolic_disasm(i, memory, &debug_info.symbols)
         );
         eprintln!("{str}");
     }
 }
 
-fn read_input_path(input_file: &mut NamedTempFile) -> OsString {
-    let args: Vec<OsString> = env::args_os().collect();
+/// Command line arguments, `cli [--assembler builtin|gnu] [--as PATH] [--bare-metal] [--trace PATH] [--watch WATCH]... [FILE]`
+struct Args {
+    /// The program is read from stdin when no file is given
+    input_path: Option<OsString>,
+    /// `builtin` or `gnu`
+    assembler: String,
+    /// GNU `as` to use instead of searching `PATH`, implies `--assembler gnu`
+    assembler_path: Option<PathBuf>,
+    /// Exceptions branch to the vectors, see [`Machine::bare_metal`]
+    bare_metal: bool,
+    /// Where to write the trace of the run, formatted by its extension, see
+    /// [`TraceFormat::from_path`]
+    trace_path: Option<PathBuf>,
+    /// Execution is reported each time one is met, `--watch` can be repeated
+    watches: Vec<Watch>,
+}
 
-    let input_path = if args.len() > 1 {
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        input_path: None,
+        assembler: Assembler::default().name().to_string(),
+        assembler_path: None,
+        bare_metal: false,
+        trace_path: None,
+        watches: Vec::new(),
+    };
+
+    let mut args_os = env::args_os().skip(1);
+    while let Some(arg) = args_os.next() {
+        let arg_str = arg.to_string_lossy().to_string();
+        let (flag, value) = match arg_str.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(OsString::from(value))),
+            _ => (arg_str.as_str(), None),
+        };
+
+        match flag {
+            "--assembler" | "--as" | "--trace" | "--watch" => {
+                let value = match value {
+                    Some(value) => value,
+                    None => args_os
+                        .next()
+                        .ok_or(format!("Missing value for `{}`", flag))?,
+                };
+                match flag {
+                    "--as" => {
+                        args.assembler = "gnu".to_string();
+                        args.assembler_path = Some(PathBuf::from(value));
+                    }
+                    "--trace" => args.trace_path = Some(PathBuf::from(value)),
+                    "--watch" => args.watches.push(value.to_string_lossy().parse()?),
+                    _ => args.assembler = value.to_string_lossy().to_string(),
+                }
+            }
+            "--bare-metal" => args.bare_metal = true,
+            _ if args.input_path.is_none() && !flag.starts_with("--") => {
+                args.input_path = Some(arg)
+            }
+            _ => return Err(format!("Unexpected argument `{}`", arg_str)),
+        }
+    }
+
+    Ok(args)
+}
+
+fn read_input_path(input_path: Option<OsString>, input_file: &mut NamedTempFile) -> OsString {
+    match input_path {
         // get file path from cli args
-        args[1].clone()
-    } else {
-        // get temporary file path with content from stdin
-        for line in io::stdin().lock().lines() {
-            input_file.write_all(line.unwrap().as_bytes()).unwrap();
-            input_file.write(b"\n").unwrap();
+        Some(input_path) => input_path,
+        None => {
+            // get temporary file path with content from stdin
+            for line in io::stdin().lock().lines() {
+                input_file.write_all(line.unwrap().as_bytes()).unwrap();
+                input_file.write_all(b"\n").unwrap();
+            }
+            input_file.path().as_os_str().to_os_string()
         }
-        input_file.path().as_os_str().to_os_string()
-    };
-    input_path
+    }
 }
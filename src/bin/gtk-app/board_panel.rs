@@ -0,0 +1,78 @@
+use adw::prelude::*;
+use gtk::{Align, Orientation, glib};
+use komodo::{BUTTON_COUNT, Board, LED_COUNT};
+use std::time::Duration;
+
+/// How often the LEDs are redrawn, the program runs on another thread
+const REFRESH: Duration = Duration::from_millis(50);
+
+/// The LEDs and buttons of the lab board, LED 0 and button 0 are on the right
+pub fn create(board: &Board) -> gtk::Box {
+    let box_ = gtk::Box::builder()
+        .orientation(Orientation::Vertical)
+        .spacing(4)
+        .margin_start(8)
+        .margin_end(8)
+        .margin_bottom(8)
+        .build();
+    box_.add_css_class("darker");
+
+    let title = gtk::Label::builder()
+        .label("Board")
+        .halign(Align::Start)
+        .css_classes(["heading"])
+        .build();
+
+    let leds_box = gtk::Box::builder()
+        .orientation(Orientation::Horizontal)
+        .halign(Align::Center)
+        .spacing(4)
+        .build();
+    let leds = (0..LED_COUNT)
+        .map(|n| {
+            gtk::Label::builder()
+                .label("●")
+                .tooltip_text(format!("LED {n}"))
+                .css_classes(["led"])
+                .build()
+        })
+        .collect::<Vec<gtk::Label>>();
+    for led in leds.iter().rev() {
+        leds_box.append(led);
+    }
+
+    let buttons_box = gtk::Box::builder()
+        .orientation(Orientation::Horizontal)
+        .css_classes(["linked"])
+        .build();
+    for n in (0..BUTTON_COUNT).rev() {
+        let button = gtk::ToggleButton::builder()
+            .label(format!("SW{n}"))
+            .tooltip_text(format!("Button {n}, held while pressed in"))
+            .css_classes(["toggle"])
+            .hexpand(true)
+            .build();
+        let gpio = board.gpio.clone();
+        button.connect_toggled(move |button| {
+            gpio.lock().unwrap().set_button(n, button.is_active());
+        });
+        buttons_box.append(&button);
+    }
+
+    let gpio = board.gpio.clone();
+    glib::timeout_add_local(REFRESH, move || {
+        let lit = gpio.lock().unwrap().leds();
+        for (n, led) in leds.iter().enumerate() {
+            match lit & (1 << n) != 0 {
+                true => led.add_css_class("led-on"),
+                false => led.remove_css_class("led-on"),
+            }
+        }
+        glib::ControlFlow::Continue
+    });
+
+    box_.append(&title);
+    box_.append(&leds_box);
+    box_.append(&buttons_box);
+    box_
+}
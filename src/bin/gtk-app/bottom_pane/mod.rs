@@ -1,4 +1,12 @@
-pub fn create() -> (gtk::ScrolledWindow, gtk::TextView) {
+use gtk::prelude::*;
+use komodo::{Diagnostic, Severity};
+
+/// Stack page with the output of the program
+const OUTPUT: &str = "output";
+/// Stack page listing the diagnostics of the last assembly
+const PROBLEMS: &str = "problems";
+
+pub fn create() -> (gtk::Box, gtk::ScrolledWindow, gtk::TextView, gtk::ListBox) {
     let text_view = gtk::TextView::builder()
         .monospace(true)
         // .can_focus(false)
@@ -15,5 +23,81 @@ pub fn create() -> (gtk::ScrolledWindow, gtk::TextView) {
 
     let scroll = gtk::ScrolledWindow::builder().child(&text_view).build();
 
-    (scroll, text_view)
+    let problems = gtk::ListBox::builder()
+        .selection_mode(gtk::SelectionMode::None)
+        .build();
+    problems.set_placeholder(Some(
+        &gtk::Label::builder()
+            .label("No problems")
+            .css_classes(["dim-label"])
+            .build(),
+    ));
+    // activating a problem moves the cursor to its line in the editor
+    problems.connect_row_activated(|_, row| {
+        row.activate_action(
+            "win.action-goto-problem",
+            Some(&(row.index() as u32).to_variant()),
+        )
+        .unwrap();
+    });
+    let problems_scroll = gtk::ScrolledWindow::builder().child(&problems).build();
+
+    let stack = gtk::Stack::builder().vexpand(true).build();
+    stack.add_titled(&scroll, Some(OUTPUT), "Output");
+    stack.add_titled(&problems_scroll, Some(PROBLEMS), "Problems");
+
+    let switcher = gtk::StackSwitcher::builder()
+        .stack(&stack)
+        .halign(gtk::Align::Start)
+        .css_classes(["toggle"])
+        .build();
+
+    let container = gtk::Box::builder()
+        .orientation(gtk::Orientation::Vertical)
+        .build();
+    container.append(&switcher);
+    container.append(&stack);
+
+    (container, scroll, text_view, problems)
+}
+
+/// Lists `diagnostics` in the problems page, which is shown if there are any
+pub fn set_problems(problems: &gtk::ListBox, diagnostics: &[Diagnostic]) {
+    problems.remove_all();
+    for diagnostic in diagnostics {
+        let icon_name = match diagnostic.severity {
+            Severity::Error => "dialog-error-symbolic",
+            Severity::Warning => "dialog-warning-symbolic",
+        };
+        let row = gtk::Box::builder().spacing(8).build();
+        row.append(&gtk::Image::from_icon_name(icon_name));
+        row.append(
+            &gtk::Label::builder()
+                .label(format!("Line {}: {}", diagnostic.line, diagnostic.message))
+                .halign(gtk::Align::Start)
+                .wrap(true)
+                .build(),
+        );
+        problems.append(&row);
+    }
+
+    let Some(stack) = problems
+        .ancestor(gtk::Stack::static_type())
+        .and_downcast::<gtk::Stack>()
+    else {
+        return;
+    };
+    if let Some(page) = stack
+        .child_by_name(PROBLEMS)
+        .map(|child| stack.page(&child))
+    {
+        page.set_title(&match diagnostics.len() {
+            0 => "Problems".to_string(),
+            n => format!("Problems ({})", n),
+        });
+    }
+    stack.set_visible_child_name(match diagnostics.is_empty() {
+        true => OUTPUT,
+        false => PROBLEMS,
+    });
 }
@@ -10,9 +10,21 @@ pub fn create() -> (gtk::Revealer, gtk::Button) {
 
     let continue_ = gtk::Button::builder()
         .icon_name("skip-forward-large-symbolic")
+        .action_name("win.action-continue")
         .build();
     let step_over = gtk::Button::builder()
         .icon_name("step-over-symbolic")
+        .action_name("win.action-step")
+        .build();
+    let step_back = gtk::Button::builder()
+        .icon_name("edit-undo-symbolic")
+        .tooltip_text("Step back")
+        .action_name("win.action-step-back")
+        .build();
+    let reverse_continue = gtk::Button::builder()
+        .icon_name("media-seek-backward-symbolic")
+        .tooltip_text("Reverse continue to a breakpoint")
+        .action_name("win.action-reverse-continue")
         .build();
     // let step_in = gtk::Button::builder()
     //     .icon_name("arrow-pointing-at-line-down-symbolic")
@@ -22,11 +34,14 @@ pub fn create() -> (gtk::Revealer, gtk::Button) {
     //     .build();
     let restart = gtk::Button::builder()
         .icon_name("arrow-circular-top-left-symbolic")
+        .action_name("win.action-restart")
         .build();
     let stop = gtk::Button::builder().icon_name("stop-symbolic").build();
 
     toolbar.append(&continue_);
     toolbar.append(&step_over);
+    toolbar.append(&step_back);
+    toolbar.append(&reverse_continue);
     // toolbar.append(&step_out);
     // toolbar.append(&step_in);
     toolbar.append(&restart);
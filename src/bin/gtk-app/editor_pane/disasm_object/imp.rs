@@ -16,6 +16,9 @@ pub struct DisasmObject {
     encoding: Cell<u32>,
     #[property(get, set)]
     source: RefCell<String>,
+    /// A label row above the first instruction at `address`
+    #[property(get, set)]
+    is_label: Cell<bool>,
 }
 
 // The central trait for subclassing a GObject
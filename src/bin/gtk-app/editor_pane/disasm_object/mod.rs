@@ -15,4 +15,12 @@ impl DisasmObject {
             .property("source", source)
             .build()
     }
+
+    pub fn new_label(address: u32, name: &str) -> Self {
+        Object::builder()
+            .property("address", address)
+            .property("source", format!("{}:", name))
+            .property("is-label", true)
+            .build()
+    }
 }
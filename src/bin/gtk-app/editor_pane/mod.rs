@@ -1,13 +1,28 @@
 pub mod disasm_object;
-use gtk::gdk::pango;
+use gtk::gdk::{self, pango};
 use gtk::{gio, glib, prelude::*};
+use komodo::{Diagnostic, Severity};
 use sourceview5::prelude::*;
+use std::{cell::RefCell, rc::Rc};
 
 use disasm_object::DisasmObject;
 
+/// Source mark category of lines with a breakpoint
+const BREAKPOINT: &str = "breakpoint";
+/// Source mark category of the line containing `pc` while paused
+const PC: &str = "pc";
+/// Source mark category and text tag of lines with an assembler error
+const ERROR: &str = "error";
+/// Source mark category and text tag of lines with an assembler warning
+const WARNING: &str = "warning";
+
+/// Diagnostics of the last assembly, the `problem-{index}` marks refer to them
+pub type Problems = Rc<RefCell<Vec<Diagnostic>>>;
+
 pub fn create_source(
     window: &adw::ApplicationWindow,
     css_provider: &gtk::CssProvider,
+    problems: &Problems,
 ) -> (gtk::ScrolledWindow, sourceview5::Buffer) {
     let buffer = sourceview5::Buffer::builder()
         .style_scheme(&get_style_scheme())
@@ -23,10 +38,101 @@ pub fn create_source(
     let view = sourceview5::View::builder()
         .monospace(true)
         .show_line_numbers(true)
+        .show_line_marks(true)
         .highlight_current_line(true)
         .buffer(&buffer)
         .build();
 
+    view.set_mark_attributes(
+        BREAKPOINT,
+        &sourceview5::MarkAttributes::builder()
+            .icon_name("media-record-symbolic")
+            .build(),
+        1,
+    );
+    view.set_mark_attributes(
+        PC,
+        &sourceview5::MarkAttributes::builder()
+            .background(&gdk::RGBA::new(0.96, 0.83, 0.18, 0.3))
+            .build(),
+        0,
+    );
+
+    for (category, icon_name) in [
+        (ERROR, "dialog-error-symbolic"),
+        (WARNING, "dialog-warning-symbolic"),
+    ] {
+        let attributes = sourceview5::MarkAttributes::builder()
+            .icon_name(icon_name)
+            .build();
+        attributes.connect_query_tooltip_text(glib::clone!(
+            #[strong]
+            buffer,
+            #[strong]
+            problems,
+            move |_, mark| {
+                let line = buffer.iter_at_mark(mark).line();
+                line_problems(&buffer, &problems, line).unwrap_or_default()
+            }
+        ));
+        view.set_mark_attributes(category, &attributes, 2);
+    }
+
+    let tag_table = buffer.tag_table();
+    tag_table.add(
+        &gtk::TextTag::builder()
+            .name(ERROR)
+            .underline(pango::Underline::Error)
+            .build(),
+    );
+    tag_table.add(
+        &gtk::TextTag::builder()
+            .name(WARNING)
+            .underline(pango::Underline::Error)
+            .underline_rgba(&gdk::RGBA::new(0.9, 0.6, 0.0, 1.0))
+            .build(),
+    );
+
+    // hovering an underlined line shows its problems
+    view.set_has_tooltip(true);
+    view.connect_query_tooltip(glib::clone!(
+        #[strong]
+        buffer,
+        #[strong]
+        problems,
+        move |view, x, y, keyboard, tooltip| {
+            if keyboard {
+                return false;
+            }
+            let (x, y) = view.window_to_buffer_coords(gtk::TextWindowType::Widget, x, y);
+            let Some(iter) = view.iter_at_location(x, y) else {
+                return false;
+            };
+            if !iter
+                .tags()
+                .iter()
+                .any(|tag| matches!(tag.name().as_deref(), Some(ERROR | WARNING)))
+            {
+                return false;
+            }
+
+            match line_problems(&buffer, &problems, iter.line()) {
+                Some(text) => {
+                    tooltip.set_text(Some(&text));
+                    true
+                }
+                None => false,
+            }
+        }
+    ));
+
+    // clicking the gutter next to a line toggles its breakpoint
+    view.connect_line_mark_activated(glib::clone!(
+        #[strong]
+        buffer,
+        move |_, iter, _, _, _| toggle_breakpoint(&buffer, iter.line())
+    ));
+
     let action_zoom_in = gio::ActionEntry::builder("action-zoom-in")
         .activate(glib::clone!(
             #[strong]
@@ -70,6 +176,29 @@ pub fn create_source(
         .build();
     window.add_action_entries([action_zoom_in, action_zoom_out, action_zoom_reset]);
 
+    // jumps to a problem listed in the bottom pane, marks follow edits made since
+    let action_goto_problem = gio::ActionEntry::builder("action-goto-problem")
+        .parameter_type(Some(&u32::static_variant_type()))
+        .activate(glib::clone!(
+            #[strong]
+            view,
+            #[strong]
+            buffer,
+            move |_: &adw::ApplicationWindow, _, parameter| {
+                let Some(index) = parameter.and_then(|parameter| parameter.get::<u32>()) else {
+                    return;
+                };
+                let Some(mark) = buffer.mark(&problem_mark(index as usize)) else {
+                    return;
+                };
+                buffer.place_cursor(&buffer.iter_at_mark(&mark));
+                view.scroll_to_mark(&mark, 0.25, false, 0.0, 0.0);
+                view.grab_focus();
+            }
+        ))
+        .build();
+    window.add_action_entries([action_goto_problem]);
+
     let scroll = gtk::ScrolledWindow::builder()
         .vscrollbar_policy(gtk::PolicyType::External)
         .vexpand(true)
@@ -80,6 +209,105 @@ pub fn create_source(
     (scroll, buffer)
 }
 
+fn toggle_breakpoint(buffer: &sourceview5::Buffer, line: i32) {
+    let Some(start) = buffer.iter_at_line(line) else {
+        return;
+    };
+
+    if buffer
+        .source_marks_at_line(line, Some(BREAKPOINT))
+        .is_empty()
+    {
+        buffer.create_source_mark(None, BREAKPOINT, &start);
+    } else {
+        let mut end = start;
+        end.forward_to_line_end();
+        buffer.remove_source_marks(&start, &end, Some(BREAKPOINT));
+    }
+}
+
+/// Lines with a breakpoint, line numbers start at 1
+pub fn breakpoint_lines(buffer: &sourceview5::Buffer) -> Vec<u32> {
+    (0..buffer.line_count())
+        .filter(|&line| {
+            !buffer
+                .source_marks_at_line(line, Some(BREAKPOINT))
+                .is_empty()
+        })
+        .map(|line| line as u32 + 1)
+        .collect()
+}
+
+/// Highlights the line of the instruction at `pc`, or clears the highlight
+pub fn set_pc_line(buffer: &sourceview5::Buffer, line: Option<u32>) {
+    let (start, end) = buffer.bounds();
+    buffer.remove_source_marks(&start, &end, Some(PC));
+
+    if let Some(iter) = line.and_then(|line| buffer.iter_at_line(line as i32 - 1)) {
+        buffer.create_source_mark(None, PC, &iter);
+    }
+}
+
+/// Marks and underlines the lines of `diagnostics`, replacing those of the
+/// last assembly
+pub fn set_problems(
+    buffer: &sourceview5::Buffer,
+    problems: &Problems,
+    diagnostics: Vec<Diagnostic>,
+) {
+    let (start, end) = buffer.bounds();
+    for category in [ERROR, WARNING] {
+        buffer.remove_source_marks(&start, &end, Some(category));
+        buffer.remove_tag_by_name(category, &start, &end);
+    }
+
+    for (index, diagnostic) in diagnostics.iter().enumerate() {
+        let Some(mut start) = buffer.iter_at_line(diagnostic.line as i32 - 1) else {
+            continue;
+        };
+        match diagnostic.column {
+            Some(column) => {
+                start.set_line_offset((column as i32 - 1).min(start.chars_in_line() - 1).max(0))
+            }
+            None => {
+                while !start.ends_line() && start.char().is_whitespace() {
+                    start.forward_char();
+                }
+            }
+        }
+        let mut end = start;
+        if !end.ends_line() {
+            end.forward_to_line_end();
+        }
+
+        let category = match diagnostic.severity {
+            Severity::Error => ERROR,
+            Severity::Warning => WARNING,
+        };
+        buffer.create_source_mark(Some(&problem_mark(index)), category, &start);
+        buffer.apply_tag_by_name(category, &start, &end);
+    }
+
+    *problems.borrow_mut() = diagnostics;
+}
+
+fn problem_mark(index: usize) -> String {
+    format!("problem-{}", index)
+}
+
+/// Messages of the problems marked on `line`, one per line of text
+fn line_problems(buffer: &sourceview5::Buffer, problems: &Problems, line: i32) -> Option<String> {
+    let problems = problems.borrow();
+    let messages = buffer
+        .source_marks_at_line(line, None)
+        .iter()
+        .filter_map(|mark| mark.name()?.strip_prefix("problem-")?.parse::<usize>().ok())
+        .filter_map(|index| problems.get(index))
+        .map(|diagnostic| diagnostic.to_string())
+        .collect::<Vec<String>>();
+    (!messages.is_empty()).then(|| messages.join("\n"))
+}
+
 pub fn create_disasm(model: &gio::ListStore) -> gtk::ScrolledWindow {
     let column_view = gtk::ColumnView::new(Some(gtk::NoSelection::new(Some(model.clone()))));
 
@@ -112,19 +340,31 @@ pub fn create_disasm(model: &gio::ListStore) -> gtk::ScrolledWindow {
         let list_item = list_item_obj.downcast_ref::<gtk::ColumnViewCell>().unwrap();
         let disasm_obj = list_item.item().and_downcast::<DisasmObject>().unwrap();
         let label = list_item.child().and_downcast::<gtk::Label>().unwrap();
-        label.set_text(&disasm_obj.address().to_string());
+        if disasm_obj.is_label() {
+            label.set_text("");
+        } else {
+            label.set_text(&format!("{:08x}", disasm_obj.address()));
+        }
     });
     encoding_factory.connect_bind(|_, list_item_obj| {
         let list_item = list_item_obj.downcast_ref::<gtk::ColumnViewCell>().unwrap();
         let disasm_obj = list_item.item().and_downcast::<DisasmObject>().unwrap();
         let label = list_item.child().and_downcast::<gtk::Label>().unwrap();
-        label.set_text(&format!("{:08x}", disasm_obj.encoding()));
+        if disasm_obj.is_label() {
+            label.set_text("");
+        } else {
+            label.set_text(&format!("{:08x}", disasm_obj.encoding()));
+        }
     });
     source_factory.connect_bind(|_, list_item_obj| {
         let list_item = list_item_obj.downcast_ref::<gtk::ColumnViewCell>().unwrap();
         let disasm_obj = list_item.item().and_downcast::<DisasmObject>().unwrap();
         let label = list_item.child().and_downcast::<gtk::Label>().unwrap();
-        label.set_text(&disasm_obj.source());
+        if disasm_obj.is_label() {
+            label.set_text(&disasm_obj.source());
+        } else {
+            label.set_text(&format!("    {}", disasm_obj.source()));
+        }
     });
 
     column_view.insert_column(
@@ -1,7 +1,9 @@
+mod board_panel;
 mod bottom_pane;
 mod debug_panel;
 mod editor_pane;
 mod panes;
+mod settings;
 mod side_pane;
 mod status_bar;
 mod top_buttons;
@@ -11,17 +13,63 @@ use async_channel::Sender;
 use editor_pane::disasm_object::DisasmObject;
 use gtk::glib::Propagation;
 use gtk::{Orientation, gdk, gio, glib};
-use komodo::{RegTuple, Registers};
+use komodo::{
+    Assembler, AssemblyCache, Board, Cycles, DebugInfo, Diagnostic, History, Machine, RegTuple,
+    Registers, StopReason, TRACE_LIMIT, Trace, TraceFormat, Watch,
+};
 use side_pane::reg_object::RegObject;
 use std::{
-    io::Write,
+    collections::HashSet,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
     sync::{Arc, Mutex},
 };
-use tempfile::NamedTempFile;
 
 enum Signal {
     Log(String),
-    Halt(String, Vec<RegTuple>),
+    /// Message, registers and the source line of `pc` if execution is paused
+    Halt(String, Vec<RegTuple>, Option<u32>),
+    /// Diagnostics of an assembly, empty if it succeeded
+    Problems(Vec<Diagnostic>),
+    /// Cycles taken by the program so far
+    Cycles(Cycles),
+}
+
+/// A program being debugged
+struct Session {
+    machine: Machine,
+    debug_info: DebugInfo,
+    /// The program halted or faulted, and is only kept to step back from
+    exited: bool,
+}
+
+/// Options from the status bar and the settings, read when the program is run
+struct RunOptions {
+    /// `builtin` or `gnu`
+    assembler: String,
+    bare_metal: bool,
+    trace: bool,
+    /// Steps kept to step back over
+    history_size: u32,
+    /// Registers watched in the side pane
+    watches: Vec<Watch>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Command {
+    /// Assembles the buffer and runs it from the start
+    Run,
+    /// Runs from `pc` until a breakpoint or the program exits
+    Continue,
+    /// Executes a single instruction
+    Step,
+    /// Resets registers and memory without running
+    Restart,
+    /// Undoes the last instruction
+    StepBack,
+    /// Steps back until a breakpoint or the oldest step kept
+    ReverseContinue,
 }
 
 fn main() -> glib::ExitCode {
@@ -45,6 +93,7 @@ fn setup_shortcuts(app: &adw::Application) {
     app.add_action_entries([quit_action]);
     app.set_accels_for_action("app.quit", &["<control>q"]);
 
+    app.set_accels_for_action("win.action-settings", &["<control>comma"]);
     app.set_accels_for_action("win.action-toggle-side", &["<control>b"]);
     app.set_accels_for_action("win.action-toggle-bottom", &["<control>j"]);
     app.set_accels_for_action("win.action-zoom-in", &["<control>equal"]);
@@ -86,7 +135,8 @@ fn build_ui(app: &adw::Application, css_provider: &gtk::CssProvider) {
     toolbar.add_top_bar(&header);
     toolbar.set_content(Some(&container));
 
-    let (editor_scroll, buffer) = editor_pane::create_source(&window, &css_provider);
+    let problems = editor_pane::Problems::default();
+    let (editor_scroll, buffer) = editor_pane::create_source(&window, &css_provider, &problems);
     let center_box = gtk::CenterBox::builder()
         .hexpand(true)
         .center_widget(&editor_scroll)
@@ -98,13 +148,14 @@ fn build_ui(app: &adw::Application, css_provider: &gtk::CssProvider) {
         .map(|v| RegObject::new(v.0.to_string(), v.1))
         .collect::<Vec<RegObject>>();
 
-    let (b_pane, b_text_view) = bottom_pane::create();
-    container.append(&panes::create(
-        &window,
-        &center_box,
-        &side_pane::create(&vec_reg_objs),
-        &b_pane,
-    ));
+    // mapped into every program, so the LEDs stay lit between runs
+    let board = Board::new();
+    let side_box = gtk::Box::new(Orientation::Vertical, 0);
+    side_box.append(&side_pane::create(&vec_reg_objs));
+    side_box.append(&board_panel::create(&board));
+
+    let (b_pane, b_scroll, b_text_view, b_problems) = bottom_pane::create();
+    container.append(&panes::create(&window, &center_box, &side_box, &b_pane));
 
     let (revealer, stop_btn) = debug_panel::create();
     container.append(&revealer);
@@ -120,7 +171,70 @@ fn build_ui(app: &adw::Application, css_provider: &gtk::CssProvider) {
         .build();
     window.add_action_entries([action_debug]);
 
-    toolbar.add_bottom_bar(&status_bar::create());
+    // the assembler used by the debug actions, `builtin` or `gnu`
+    let action_assembler = gio::ActionEntry::builder("action-assembler")
+        .parameter_type(Some(&String::static_variant_type()))
+        .state(Assembler::default().name().to_variant())
+        .activate(move |_: &adw::ApplicationWindow, action, parameter| {
+            if let Some(parameter) = parameter {
+                action.set_state(parameter);
+            }
+        })
+        .build();
+    window.add_action_entries([action_assembler]);
+
+    // exceptions branch to the vectors instead of `swi` calling the Komodo services
+    let action_bare_metal = gio::ActionEntry::builder("action-bare-metal")
+        .state(false.to_variant())
+        .activate(move |_: &adw::ApplicationWindow, action, _| {
+            let bare_metal = action.state().and_then(|state| state.get::<bool>());
+            action.set_state(&(!bare_metal.unwrap_or(false)).to_variant());
+        })
+        .build();
+    window.add_action_entries([action_bare_metal]);
+
+    // records the instructions executed by the next run
+    let action_trace = gio::ActionEntry::builder("action-trace")
+        .state(false.to_variant())
+        .activate(move |_: &adw::ApplicationWindow, action, _| {
+            let trace = action.state().and_then(|state| state.get::<bool>());
+            action.set_state(&(!trace.unwrap_or(false)).to_variant());
+        })
+        .build();
+    window.add_action_entries([action_trace]);
+
+    let action_history_size = gio::ActionEntry::builder("action-history-size")
+        .parameter_type(Some(&u32::static_variant_type()))
+        .state(settings::DEFAULT_HISTORY_SIZE.to_variant())
+        .activate(move |_: &adw::ApplicationWindow, action, parameter| {
+            if let Some(parameter) = parameter {
+                action.set_state(parameter);
+            }
+        })
+        .build();
+    let action_settings = gio::ActionEntry::builder("action-settings")
+        .activate(move |window: &adw::ApplicationWindow, _, _| settings::present(window))
+        .build();
+    window.add_action_entries([action_history_size, action_settings]);
+
+    // watches the register named by the parameter, or stops watching it
+    let action_watch = gio::ActionEntry::builder("action-watch")
+        .parameter_type(Some(&String::static_variant_type()))
+        .activate(glib::clone!(
+            #[strong]
+            vec_reg_objs,
+            move |_: &adw::ApplicationWindow, _, parameter| {
+                let name = parameter.and_then(|parameter| parameter.get::<String>());
+                for obj in vec_reg_objs.iter().filter(|obj| Some(obj.name()) == name) {
+                    obj.set_watched(!obj.watched());
+                }
+            }
+        ))
+        .build();
+    window.add_action_entries([action_watch]);
+
+    let (status_bar, cycles_label) = status_bar::create();
+    toolbar.add_bottom_bar(&status_bar);
 
     let (sender, receiver) = async_channel::bounded::<Signal>(1);
     let stopped = Arc::new(Mutex::new(false));
@@ -131,64 +245,110 @@ fn build_ui(app: &adw::Application, css_provider: &gtk::CssProvider) {
     controller.connect_key_pressed(glib::clone!(
         #[strong]
         read_char,
+        #[strong]
+        board,
         move |_, key, _, _| {
             if let Some(unicode) = key.to_unicode() {
                 *read_char.lock().unwrap() = Some(unicode);
+                board.uart.lock().unwrap().receive(unicode as u8);
             }
             Propagation::Stop
         }
     ));
     b_text_view.add_controller(controller);
 
-    let action_run = gio::ActionEntry::builder("action-run")
-        .activate(glib::clone!(
-            #[strong]
-            sender,
-            #[strong]
-            buffer,
-            #[strong]
-            vec_reg_objs,
-            #[strong]
-            stopped,
-            #[strong]
-            read_char,
-            #[strong]
-            first_execution,
-            move |_: &adw::ApplicationWindow, _, _| {
-                reset_pc(&vec_reg_objs);
-
-                let vec_regs = vec_reg_objs
-                    .iter()
-                    .map(|obj| (obj.name(), obj.number()))
-                    .collect::<Vec<RegTuple>>();
-                let buffer_text = buffer_get_text(&buffer);
+    let session = Arc::new(Mutex::new(Option::<Session>::None));
+    // the trace of the last session to end, the paused session keeps its own
+    let last_trace = Arc::new(Mutex::new(Option::<Trace>::None));
+    // shared by running and viewing the disassembly of the same source
+    let cache = Arc::new(Mutex::new(AssemblyCache::new(AssemblyCache::user_dir())));
+
+    let debug_actions = [
+        ("action-run", Command::Run),
+        ("action-continue", Command::Continue),
+        ("action-step", Command::Step),
+        ("action-restart", Command::Restart),
+        ("action-step-back", Command::StepBack),
+        ("action-reverse-continue", Command::ReverseContinue),
+    ]
+    .map(|(name, command)| {
+        gio::ActionEntry::builder(name)
+            .activate(glib::clone!(
+                #[strong]
+                sender,
+                #[strong]
+                buffer,
+                #[strong]
+                vec_reg_objs,
+                #[strong]
+                stopped,
+                #[strong]
+                read_char,
+                #[strong]
+                first_execution,
+                #[strong]
+                session,
+                #[strong]
+                cache,
+                #[strong]
+                board,
+                #[strong]
+                last_trace,
+                move |window: &adw::ApplicationWindow, _, _| {
+                    if command == Command::Run {
+                        reset_pc(&vec_reg_objs);
+                    }
 
-                gio::spawn_blocking(glib::clone!(
-                    #[strong]
-                    sender,
-                    #[strong]
-                    stopped,
-                    #[strong]
-                    read_char,
-                    #[strong]
-                    first_execution,
-                    move || {
-                        on_action_run(
-                            &vec_regs,
-                            buffer_text,
-                            sender.clone(),
-                            stopped.clone(),
-                            read_char.clone(),
-                            first_execution.clone(),
-                        );
-                        {
-                            *first_execution.lock().unwrap() = false;
+                    let vec_regs = vec_reg_objs
+                        .iter()
+                        .map(|obj| (obj.name(), obj.number()))
+                        .collect::<Vec<RegTuple>>();
+                    let buffer_text = buffer_get_text(&buffer);
+                    let breakpoint_lines = editor_pane::breakpoint_lines(&buffer);
+                    let options = run_options(window, &vec_reg_objs);
+
+                    gio::spawn_blocking(glib::clone!(
+                        #[strong]
+                        sender,
+                        #[strong]
+                        stopped,
+                        #[strong]
+                        read_char,
+                        #[strong]
+                        first_execution,
+                        #[strong]
+                        session,
+                        #[strong]
+                        cache,
+                        #[strong]
+                        board,
+                        #[strong]
+                        last_trace,
+                        move || {
+                            on_action_run(
+                                command,
+                                &vec_regs,
+                                buffer_text,
+                                options,
+                                breakpoint_lines,
+                                sender.clone(),
+                                stopped.clone(),
+                                read_char.clone(),
+                                first_execution.clone(),
+                                session.clone(),
+                                cache.clone(),
+                                &board,
+                                &last_trace,
+                            );
+                            {
+                                *first_execution.lock().unwrap() = false;
+                            }
                         }
-                    }
-                ));
-            }
-        ))
-        .build();
+                    ));
+                }
+            ))
+            .build()
+    });
 
     stop_btn.connect_clicked(glib::clone!(
         #[strong]
@@ -200,7 +360,13 @@ fn build_ui(app: &adw::Application, css_provider: &gtk::CssProvider) {
         #[weak]
         run_btn,
         #[strong]
-        b_pane,
+        b_scroll,
+        #[strong]
+        b_problems,
+        #[strong]
+        buffer,
+        #[strong]
+        problems,
         async move {
             while let Ok(signal) = receiver.recv().await {
                 match signal {
@@ -209,40 +375,46 @@ fn build_ui(app: &adw::Application, css_provider: &gtk::CssProvider) {
 
                         glib::idle_add_local(glib::clone!(
                             #[strong]
-                            b_pane,
+                            b_scroll,
                             move || {
-                                let vadj = b_pane.vadjustment();
+                                let vadj = b_scroll.vadjustment();
                                 vadj.set_value(f64::MAX);
-                                b_pane.set_vadjustment(Some(&vadj));
+                                b_scroll.set_vadjustment(Some(&vadj));
                                 return glib::ControlFlow::Break;
                             }
                         ));
 
                         run_btn.set_sensitive(false);
                     }
-                    Signal::Halt(s, vec_regs) => {
+                    Signal::Halt(s, vec_regs, pc_line) => {
                         text_view_append(&b_text_view, s);
 
                         glib::idle_add_local(glib::clone!(
                             #[strong]
-                            b_pane,
+                            b_scroll,
                             move || {
-                                let vadj = b_pane.vadjustment();
+                                let vadj = b_scroll.vadjustment();
                                 vadj.set_value(f64::MAX);
-                                b_pane.set_vadjustment(Some(&vadj));
+                                b_scroll.set_vadjustment(Some(&vadj));
                                 return glib::ControlFlow::Break;
                             }
                         ));
 
                         run_btn.set_sensitive(true);
                         apply_backend_updates(&vec_reg_objs, vec_regs);
+                        editor_pane::set_pc_line(&buffer, pc_line);
+                    }
+                    Signal::Cycles(cycles) => cycles_label.set_label(&cycles.to_string()),
+                    Signal::Problems(diagnostics) => {
+                        bottom_pane::set_problems(&b_problems, &diagnostics);
+                        editor_pane::set_problems(&buffer, &problems, diagnostics);
                     }
                 }
             }
         }
     ));
 
-    window.add_action_entries([action_run]);
+    window.add_action_entries(debug_actions);
 
     let action_view_source = gio::ActionEntry::builder("action-view-source")
         .activate(glib::clone!(
@@ -267,25 +439,58 @@ fn build_ui(app: &adw::Application, css_provider: &gtk::CssProvider) {
             buffer,
             #[strong]
             model,
-            move |_: &adw::ApplicationWindow, _, _| {
+            #[strong]
+            b_problems,
+            #[strong]
+            problems,
+            #[strong]
+            cache,
+            move |window: &adw::ApplicationWindow, _, _| {
                 let cs = komodo::new_capstone();
-                let mut input_file: NamedTempFile = NamedTempFile::new().unwrap();
-                write!(input_file, "{}", buffer_get_text(&buffer)).unwrap();
-                let input_path = input_file.path().as_os_str().to_owned();
-                let (_, _, instrs) = komodo::disassemble(&cs, input_path).unwrap();
+                let thumb_cs = komodo::new_thumb_capstone();
+                let buffer_text = buffer_get_text(&buffer);
+                let program = Assembler::from_name(&selected_assembler(window), None)
+                    .and_then(|assembler| cache.lock().unwrap().assemble(&buffer_text, &assembler))
+                    .and_then(|(memory, debug_info)| {
+                        let instrs = komodo::disassemble_mapped(
+                            &cs,
+                            &thumb_cs,
+                            &memory,
+                            &debug_info.symbols,
+                        )?;
+                        Ok((memory, instrs, debug_info))
+                    });
+                let diagnostics = match &program {
+                    Ok(_) => Vec::new(),
+                    Err(e) => komodo::parse_diagnostics(e),
+                };
+                bottom_pane::set_problems(&b_problems, &diagnostics);
+                editor_pane::set_problems(&buffer, &problems, diagnostics);
+                let (memory, instrs, debug_info) = match program {
+                    Ok(program) => program,
+                    Err(e) => {
+                        eprintln!("{e}");
+                        return;
+                    }
+                };
 
                 model.remove_all();
-                for i in instrs.iter() {
-                    let mut bytes: Vec<u8> = Vec::new();
-                    for &b in i.bytes().iter().rev() {
-                        bytes.push(b);
+                for i in instrs.iter().flat_map(|instrs| instrs.iter()) {
+                    for name in debug_info.symbols.labels_at(i.address() as u32) {
+                        model.append(&DisasmObject::new_label(i.address() as u32, name));
                     }
-                    let encoding = u32::from_be_bytes(bytes.clone().try_into().unwrap());
+
+                    // Thumb instructions are a single halfword, or a pair for `bl`
+                    let encoding = i
+                        .bytes()
+                        .iter()
+                        .rev()
+                        .fold(0, |word, &b| word << 8 | b as u32);
 
                     model.append(&DisasmObject::new(
                         i.address() as u32,
                         encoding,
-                        i.mnemonic().unwrap().to_string() + " " + i.op_str().unwrap(),
+                        komodo::symbolic_disasm(i, &memory, &debug_info.symbols),
                     ));
                 }
 
@@ -295,89 +500,306 @@ fn build_ui(app: &adw::Application, css_provider: &gtk::CssProvider) {
         .build();
     window.add_action_entries([action_view_source, action_view_disasm]);
 
+    let action_save_trace = gio::ActionEntry::builder("action-save-trace")
+        .activate(glib::clone!(
+            #[strong]
+            session,
+            #[strong]
+            last_trace,
+            move |window: &adw::ApplicationWindow, _, _| {
+                let dialog = gtk::FileDialog::builder()
+                    .title("Save trace")
+                    .initial_name("trace.txt")
+                    .build();
+                dialog.save(
+                    Some(window),
+                    None::<&gio::Cancellable>,
+                    glib::clone!(
+                        #[strong]
+                        session,
+                        #[strong]
+                        last_trace,
+                        move |result| {
+                            let Some(path) = result.ok().and_then(|file| file.path()) else {
+                                return;
+                            };
+                            if let Err(e) = save_trace(&path, &session, &last_trace) {
+                                eprintln!("{e}");
+                            }
+                        }
+                    ),
+                );
+            }
+        ))
+        .build();
+    window.add_action_entries([action_save_trace]);
+
     window.set_title(Some("Komodo, University of Nottingham"));
     window.set_content(Some(&toolbar));
     window.present();
 }
 
+#[allow(clippy::too_many_arguments)]
 fn on_action_run(
+    command: Command,
     vec_regs: &Vec<RegTuple>,
     buffer_text: String,
+    options: RunOptions,
+    breakpoint_lines: Vec<u32>,
     sender: Sender<Signal>,
     stopped: Arc<Mutex<bool>>,
     read_char: Arc<Mutex<Option<char>>>,
     first_execution: Arc<Mutex<bool>>,
+    session: Arc<Mutex<Option<Session>>>,
+    cache: Arc<Mutex<AssemblyCache>>,
+    board: &Board,
+    last_trace: &Mutex<Option<Trace>>,
 ) {
-    let msg: String;
+    let mut session = session.lock().unwrap();
+    let reverse = matches!(command, Command::StepBack | Command::ReverseContinue);
+
+    if reverse && session.is_none() {
+        sender
+            .send_blocking(Signal::Halt(
+                "[nothing to step back over]".to_string(),
+                vec_regs.clone(),
+                None,
+            ))
+            .unwrap();
+        return;
+    }
+
+    // an exited program runs again from the start, and restarting without a
+    // program assembles one
+    let exited = session.as_ref().is_none_or(|session| session.exited);
+    if command == Command::Run
+        || session.is_none()
+        || (exited && matches!(command, Command::Continue | Command::Step))
     {
-        msg = if *first_execution.lock().unwrap() {
-            "> assembling".to_string()
-        } else {
-            "\n\n> assembling".to_string()
+        let msg: String;
+        {
+            msg = if *first_execution.lock().unwrap() {
+                "> assembling".to_string()
+            } else {
+                "\n\n> assembling".to_string()
+            }
         }
-    }
-    sender.send_blocking(Signal::Log(msg)).unwrap();
-
-    let cs = komodo::new_capstone();
-    let mut input_file: NamedTempFile = NamedTempFile::new().unwrap();
-    write!(input_file, "{}", buffer_text).unwrap();
-    let input_path = input_file.path().as_os_str().to_owned();
-
-    match komodo::disassemble(&cs, input_path) {
-        Ok((data_section, text_section, instrs)) => {
-            sender
-                .send_blocking(Signal::Log("\n> executing\n".to_string()))
-                .unwrap();
-
-            let mut regs = Registers::new();
-            regs.apply_ui_updates(&vec_regs);
-            let read_char = || {
-                loop {
-                    let mut handle = read_char.lock().unwrap();
-                    if let Some(c) = *handle {
-                        *handle = None;
-                        return c;
-                    }
+        sender.send_blocking(Signal::Log(msg)).unwrap();
+
+        let result = Assembler::from_name(&options.assembler, None).and_then(|assembler| {
+            if let Assembler::Gnu(toolchain) = &assembler {
+                let msg = format!(" with {}", toolchain);
+                sender.send_blocking(Signal::Log(msg)).unwrap();
+            }
+            let mut cache = cache.lock().unwrap();
+            let (memory, debug_info) = cache.assemble(&buffer_text, &assembler)?;
+            // also reports a failure from viewing the disassembly
+            if let Some(e) = cache.take_write_error() {
+                sender
+                    .send_blocking(Signal::Log(format!("\n{}", e)))
+                    .unwrap();
+            }
+            Ok(Session {
+                machine: Machine::new(&komodo::new_capstone(), memory)?,
+                debug_info,
+                exited: false,
+            })
+        });
+        match result {
+            Ok(mut new_session) => {
+                sender.send_blocking(Signal::Problems(Vec::new())).unwrap();
+                let machine = &mut new_session.machine;
+                for (base, device) in board.devices() {
+                    machine.map_peripheral(base, device);
                 }
-            };
-            let mut print = |str: String| sender.send_blocking(Signal::Log(str)).unwrap();
-            let is_stopped = || *stopped.lock().unwrap();
-
-            komodo::run_program(
-                &cs,
-                data_section,
-                text_section,
-                instrs,
-                &mut regs,
-                &read_char,
-                &mut print,
-                is_stopped,
-            );
-
-            let vec_regs_ret = regs.to_ui_format();
-
-            {
-                let mut stopped_handle = stopped.lock().unwrap();
-                if *stopped_handle {
-                    sender
-                        .send_blocking(Signal::Halt("[stopped]".to_string(), vec_regs_ret))
-                        .unwrap();
-                    *stopped_handle = false;
-                } else {
-                    sender
-                        .send_blocking(Signal::Halt("[exited]".to_string(), vec_regs_ret))
-                        .unwrap();
+                machine.bare_metal = options.bare_metal;
+                machine.trace = options.trace.then(|| Trace::with_limit(TRACE_LIMIT));
+                machine.history =
+                    (options.history_size > 0).then(|| History::new(options.history_size as usize));
+                // also resets the board, in bare metal mode the registers too
+                // and execution starts from the reset vector
+                machine.reset();
+                if !options.bare_metal {
+                    machine.regs.apply_ui_updates(vec_regs);
+                    machine.regs.r15_pc = komodo::TEXT_BASE as i32;
                 }
+                *session = Some(new_session);
+            }
+            Err(s) => {
+                sender
+                    .send_blocking(Signal::Problems(komodo::parse_diagnostics(&s)))
+                    .unwrap();
+                sender
+                    .send_blocking(Signal::Halt(
+                        format!("\n{}[failure]", s),
+                        vec_regs.clone(),
+                        None,
+                    ))
+                    .unwrap();
+                return;
+            }
+        }
+
+        sender
+            .send_blocking(Signal::Log("\n> executing\n".to_string()))
+            .unwrap();
+    } else if command != Command::Restart {
+        // registers edited in the side pane while paused take effect
+        session
+            .as_mut()
+            .unwrap()
+            .machine
+            .regs
+            .apply_ui_updates(vec_regs);
+    }
+
+    let Session {
+        machine,
+        debug_info,
+        exited,
+    } = session.as_mut().unwrap();
+    // watches added or removed while paused apply from here on
+    machine.watches = options.watches;
+    let breakpoints = breakpoint_lines
+        .iter()
+        .flat_map(|&line| debug_info.lines.addresses(line))
+        .collect::<HashSet<u32>>();
+
+    let read_char = || {
+        loop {
+            let mut handle = read_char.lock().unwrap();
+            if let Some(c) = *handle {
+                *handle = None;
+                return c;
             }
         }
-        Err(s) => {
-            sender
-                .send_blocking(Signal::Halt(format!("\n{}[failure]", s), vec_regs.clone()))
-                .unwrap();
+    };
+    let mut print = |str: String| sender.send_blocking(Signal::Log(str)).unwrap();
+    let is_stopped = || *stopped.lock().unwrap();
+
+    let result = match command {
+        Command::Run | Command::Continue => {
+            machine.run_until(&breakpoints, &read_char, &mut print, is_stopped)
+        }
+        Command::Step => machine.step(&read_char, &mut print),
+        Command::Restart => {
+            machine.reset();
+            Ok(StopReason::Step)
+        }
+        Command::StepBack => match machine.step_back() {
+            true => Ok(StopReason::Step),
+            false => Ok(StopReason::StartOfHistory),
+        },
+        Command::ReverseContinue => Ok(machine.reverse_until(&breakpoints, is_stopped)),
+    };
+
+    let vec_regs_ret = machine.regs.to_ui_format();
+    sender
+        .send_blocking(Signal::Cycles(machine.cycles))
+        .unwrap();
+    let mut pc_line = debug_info.lines.line(machine.pc());
+    *stopped.lock().unwrap() = false;
+
+    *exited = matches!(
+        result,
+        Ok(StopReason::Halted | StopReason::EndOfProgram) | Err(_)
+    );
+    let msg = match result {
+        Ok(StopReason::Step) if command == Command::Restart => "[restarted]".to_string(),
+        Ok(StopReason::Step) => String::new(),
+        Ok(StopReason::Breakpoint(addr)) => format!("[breakpoint at {:#010x}]", addr),
+        Ok(StopReason::Stopped) => "[stopped]".to_string(),
+        Ok(StopReason::StartOfHistory) => "[start of history]".to_string(),
+        Ok(StopReason::Watchpoint { index, address }) => {
+            format!(
+                "[watchpoint {} at {:#010x}]",
+                machine.watches[index], address
+            )
+        }
+        Ok(StopReason::Halted | StopReason::EndOfProgram) => {
+            pc_line = None;
+            "[exited]".to_string()
         }
+        Err(e) => format!("\n{}\n[error]", e),
+    };
+    // with a history the program is kept to step back from where it exited
+    if *exited && machine.history.is_none() {
+        *last_trace.lock().unwrap() = machine.trace.take();
+        *session = None;
+    }
+    sender
+        .send_blocking(Signal::Halt(msg, vec_regs_ret, pc_line))
+        .unwrap();
+}
+
+/// Name of the assembler picked in the status bar, `builtin` or `gnu`
+fn selected_assembler(window: &adw::ApplicationWindow) -> String {
+    window
+        .action_state("action-assembler")
+        .and_then(|state| state.get::<String>())
+        .unwrap_or(Assembler::default().name().to_string())
+}
+
+/// Whether bare metal mode is ticked in the status bar
+fn bare_metal_selected(window: &adw::ApplicationWindow) -> bool {
+    window
+        .action_state("action-bare-metal")
+        .and_then(|state| state.get::<bool>())
+        .unwrap_or(false)
+}
+
+/// The options the program is run with, watching the registers of
+/// `vec_reg_objs` that are watched
+fn run_options(window: &adw::ApplicationWindow, vec_reg_objs: &[RegObject]) -> RunOptions {
+    RunOptions {
+        assembler: selected_assembler(window),
+        bare_metal: bare_metal_selected(window),
+        trace: trace_selected(window),
+        history_size: window
+            .action_state("action-history-size")
+            .and_then(|state| state.get::<u32>())
+            .unwrap_or(settings::DEFAULT_HISTORY_SIZE),
+        watches: vec_reg_objs
+            .iter()
+            .filter(|obj| obj.watched())
+            .filter_map(|obj| side_pane::watch_name(&obj.name())?.parse().ok())
+            .collect(),
     }
 }
 
+/// Whether tracing is ticked in the status bar
+fn trace_selected(window: &adw::ApplicationWindow) -> bool {
+    window
+        .action_state("action-trace")
+        .and_then(|state| state.get::<bool>())
+        .unwrap_or(false)
+}
+
+/// Writes the trace of the paused session, or else of the last one to end, to
+/// `path` in the format its extension names
+fn save_trace(
+    path: &Path,
+    session: &Mutex<Option<Session>>,
+    last_trace: &Mutex<Option<Trace>>,
+) -> Result<(), String> {
+    // the session is locked for as long as the program runs
+    let session = session
+        .try_lock()
+        .map_err(|_| "Cannot save the trace while the program is running".to_string())?;
+    let last_trace = last_trace.lock().unwrap();
+    let trace = session
+        .as_ref()
+        .and_then(|session| session.machine.trace.as_ref())
+        .or(last_trace.as_ref())
+        .ok_or("No trace was recorded, tick Trace before running".to_string())?;
+
+    let mut out = BufWriter::new(File::create(path).map_err(|e| e.to_string())?);
+    trace
+        .write(TraceFormat::from_path(path), &mut out)
+        .and_then(|_| out.flush())
+        .map_err(|e| e.to_string())
+}
+
 fn buffer_get_text(buffer: &sourceview5::Buffer) -> String {
     let bounds = buffer.bounds();
     let text = buffer.text(&bounds.0, &bounds.1, true);
@@ -0,0 +1,42 @@
+use adw::prelude::*;
+
+/// Steps kept for stepping back unless changed in the settings
+pub const DEFAULT_HISTORY_SIZE: u32 = 10_000;
+
+/// Shows the settings dialog over `window`, changes apply to the next run
+pub fn present(window: &adw::ApplicationWindow) {
+    let history_size = window
+        .action_state("action-history-size")
+        .and_then(|state| state.get::<u32>())
+        .unwrap_or(DEFAULT_HISTORY_SIZE);
+
+    let history = adw::SpinRow::builder()
+        .title("Step back history")
+        .subtitle("Instructions that can be stepped back over, 0 turns stepping back off")
+        .adjustment(&gtk::Adjustment::new(
+            history_size as f64,
+            0.0,
+            1_000_000.0,
+            1_000.0,
+            10_000.0,
+            0.0,
+        ))
+        .build();
+    history.connect_value_notify(move |row| {
+        row.activate_action(
+            "win.action-history-size",
+            Some(&(row.value() as u32).to_variant()),
+        )
+        .unwrap();
+    });
+
+    let debugger = adw::PreferencesGroup::builder().title("Debugger").build();
+    debugger.add(&history);
+
+    let page = adw::PreferencesPage::new();
+    page.add(&debugger);
+
+    let dialog = adw::PreferencesDialog::new();
+    dialog.add(&page);
+    dialog.present(Some(window));
+}
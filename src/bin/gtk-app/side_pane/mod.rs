@@ -4,6 +4,7 @@ use std::cell::Cell;
 use std::rc::Rc;
 
 use gtk::Align;
+use gtk::gdk;
 use gtk::gio;
 use gtk::gio::ListStore;
 use gtk::glib;
@@ -26,16 +27,59 @@ pub fn create(vec: &Vec<RegObject>) -> gtk::ScrolledWindow {
     let value_factory = gtk::SignalListItemFactory::new();
 
     register_factory.connect_setup(|_, list_item_obj| {
-        list_item_obj
-            .downcast_ref::<gtk::ColumnViewCell>()
-            .unwrap()
-            .set_child(Some(
-                &gtk::Label::builder()
-                    .halign(Align::Start)
-                    .css_classes(["font-12"])
-                    .width_chars(5)
-                    .build(),
-            ));
+        let list_item = list_item_obj.downcast_ref::<gtk::ColumnViewCell>().unwrap();
+        let label = gtk::Label::builder()
+            .halign(Align::Start)
+            .css_classes(["font-12"])
+            .width_chars(5)
+            .build();
+
+        // right-clicking a register offers to watch it
+        let gesture = gtk::GestureClick::builder()
+            .button(gdk::BUTTON_SECONDARY)
+            .build();
+        gesture.connect_pressed(glib::clone!(
+            #[weak]
+            list_item,
+            #[weak]
+            label,
+            move |_, _, x, y| {
+                let Some(int_obj) = list_item.item().and_downcast::<RegObject>() else {
+                    return;
+                };
+                let name = int_obj.name();
+                let Some(watch) = watch_name(&name) else {
+                    return;
+                };
+                let text = match int_obj.watched() {
+                    true => format!("Stop watching {}", watch),
+                    false => format!("Watch {}", watch),
+                };
+                let item = gio::MenuItem::new(Some(&text), None);
+                item.set_action_and_target_value(
+                    Some("win.action-watch"),
+                    Some(&name.to_variant()),
+                );
+                let menu = gio::Menu::new();
+                menu.append_item(&item);
+
+                let popover = gtk::PopoverMenu::from_model(Some(&menu));
+                popover.set_has_arrow(false);
+                popover.set_pointing_to(Some(&gdk::Rectangle::new(x as i32, y as i32, 1, 1)));
+                popover.set_parent(&label);
+                // unparented once the item's action has been activated
+                popover.connect_closed(|popover| {
+                    glib::idle_add_local_once(glib::clone!(
+                        #[weak]
+                        popover,
+                        move || popover.unparent()
+                    ));
+                });
+                popover.popup();
+            }
+        ));
+        label.add_controller(gesture);
+        list_item.set_child(Some(&label));
     });
     value_factory.connect_setup(|_, list_item_obj| {
         list_item_obj
@@ -48,11 +92,19 @@ pub fn create(vec: &Vec<RegObject>) -> gtk::ScrolledWindow {
         let list_item = list_item_obj.downcast_ref::<gtk::ColumnViewCell>().unwrap();
         let int_obj = list_item.item().and_downcast::<RegObject>().unwrap();
 
-        list_item
-            .child()
-            .and_downcast::<gtk::Label>()
-            .unwrap()
-            .set_label(&int_obj.name());
+        let label = list_item.child().and_downcast::<gtk::Label>().unwrap();
+        label.set_label(&int_obj.name());
+        int_obj
+            .bind_property("watched", &label, "css-classes")
+            .transform_to(|_, watched: bool| {
+                let classes = match watched {
+                    true => ["font-12", "watched"].as_slice(),
+                    false => ["font-12"].as_slice(),
+                };
+                Some(glib::StrV::from(classes).to_value())
+            })
+            .sync_create()
+            .build();
     });
 
     let side_pane_box = gtk::Box::new(gtk::Orientation::Vertical, 8);
@@ -64,10 +116,12 @@ pub fn create(vec: &Vec<RegObject>) -> gtk::ScrolledWindow {
     let z_toggle = toggle_btn_create("Z", "Zero");
     let c_toggle = toggle_btn_create("C", "Carry");
     let v_toggle = toggle_btn_create("V", "Overflow");
+    let t_toggle = toggle_btn_create("T", "Thumb state");
     toggle_btns.append(&n_toggle);
     toggle_btns.append(&z_toggle);
     toggle_btns.append(&c_toggle);
     toggle_btns.append(&v_toggle);
+    toggle_btns.append(&t_toggle);
 
     let signal_from_toggle = Rc::new(Cell::new(false));
     let signal_from_field = Rc::new(Cell::new(false));
@@ -144,6 +198,25 @@ pub fn create(vec: &Vec<RegObject>) -> gtk::ScrolledWindow {
         }
     ));
 
+    t_toggle.connect_active_notify(glib::clone!(
+        #[strong]
+        model,
+        #[strong]
+        signal_from_toggle,
+        #[strong]
+        signal_from_field,
+        move |_| {
+            if signal_from_field.get() {
+                return;
+            }
+            toggle_update_apsr(&model, signal_from_toggle.clone(), |sf| {
+                let mut sf_copy = sf.clone();
+                sf_copy.thumb = !sf_copy.thumb;
+                sf_copy
+            });
+        }
+    ));
+
     let psr_box = gtk::Box::builder()
         .orientation(gtk::Orientation::Vertical)
         .margin_start(8)
@@ -209,6 +282,8 @@ pub fn create(vec: &Vec<RegObject>) -> gtk::ScrolledWindow {
         #[strong]
         v_toggle,
         #[strong]
+        t_toggle,
+        #[strong]
         dropdown,
         #[strong]
         signal_from_toggle,
@@ -230,6 +305,8 @@ pub fn create(vec: &Vec<RegObject>) -> gtk::ScrolledWindow {
                     #[strong]
                     v_toggle,
                     #[strong]
+                    t_toggle,
+                    #[strong]
                     dropdown,
                     #[strong]
                     signal_from_toggle,
@@ -246,6 +323,7 @@ pub fn create(vec: &Vec<RegObject>) -> gtk::ScrolledWindow {
                         z_toggle.set_active(sf.zero);
                         c_toggle.set_active(sf.carry);
                         v_toggle.set_active(sf.overflow);
+                        t_toggle.set_active(sf.thumb);
 
                         use komodo::ProcessorMode::*;
                         match sf.processor_mode {
@@ -291,6 +369,15 @@ pub fn create(vec: &Vec<RegObject>) -> gtk::ScrolledWindow {
     scroll
 }
 
+/// What watching the register named `name` in the side pane is written as,
+/// `r13` for `r13/sp` and `None` for the SPSR, which can't be watched
+pub fn watch_name(name: &str) -> Option<&str> {
+    match name {
+        "spsr" => None,
+        _ => name.split('/').next(),
+    }
+}
+
 fn spin_btn_create() -> gtk::SpinButton {
     let adjustment = gtk::Adjustment::new(0.0, i32::MIN.into(), i32::MAX.into(), 1.0, 0.0, 0.0);
     let spin_btn = gtk::SpinButton::builder()
@@ -14,6 +14,9 @@ pub struct RegObject {
     name: RefCell<String>,
     #[property(get, set)]
     number: Cell<i32>,
+    /// Execution stops after an instruction changes the register
+    #[property(get, set)]
+    watched: Cell<bool>,
 }
 
 // The central trait for subclassing a GObject
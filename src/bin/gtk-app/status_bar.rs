@@ -1,6 +1,7 @@
 use adw::prelude::*;
 
-pub fn create() -> gtk::HeaderBar {
+/// The status bar and the label showing the cycles taken by the program
+pub fn create() -> (gtk::HeaderBar, gtk::Label) {
     let header = gtk::HeaderBar::builder()
         .title_widget(&gtk::Label::new(Some("")))
         .show_title_buttons(false)
@@ -21,8 +22,54 @@ pub fn create() -> gtk::HeaderBar {
             .unwrap();
     });
 
+    // names accepted by `komodo::Assembler::from_name`, in the order of the items
+    let names = ["builtin", "gnu"];
+    let assembler = gtk::DropDown::from_strings(&["Built-in assembler", "GNU assembler"]);
+    assembler.set_tooltip_text(Some("Assembler used to build the program"));
+    assembler.connect_selected_notify(move |dropdown| {
+        let name = names[dropdown.selected() as usize];
+        dropdown
+            .activate_action("win.action-assembler", Some(&name.to_variant()))
+            .unwrap();
+    });
+
+    let bare_metal = gtk::CheckButton::builder()
+        .label("Bare metal")
+        .tooltip_text("Exceptions branch to the vectors at 0x00-0x1c")
+        .action_name("win.action-bare-metal")
+        .build();
+
+    let trace = gtk::CheckButton::builder()
+        .label("Trace")
+        .tooltip_text("Record the instructions executed by the next run")
+        .action_name("win.action-trace")
+        .build();
+    let save_trace = gtk::Button::builder()
+        .label("Save trace…")
+        .tooltip_text("Save the trace as text, JSON Lines (.jsonl) or CSV (.csv)")
+        .action_name("win.action-save-trace")
+        .build();
+
+    let settings = gtk::Button::builder()
+        .icon_name("emblem-system-symbolic")
+        .tooltip_text("Settings")
+        .action_name("win.action-settings")
+        .build();
+
+    let cycles = gtk::Label::builder()
+        .label(komodo::Cycles::default().to_string())
+        .tooltip_text("Cycles taken on an ARM7TDMI with no wait states")
+        .css_classes(["font-12"])
+        .build();
+
     header.pack_start(&toggle_left);
+    header.pack_start(&cycles);
     header.pack_end(&toggle_bottom);
+    header.pack_end(&settings);
+    header.pack_end(&assembler);
+    header.pack_end(&bare_metal);
+    header.pack_end(&save_trace);
+    header.pack_end(&trace);
 
-    return header;
+    return (header, cycles);
 }
@@ -0,0 +1,187 @@
+use crate::{Assembler, DebugInfo, Memory, Toolchain, assembler, gnu_object, load_object};
+use std::{
+    env, fs,
+    io::Write,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+use tempfile::NamedTempFile;
+
+/// Programs kept in memory, the least recently used is evicted first
+const CAPACITY: usize = 16;
+
+/// File name the built-in assembler reports errors against
+const SOURCE_NAME: &str = "program.s";
+
+/// Assembled programs keyed by their source text and the assembler used, so
+/// running a program again, or viewing its disassembly, does not assemble it again.
+///
+/// Object files from the GNU assembler are also written to a directory, if
+/// one is given, so they are reused after a restart. Errors are only kept in memory,
+/// and failing to write an object file is not one, see [`AssemblyCache::take_write_error`].
+#[derive(Debug, Default)]
+pub struct AssemblyCache {
+    /// Most recently used last
+    entries: Vec<Entry>,
+    dir: Option<PathBuf>,
+    /// The last object file that could not be written and why
+    write_error: Option<String>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    key: u64,
+    source: String,
+    assembler: Assembler,
+    program: Result<(Memory, DebugInfo), String>,
+}
+
+impl AssemblyCache {
+    /// A cache that also stores object files in `dir`, created when first written to
+    pub fn new(dir: Option<PathBuf>) -> AssemblyCache {
+        AssemblyCache {
+            entries: Vec::new(),
+            dir,
+            write_error: None,
+        }
+    }
+
+    /// `komodo` in the user's cache directory, `$XDG_CACHE_HOME` or `~/.cache` on Linux
+    pub fn user_dir() -> Option<PathBuf> {
+        let home = || env::var_os("HOME").map(PathBuf::from);
+        let base = if cfg!(target_os = "macos") {
+            home().map(|home| home.join("Library").join("Caches"))
+        } else if cfg!(windows) {
+            env::var_os("LOCALAPPDATA").map(PathBuf::from)
+        } else {
+            env::var_os("XDG_CACHE_HOME")
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from)
+                .or_else(|| home().map(|home| home.join(".cache")))
+        };
+        base.map(|dir| dir.join("komodo"))
+    }
+
+    /// Assembles `source`, or returns the result of assembling it before
+    pub fn assemble(
+        &mut self,
+        source: &str,
+        assembler: &Assembler,
+    ) -> Result<(Memory, DebugInfo), String> {
+        let key = cache_key(source, assembler);
+        let hit = self.entries.iter().position(|entry| {
+            entry.key == key && entry.source == source && entry.assembler == *assembler
+        });
+
+        let entry = match hit {
+            Some(index) => self.entries.remove(index),
+            None => {
+                if self.entries.len() == CAPACITY {
+                    self.entries.remove(0);
+                }
+                Entry {
+                    key,
+                    source: source.to_string(),
+                    assembler: assembler.clone(),
+                    program: self.build(key, source, assembler),
+                }
+            }
+        };
+
+        let program = entry.program.clone();
+        self.entries.push(entry);
+        program
+    }
+
+    /// Number of programs kept in memory
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Why the last object file could not be written to the directory, if one
+    /// could not since the last call
+    pub fn take_write_error(&mut self) -> Option<String> {
+        self.write_error.take()
+    }
+
+    fn build(
+        &mut self,
+        key: u64,
+        source: &str,
+        assembler: &Assembler,
+    ) -> Result<(Memory, DebugInfo), String> {
+        let toolchain = match assembler {
+            Assembler::Builtin => return assembler::assemble(source, SOURCE_NAME),
+            Assembler::Gnu(toolchain) => toolchain,
+        };
+
+        let path = self
+            .dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{:016x}.o", key)));
+        // an unreadable or corrupt object file is assembled again
+        if let Some(program) = path
+            .as_ref()
+            .and_then(|path| fs::read(path).ok())
+            .and_then(|buf| load_object(&buf).ok())
+        {
+            return Ok(program);
+        }
+
+        let buf = assemble_source(toolchain, source)?;
+        let program = load_object(&buf)?;
+        if let (Some(dir), Some(path)) = (&self.dir, &path) {
+            // the disk cache is an optimisation, failing to write it is not an error
+            if let Err(e) = write_object(dir, path, &buf) {
+                self.write_error = Some(format!("Cannot write {}: {}", path.display(), e));
+            }
+        }
+        Ok(program)
+    }
+}
+
+fn assemble_source(toolchain: &Toolchain, source: &str) -> Result<Vec<u8>, String> {
+    let mut input_file = NamedTempFile::new().map_err(|e| e.to_string())?;
+    write!(input_file, "{}", source).map_err(|e| e.to_string())?;
+    gnu_object(toolchain, input_file.path().as_os_str().to_owned())
+}
+
+/// Writes to a temporary file first so other instances never read a partial object
+fn write_object(dir: &Path, path: &Path, buf: &[u8]) -> std::io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let mut file = NamedTempFile::new_in(dir)?;
+    file.write_all(buf)?;
+    file.persist(path)?;
+    Ok(())
+}
+
+/// FNV-1a hash of everything that changes the assembled program. Unlike
+/// `DefaultHasher` it is stable between builds, which the disk cache relies on.
+fn cache_key(source: &str, assembler: &Assembler) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut update = |bytes: &[u8]| {
+        for &byte in bytes.iter().chain([&0]) {
+            hash = (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    };
+
+    update(env!("CARGO_PKG_VERSION").as_bytes());
+    update(assembler.name().as_bytes());
+    if let Assembler::Gnu(toolchain) = assembler {
+        update(toolchain.assembler.as_os_str().as_encoded_bytes());
+        // a reinstalled or upgraded assembler can produce different output
+        let modified = fs::metadata(&toolchain.assembler)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |duration| duration.as_nanos());
+        update(&modified.to_le_bytes());
+    }
+    update(source.as_bytes());
+
+    hash
+}
@@ -0,0 +1,156 @@
+use crate::{
+    Registers,
+    decode::{DecodedInsn, Operand2, RegNum, ShiftAmount},
+};
+use std::{
+    fmt,
+    ops::{Add, AddAssign},
+};
+
+const PC: RegNum = 15;
+
+/// Cycles spent executing, following the instruction cycle timings of the
+/// ARM7TDMI, chapter 6 from DDI0210C. With no wait states each is one clock.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Cycles {
+    /// S cycles, memory accesses to the address following the previous one
+    pub sequential: u64,
+    /// N cycles, memory accesses unrelated to the previous one
+    pub non_sequential: u64,
+    /// I cycles, internal operations with no memory access
+    pub internal: u64,
+}
+
+impl Cycles {
+    /// A branch, or taking an exception, which refills the pipeline
+    pub(crate) const BRANCH: Cycles = Cycles::new(2, 1, 0);
+    /// An instruction that is not executed because its condition fails
+    pub(crate) const SKIPPED: Cycles = Cycles::new(1, 0, 0);
+    /// Taking the undefined instruction exception
+    pub(crate) const UNDEFINED: Cycles = Cycles::new(2, 1, 1);
+
+    pub const fn new(sequential: u64, non_sequential: u64, internal: u64) -> Cycles {
+        Cycles {
+            sequential,
+            non_sequential,
+            internal,
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.sequential + self.non_sequential + self.internal
+    }
+
+    /// Cycles taken by `insn`, `regs` are those before it is executed
+    pub(crate) fn of(insn: &DecodedInsn, regs: &Registers) -> Cycles {
+        match *insn {
+            DecodedInsn::DataProcessing {
+                opcode,
+                rd,
+                operand2,
+                ..
+            } => {
+                let shift_by_register = matches!(
+                    operand2,
+                    Operand2::Register {
+                        amount: ShiftAmount::Register(_),
+                        ..
+                    }
+                );
+                let cycles = Cycles::new(1, 0, shift_by_register as u64);
+                match rd == PC && !opcode.is_comparison() {
+                    true => cycles + Cycles::new(1, 1, 0),
+                    false => cycles,
+                }
+            }
+
+            DecodedInsn::Multiply { rs, accumulate, .. } => {
+                let m = multiplier_cycles(regs[rs] as u32, true);
+                Cycles::new(1, 0, m + accumulate.is_some() as u64)
+            }
+            DecodedInsn::MultiplyLong {
+                signed,
+                accumulate,
+                rs,
+                ..
+            } => {
+                let m = multiplier_cycles(regs[rs] as u32, signed);
+                Cycles::new(1, 0, m + 1 + accumulate as u64)
+            }
+
+            DecodedInsn::Transfer { load: true, rd, .. } if rd == PC => Cycles::new(2, 2, 1),
+            DecodedInsn::Transfer { load: true, .. } => Cycles::new(1, 1, 1),
+            DecodedInsn::Transfer { load: false, .. } => Cycles::new(0, 2, 0),
+
+            DecodedInsn::Block {
+                load, registers, ..
+            } => {
+                let n = (registers.count_ones() as u64).max(1);
+                match (load, registers & (1 << PC) != 0) {
+                    (true, true) => Cycles::new(n + 1, 2, 1),
+                    (true, false) => Cycles::new(n, 1, 1),
+                    (false, _) => Cycles::new(n - 1, 2, 0),
+                }
+            }
+
+            DecodedInsn::Swap { .. } => Cycles::new(1, 2, 1),
+
+            DecodedInsn::Branch { .. } | DecodedInsn::BranchExchange { .. } => Cycles::BRANCH,
+            // the Komodo services are called through the SWI vector
+            DecodedInsn::Swi(_) => Cycles::BRANCH,
+
+            DecodedInsn::Mrs { .. } | DecodedInsn::Msr { .. } | DecodedInsn::Adr { .. } => {
+                Cycles::new(1, 0, 0)
+            }
+        }
+    }
+}
+
+/// I cycles of the multiplier, which stops early when the top bytes of the
+/// multiplier `rs` are all zero, or all one for a signed multiply
+fn multiplier_cycles(rs: u32, signed: bool) -> u64 {
+    let fits = |bits: u32| {
+        let top = rs >> bits;
+        top == 0 || (signed && top == u32::MAX >> bits)
+    };
+    if fits(8) {
+        1
+    } else if fits(16) {
+        2
+    } else if fits(24) {
+        3
+    } else {
+        4
+    }
+}
+
+impl Add for Cycles {
+    type Output = Cycles;
+
+    fn add(self, other: Cycles) -> Cycles {
+        Cycles::new(
+            self.sequential + other.sequential,
+            self.non_sequential + other.non_sequential,
+            self.internal + other.internal,
+        )
+    }
+}
+
+impl AddAssign for Cycles {
+    fn add_assign(&mut self, other: Cycles) {
+        *self = *self + other;
+    }
+}
+
+impl fmt::Display for Cycles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} cycles ({} S, {} N, {} I)",
+            self.total(),
+            self.sequential,
+            self.non_sequential,
+            self.internal
+        )
+    }
+}
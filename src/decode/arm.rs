@@ -0,0 +1,311 @@
+use super::{
+    Condition, DecodedInsn, Indexing, Offset, Operand2, RegNum, ShiftAmount, TransferSize,
+};
+use crate::{
+    Fault,
+    alu::{Opcode, Shift},
+};
+
+/// Decodes the ARM instruction `encoding` found at `address`, following the
+/// encoding tables in A3.1, p110 from DDI01001 spec. The condition is returned
+/// even if the instruction cannot be executed, which is only a fault if the
+/// condition passes.
+pub fn decode(encoding: u32, address: u32) -> (Condition, Result<DecodedInsn, Fault>) {
+    match condition(encoding >> 28) {
+        Some(condition) => (condition, decode_unconditional(encoding, address)),
+        // the `nv` space holds ARMv5 instructions such as `blx`
+        None => (
+            Condition::Al,
+            Err(unimplemented("unconditional instruction")),
+        ),
+    }
+}
+
+/// A3.2.1, p112 from DDI01001 spec, `None` for `0b1111`
+pub(super) fn condition(bits: u32) -> Option<Condition> {
+    use Condition::*;
+    Some(match bits {
+        0 => Eq,
+        1 => Ne,
+        2 => CsHs,
+        3 => CcLo,
+        4 => Mi,
+        5 => Pl,
+        6 => Vs,
+        7 => Vc,
+        8 => Hi,
+        9 => Ls,
+        10 => Ge,
+        11 => Lt,
+        12 => Gt,
+        13 => Le,
+        14 => Al,
+        _ => return None,
+    })
+}
+
+fn unimplemented(what: &str) -> Fault {
+    Fault::Unimplemented(what.to_string())
+}
+
+/// Bit `n` of `encoding`
+fn bit(encoding: u32, n: u32) -> bool {
+    encoding & (1 << n) != 0
+}
+
+/// The register number in the four bits starting at bit `n`
+fn reg(encoding: u32, n: u32) -> RegNum {
+    ((encoding >> n) & 0xf) as RegNum
+}
+
+fn decode_unconditional(encoding: u32, address: u32) -> Result<DecodedInsn, Fault> {
+    match (encoding >> 25) & 7 {
+        0b000 if bit(encoding, 7) && bit(encoding, 4) => multiply_or_extra_load_store(encoding),
+        // the comparison opcodes without the S bit encode miscellaneous instructions
+        0b000 | 0b001 if (encoding >> 23) & 3 == 0b10 && !bit(encoding, 20) => {
+            miscellaneous(encoding)
+        }
+        0b000 | 0b001 => Ok(data_processing(encoding)),
+        0b010 => Ok(load_store(
+            encoding,
+            Offset::Immediate((encoding & 0xfff) as i32),
+        )),
+        0b011 if bit(encoding, 4) => Err(unimplemented("undefined instruction")),
+        0b011 => {
+            let (shift, amount) = immediate_shift(encoding);
+            let offset = Offset::Register {
+                rm: reg(encoding, 0),
+                shift,
+                amount,
+                subtract: false,
+            };
+            Ok(load_store(encoding, offset))
+        }
+        0b100 => block(encoding),
+        0b101 => {
+            // signed 24 bit word offset from the instruction after next
+            let offset = ((encoding << 8) as i32 >> 6) as u32;
+            Ok(DecodedInsn::Branch {
+                link: bit(encoding, 24),
+                target: address.wrapping_add(8).wrapping_add(offset),
+            })
+        }
+        0b111 if bit(encoding, 24) => Ok(DecodedInsn::Swi(encoding & 0xff_ffff)),
+        _ => Err(unimplemented("coprocessor instruction")),
+    }
+}
+
+/// A5.1.1, p443 from DDI01001 spec
+fn data_processing(encoding: u32) -> DecodedInsn {
+    use Opcode::*;
+    let opcode = [
+        And, Eor, Sub, Rsb, Add, Adc, Sbc, Rsc, Tst, Teq, Cmp, Cmn, Orr, Mov, Bic, Mvn,
+    ][((encoding >> 21) & 0xf) as usize];
+
+    let operand2 = if bit(encoding, 25) {
+        Operand2::Immediate {
+            immed_8: encoding & 0xff,
+            rotate_imm: (encoding >> 8) & 0xf,
+        }
+    } else if bit(encoding, 4) {
+        Operand2::Register {
+            rm: reg(encoding, 0),
+            shift: [Shift::Lsl, Shift::Lsr, Shift::Asr, Shift::Ror][((encoding >> 5) & 3) as usize],
+            amount: ShiftAmount::Register(reg(encoding, 8)),
+        }
+    } else {
+        let (shift, amount) = immediate_shift(encoding);
+        Operand2::Register {
+            rm: reg(encoding, 0),
+            shift,
+            amount: ShiftAmount::Immediate(amount),
+        }
+    };
+
+    DecodedInsn::DataProcessing {
+        opcode,
+        set_flags: bit(encoding, 20),
+        // comparisons have no destination, the field should be zero
+        rd: if opcode.is_comparison() {
+            0
+        } else {
+            reg(encoding, 12)
+        },
+        rn: reg(encoding, 16),
+        operand2,
+    }
+}
+
+/// Shift by the five bit immediate at bit 7. An amount of 0 encodes a shift
+/// of 32 for `lsr` and `asr`, and `rrx` instead of `ror`, A5.1.4, p447
+fn immediate_shift(encoding: u32) -> (Shift, u32) {
+    let amount = (encoding >> 7) & 0x1f;
+    match ((encoding >> 5) & 3, amount) {
+        (0b00, _) => (Shift::Lsl, amount),
+        (0b01, 0) => (Shift::Lsr, 32),
+        (0b01, _) => (Shift::Lsr, amount),
+        (0b10, 0) => (Shift::Asr, 32),
+        (0b10, _) => (Shift::Asr, amount),
+        (_, 0) => (Shift::Rrx, 1),
+        _ => (Shift::Ror, amount),
+    }
+}
+
+/// `mrs`, `msr` and the ARMv5 additions that share their encoding space, A3.16, p145
+fn miscellaneous(encoding: u32) -> Result<DecodedInsn, Fault> {
+    let msr = bit(encoding, 21) && (bit(encoding, 25) || (encoding >> 4) & 0xf == 0);
+    if encoding & 0x0fbf_0fff == 0x010f_0000 {
+        Ok(DecodedInsn::Mrs {
+            rd: reg(encoding, 12),
+            spsr: bit(encoding, 22),
+        })
+    } else if msr {
+        let operand2 = match bit(encoding, 25) {
+            true => Operand2::Immediate {
+                immed_8: encoding & 0xff,
+                rotate_imm: (encoding >> 8) & 0xf,
+            },
+            false => Operand2::Register {
+                rm: reg(encoding, 0),
+                shift: Shift::Lsl,
+                amount: ShiftAmount::Immediate(0),
+            },
+        };
+        Ok(DecodedInsn::Msr {
+            spsr: bit(encoding, 22),
+            fields: (encoding >> 16) & 0xf,
+            operand2,
+        })
+    } else if encoding & 0x0fff_fff0 == 0x012f_ff10 {
+        Ok(DecodedInsn::BranchExchange {
+            rm: reg(encoding, 0),
+        })
+    } else {
+        Err(unimplemented("undefined instruction"))
+    }
+}
+
+/// Multiplies, swaps and the halfword and signed byte transfers, A3.1, p110
+fn multiply_or_extra_load_store(encoding: u32) -> Result<DecodedInsn, Fault> {
+    let set_flags = bit(encoding, 20);
+    if (encoding >> 5) & 3 != 0 {
+        return extra_load_store(encoding);
+    }
+
+    match (encoding >> 23) & 3 {
+        0b00 if !bit(encoding, 22) => Ok(DecodedInsn::Multiply {
+            set_flags,
+            rd: reg(encoding, 16),
+            rm: reg(encoding, 0),
+            rs: reg(encoding, 8),
+            accumulate: bit(encoding, 21).then(|| reg(encoding, 12)),
+        }),
+        0b01 => Ok(DecodedInsn::MultiplyLong {
+            set_flags,
+            signed: bit(encoding, 22),
+            accumulate: bit(encoding, 21),
+            rd_lo: reg(encoding, 12),
+            rd_hi: reg(encoding, 16),
+            rm: reg(encoding, 0),
+            rs: reg(encoding, 8),
+        }),
+        0b10 if (encoding >> 20) & 3 == 0 => Ok(DecodedInsn::Swap {
+            byte: bit(encoding, 22),
+            rd: reg(encoding, 12),
+            rm: reg(encoding, 0),
+            rn: reg(encoding, 16),
+        }),
+        _ => Err(unimplemented("undefined instruction")),
+    }
+}
+
+/// `ldrh`, `strh`, `ldrsb` and `ldrsh`, A5.3, p473
+fn extra_load_store(encoding: u32) -> Result<DecodedInsn, Fault> {
+    let load = bit(encoding, 20);
+    let size = match ((encoding >> 5) & 3, load) {
+        (0b01, _) => TransferSize::Halfword,
+        (0b10, true) => TransferSize::SignedByte,
+        (0b11, true) => TransferSize::SignedHalfword,
+        // `ldrd` and `strd` were added in ARMv5TE
+        _ => return Err(unimplemented("instruction `ldrd` or `strd`")),
+    };
+
+    let subtract = !bit(encoding, 23);
+    let offset = if bit(encoding, 22) {
+        let offset = (((encoding >> 4) & 0xf0) | (encoding & 0xf)) as i32;
+        Offset::Immediate(if subtract { -offset } else { offset })
+    } else {
+        Offset::Register {
+            rm: reg(encoding, 0),
+            shift: Shift::Lsl,
+            amount: 0,
+            subtract,
+        }
+    };
+
+    Ok(DecodedInsn::Transfer {
+        load,
+        size,
+        rd: reg(encoding, 12),
+        rn: reg(encoding, 16),
+        offset,
+        indexing: indexing(encoding),
+    })
+}
+
+/// `ldr`, `str`, `ldrb` and `strb`, `offset` is added to the base unless the U bit is clear
+fn load_store(encoding: u32, offset: Offset) -> DecodedInsn {
+    let subtract = !bit(encoding, 23);
+    let offset = match offset {
+        Offset::Immediate(n) if subtract => Offset::Immediate(-n),
+        Offset::Register {
+            rm, shift, amount, ..
+        } => Offset::Register {
+            rm,
+            shift,
+            amount,
+            subtract,
+        },
+        offset => offset,
+    };
+
+    DecodedInsn::Transfer {
+        load: bit(encoding, 20),
+        size: match bit(encoding, 22) {
+            true => TransferSize::Byte,
+            false => TransferSize::Word,
+        },
+        rd: reg(encoding, 12),
+        rn: reg(encoding, 16),
+        offset,
+        // post-indexed with W set is `ldrt` and friends, the user mode access
+        // is the same as any other in the emulator
+        indexing: indexing(encoding),
+    }
+}
+
+/// P and W bits of a single data transfer
+fn indexing(encoding: u32) -> Indexing {
+    match (bit(encoding, 24), bit(encoding, 21)) {
+        (false, _) => Indexing::PostIndexed,
+        (true, false) => Indexing::Offset,
+        (true, true) => Indexing::PreIndexed,
+    }
+}
+
+/// `ldm` and `stm`, A5.4, p481
+fn block(encoding: u32) -> Result<DecodedInsn, Fault> {
+    // the S bit transfers user mode registers, or restores the CPSR
+    if bit(encoding, 22) {
+        return Err(unimplemented("block transfer with `^`"));
+    }
+
+    Ok(DecodedInsn::Block {
+        load: bit(encoding, 20),
+        increment: bit(encoding, 23),
+        before: bit(encoding, 24),
+        writeback: bit(encoding, 21),
+        rn: reg(encoding, 16),
+        registers: encoding as u16,
+    })
+}
@@ -0,0 +1,452 @@
+use super::{
+    Condition, DecodedInsn, Indexing, Offset, Operand2, RegNum, ShiftAmount, TransferSize,
+};
+use crate::{
+    Fault,
+    alu::{Opcode, Shift},
+    registers,
+};
+use capstone::{
+    Capstone, Insn, RegId,
+    arch::{
+        ArchDetail, ArchOperand,
+        arm::{
+            ArmOperand,
+            ArmOperandType::{Imm, Mem, Reg},
+            ArmReg::{ARM_REG_APSR, ARM_REG_SP, ARM_REG_SPSR},
+            ArmShift,
+        },
+    },
+};
+
+/// Decodes `insn` with capstone. The condition is returned even if the
+/// instruction cannot be executed, which is only a fault if the condition passes.
+pub fn from_capstone(cs: &Capstone, insn: &Insn) -> (Condition, Result<DecodedInsn, Fault>) {
+    let mnemonic = insn.mnemonic().unwrap_or_default();
+    match split_mnemonic(mnemonic) {
+        Some((base, set_flags, condition)) => {
+            (condition, decode_operands(cs, insn, base, set_flags))
+        }
+        None => (
+            Condition::Al,
+            Err(Fault::Unimplemented(format!("instruction `{}`", mnemonic))),
+        ),
+    }
+}
+
+/// Splits a mnemonic into the instruction, the `S` suffix and the condition
+// A4.2, p436 from DDI01001 spec
+fn split_mnemonic(mnemonic: &str) -> Option<(&'static str, bool, Condition)> {
+    let instr_s_cond = [
+        "add", "sub", "adc", "sbc", "and", "bic", "eor", "mla", "mov", "mul", "mvn", "orr", "rsb",
+        "rsc", "smlal", "smull", "umlal", "umull", "lsl", "lsr", "asr", "ror", "rrx",
+    ];
+
+    let instr_cond = [
+        "bl", "bx", "b", "cmn", "cmp", "ldmia", "ldmib", "ldmda", "ldmdb", "ldmfd", "ldmed",
+        "ldmfa", "ldmea", "ldm", "ldrbt", "ldrb", "ldrh", "ldrsb", "ldrsh", "ldrt", "ldr", "mrs",
+        "msr", "pop", "push", "stmia", "stmib", "stmda", "stmdb", "stmfd", "stmed", "stmfa",
+        "stmea", "stm", "strbt", "strb", "strh", "strt", "str", "svc", "swpb", "swp", "teq", "tst",
+    ];
+
+    let candidates = instr_s_cond
+        .iter()
+        .map(|instr| (instr, true))
+        .chain(instr_cond.iter().map(|instr| (instr, false)));
+
+    // A prefix only matches if the remainder is a valid suffix, this stops
+    // `blt` being read as `bl` + `t` and `ldrsb` as `ldr` + `sb`
+    for (instr, takes_s) in candidates {
+        let Some(rest) = mnemonic.strip_prefix(instr) else {
+            continue;
+        };
+        let is_s = takes_s && rest.starts_with("s");
+        let condition_str = if is_s { &rest[1..] } else { rest };
+
+        if let Ok(condition) = Condition::try_from(condition_str) {
+            return Some((instr, is_s, condition));
+        }
+    }
+    None
+}
+
+/// The operand shapes capstone gives for each instruction
+enum Operand {
+    Reg(RegNum),
+    /// A register with a shift, `r1, lsl #2`
+    Shifted(RegNum, Shift, ShiftAmount),
+    Imm(i32),
+    /// `[Rn]`, `[Rn, #offset]` or `[Rn, Rm, shift]`
+    Mem(RegNum, Offset),
+    /// `apsr`, or `spsr` if `true`
+    StatusRegister(bool),
+}
+
+fn decode_operands(
+    cs: &Capstone,
+    insn: &Insn,
+    mnemonic: &str,
+    set_flags: bool,
+) -> Result<DecodedInsn, Fault> {
+    let detail = cs.insn_detail(insn).unwrap();
+    let arch_detail: ArchDetail = detail.arch_detail();
+    let arm = arch_detail.arm().unwrap();
+    let writeback = arm.writeback();
+    // `insn` must come from capstone's ARM mode, Thumb instructions are two bytes
+    let Ok(bytes) = insn.bytes().try_into() else {
+        return Err(Fault::Unimplemented(format!("instruction `{}`", mnemonic)));
+    };
+    let encoding = u32::from_le_bytes(bytes);
+
+    let arch_ops = arch_detail.operands();
+    let ops = arch_ops
+        .iter()
+        .map(|op| operand(cs, arm_operand(op)))
+        .collect::<Result<Vec<Operand>, Fault>>()?;
+
+    let unimplemented = || Fault::Unimplemented(format!("instruction `{}`", mnemonic));
+    // shifted registers are only valid as the last operand
+    let reg = |op: &Operand| match op {
+        Operand::Reg(reg) => Ok(*reg),
+        _ => Err(unimplemented()),
+    };
+    let operand2 = |op: &Operand| match *op {
+        Operand::Reg(rm) => Ok(Operand2::Register {
+            rm,
+            shift: Shift::Lsl,
+            amount: ShiftAmount::Immediate(0),
+        }),
+        Operand::Shifted(rm, shift, amount) => Ok(Operand2::Register { rm, shift, amount }),
+        // capstone gives the rotated value, the carry depends on the encoded rotation
+        Operand::Imm(_) => Ok(Operand2::Immediate {
+            immed_8: encoding & 0xff,
+            rotate_imm: (encoding >> 8) & 0xf,
+        }),
+        _ => Err(unimplemented()),
+    };
+
+    use DecodedInsn::*;
+    Ok(match (mnemonic, ops.as_slice()) {
+        (
+            "and" | "eor" | "sub" | "rsb" | "add" | "adc" | "sbc" | "rsc" | "orr" | "bic",
+            [rd, rn, shifter],
+        ) => DataProcessing {
+            opcode: Opcode::try_from(mnemonic).unwrap(),
+            set_flags,
+            rd: reg(rd)?,
+            rn: reg(rn)?,
+            operand2: operand2(shifter)?,
+        },
+
+        ("mov" | "mvn", [rd, shifter]) => DataProcessing {
+            opcode: Opcode::try_from(mnemonic).unwrap(),
+            set_flags,
+            rd: reg(rd)?,
+            rn: 0,
+            operand2: operand2(shifter)?,
+        },
+
+        ("tst" | "teq" | "cmp" | "cmn", [rn, shifter]) => DataProcessing {
+            opcode: Opcode::try_from(mnemonic).unwrap(),
+            set_flags: true,
+            rd: 0,
+            rn: reg(rn)?,
+            operand2: operand2(shifter)?,
+        },
+
+        // `lsl`, `lsr`, `asr`, `ror` and `rrx` are aliases of `mov` with a shifted register
+        ("lsl" | "lsr" | "asr" | "ror" | "rrx", [rd, rm]) => {
+            let operand2 = match (mnemonic, rm) {
+                ("rrx", Operand::Reg(rm)) => Operand2::Register {
+                    rm: *rm,
+                    shift: Shift::Rrx,
+                    amount: ShiftAmount::Immediate(1),
+                },
+                _ => operand2(rm)?,
+            };
+            DataProcessing {
+                opcode: Opcode::Mov,
+                set_flags,
+                rd: reg(rd)?,
+                rn: 0,
+                operand2,
+            }
+        }
+        ("lsl" | "lsr" | "asr" | "ror", [rd, rm, rs]) => DataProcessing {
+            opcode: Opcode::Mov,
+            set_flags,
+            rd: reg(rd)?,
+            rn: 0,
+            operand2: Operand2::Register {
+                rm: reg(rm)?,
+                shift: match mnemonic {
+                    "lsl" => Shift::Lsl,
+                    "lsr" => Shift::Lsr,
+                    "asr" => Shift::Asr,
+                    _ => Shift::Ror,
+                },
+                amount: ShiftAmount::Register(reg(rs)?),
+            },
+        },
+
+        ("mul", [rd, rm, rs]) => Multiply {
+            set_flags,
+            rd: reg(rd)?,
+            rm: reg(rm)?,
+            rs: reg(rs)?,
+            accumulate: None,
+        },
+        ("mla", [rd, rm, rs, rn]) => Multiply {
+            set_flags,
+            rd: reg(rd)?,
+            rm: reg(rm)?,
+            rs: reg(rs)?,
+            accumulate: Some(reg(rn)?),
+        },
+        ("umull" | "umlal" | "smull" | "smlal", [rd_lo, rd_hi, rm, rs]) => MultiplyLong {
+            set_flags,
+            signed: mnemonic.starts_with('s'),
+            accumulate: mnemonic.ends_with("lal"),
+            rd_lo: reg(rd_lo)?,
+            rd_hi: reg(rd_hi)?,
+            rm: reg(rm)?,
+            rs: reg(rs)?,
+        },
+
+        ("mrs", [rd, Operand::StatusRegister(spsr)]) => Mrs {
+            rd: reg(rd)?,
+            spsr: *spsr,
+        },
+
+        ("svc", [Operand::Imm(n)]) => Swi(*n as u32),
+
+        (
+            "ldr" | "ldrt" | "ldrb" | "ldrbt" | "ldrh" | "ldrsb" | "ldrsh" | "str" | "strt"
+            | "strb" | "strbt" | "strh",
+            [rd, Operand::Mem(rn, offset), rest @ ..],
+        ) => {
+            let (offset, indexing) = match rest {
+                [] if writeback => (*offset, Indexing::PreIndexed),
+                [] => (*offset, Indexing::Offset),
+                // post-indexed, capstone gives the offset as a separate operand
+                [post] => (
+                    post_offset(post, arm_operand(&arch_ops[2]))?,
+                    Indexing::PostIndexed,
+                ),
+                _ => return Err(unimplemented()),
+            };
+            Transfer {
+                load: mnemonic.starts_with("ld"),
+                size: match mnemonic.trim_start_matches("ldr").trim_start_matches("str") {
+                    "" | "t" => TransferSize::Word,
+                    "b" | "bt" => TransferSize::Byte,
+                    "h" => TransferSize::Halfword,
+                    "sb" => TransferSize::SignedByte,
+                    _ => TransferSize::SignedHalfword,
+                },
+                rd: reg(rd)?,
+                rn: *rn,
+                offset,
+                indexing,
+            }
+        }
+
+        ("swp" | "swpb", [rd, rm, Operand::Mem(rn, _)]) => Swap {
+            byte: mnemonic == "swpb",
+            rd: reg(rd)?,
+            rm: reg(rm)?,
+            rn: *rn,
+        },
+
+        // a single register `push` or `pop` is encoded as `str rd, [sp, #-4]!`
+        // or `ldr rd, [sp], #4`
+        ("push" | "pop", [rd]) if (encoding >> 26) & 3 == 0b01 => {
+            let push = mnemonic == "push";
+            Transfer {
+                load: !push,
+                size: TransferSize::Word,
+                rd: reg(rd)?,
+                rn: registers::reg_number(&RegId(ARM_REG_SP as u16)),
+                offset: Offset::Immediate(if push { -4 } else { 4 }),
+                indexing: match push {
+                    true => Indexing::PreIndexed,
+                    false => Indexing::PostIndexed,
+                },
+            }
+        }
+
+        (
+            "push" | "pop" | "ldm" | "ldmia" | "ldmib" | "ldmda" | "ldmdb" | "ldmfd" | "ldmed"
+            | "ldmfa" | "ldmea" | "stm" | "stmia" | "stmib" | "stmda" | "stmdb" | "stmfd" | "stmed"
+            | "stmfa" | "stmea",
+            [Operand::Reg(_), ..],
+        ) => {
+            let regs = ops
+                .iter()
+                .zip(&arch_ops)
+                .map(|(op, arch_op)| match op {
+                    Operand::Reg(reg) => Ok(*reg),
+                    _ => Err(Fault::Unimplemented(format!(
+                        "operand {:?} in register list",
+                        arm_operand(arch_op).op_type
+                    ))),
+                })
+                .collect::<Result<Vec<RegNum>, Fault>>()?;
+
+            let (rn, reg_list, writeback) = match mnemonic {
+                "push" | "pop" => (
+                    registers::reg_number(&RegId(ARM_REG_SP as u16)),
+                    &regs[..],
+                    true,
+                ),
+                _ => (regs[0], &regs[1..], writeback),
+            };
+
+            // full/empty and ascending/descending stacks are aliases for the
+            // increment/decrement before/after modes, depending on load or store
+            let (increment, before) = match mnemonic {
+                "ldm" | "ldmia" | "ldmfd" | "stm" | "stmia" | "stmea" | "pop" => (true, false),
+                "ldmib" | "ldmed" | "stmib" | "stmfa" => (true, true),
+                "ldmda" | "ldmfa" | "stmda" | "stmed" => (false, false),
+                _ => (false, true),
+            };
+
+            Block {
+                load: matches!(&mnemonic[..2], "ld" | "po"),
+                increment,
+                before,
+                writeback,
+                rn,
+                registers: reg_list.iter().fold(0, |bits, reg| bits | 1 << reg),
+            }
+        }
+
+        ("bx", [rm]) => BranchExchange { rm: reg(rm)? },
+
+        ("b", [Operand::Imm(n)]) => Branch {
+            link: false,
+            target: *n as u32,
+        },
+        ("bl", [Operand::Imm(n)]) => Branch {
+            link: true,
+            target: *n as u32,
+        },
+
+        _ => return Err(unimplemented()),
+    })
+}
+
+fn arm_operand(op: &ArchOperand) -> &ArmOperand {
+    match op {
+        ArchOperand::ArmOperand(arm_op) => arm_op,
+        _ => panic!("Expected ARM operand"),
+    }
+}
+
+/// Architectural number of a general purpose register
+fn reg_num(cs: &Capstone, reg_id: RegId) -> Result<RegNum, Fault> {
+    match registers::is_mapped(&reg_id) && !is_status_register(reg_id) {
+        true => Ok(registers::reg_number(&reg_id)),
+        false => Err(Fault::Unimplemented(format!(
+            "register `{}`",
+            cs.reg_name(reg_id).unwrap_or_default()
+        ))),
+    }
+}
+
+fn is_status_register(reg_id: RegId) -> bool {
+    matches!(reg_id.0 as u32, ARM_REG_APSR | ARM_REG_SPSR)
+}
+
+fn operand(cs: &Capstone, op: &ArmOperand) -> Result<Operand, Fault> {
+    Ok(match op.op_type {
+        Reg(reg_id) if is_status_register(reg_id) => {
+            Operand::StatusRegister(reg_id.0 as u32 == ARM_REG_SPSR)
+        }
+        Reg(reg_id) => match shift(&op.shift) {
+            None => Operand::Reg(reg_num(cs, reg_id)?),
+            Some((shift, ArmShiftAmount::Immediate(amount))) => {
+                Operand::Shifted(reg_num(cs, reg_id)?, shift, ShiftAmount::Immediate(amount))
+            }
+            Some((shift, ArmShiftAmount::Register(rs))) => Operand::Shifted(
+                reg_num(cs, reg_id)?,
+                shift,
+                ShiftAmount::Register(reg_num(cs, rs)?),
+            ),
+        },
+        Imm(n) => Operand::Imm(n),
+        Mem(addressing_mode) => {
+            let rn = reg_num(cs, addressing_mode.base())?;
+            let offset = if addressing_mode.index().0 != 0 {
+                let (shift, amount) = match shift(&op.shift) {
+                    None => (Shift::Lsl, 0),
+                    Some((shift, ArmShiftAmount::Immediate(amount))) => (shift, amount),
+                    Some((_, ArmShiftAmount::Register(_))) => {
+                        return Err(Fault::Unimplemented(format!(
+                            "addressing mode {:?}",
+                            op.op_type
+                        )));
+                    }
+                };
+                Offset::Register {
+                    rm: reg_num(cs, addressing_mode.index())?,
+                    shift,
+                    amount,
+                    subtract: op.subtracted || addressing_mode.scale() == -1,
+                }
+            } else {
+                Offset::Immediate(addressing_mode.disp())
+            };
+            Operand::Mem(rn, offset)
+        }
+        _ => {
+            return Err(Fault::Unimplemented(format!("operand {:?}", op.op_type)));
+        }
+    })
+}
+
+/// Offset operand of a post-indexed transfer, `[Rn], #-4` or `[Rn], -Rm, lsl #2`
+fn post_offset(op: &Operand, arm_op: &ArmOperand) -> Result<Offset, Fault> {
+    match *op {
+        Operand::Imm(n) => Ok(Offset::Immediate(match arm_op.subtracted {
+            true => -n,
+            false => n,
+        })),
+        Operand::Reg(rm) => Ok(Offset::Register {
+            rm,
+            shift: Shift::Lsl,
+            amount: 0,
+            subtract: arm_op.subtracted,
+        }),
+        Operand::Shifted(rm, shift, ShiftAmount::Immediate(amount)) => Ok(Offset::Register {
+            rm,
+            shift,
+            amount,
+            subtract: arm_op.subtracted,
+        }),
+        _ => Err(Fault::Unimplemented(format!("offset {:?}", arm_op.op_type))),
+    }
+}
+
+enum ArmShiftAmount {
+    Immediate(u32),
+    Register(RegId),
+}
+
+/// The shift capstone gives for a register operand, `None` if it is unshifted
+fn shift(shift: &ArmShift) -> Option<(Shift, ArmShiftAmount)> {
+    use ArmShift::*;
+    use ArmShiftAmount::{Immediate, Register};
+    Some(match *shift {
+        Lsl(s) => (Shift::Lsl, Immediate(s)),
+        Lsr(s) => (Shift::Lsr, Immediate(s)),
+        Asr(s) => (Shift::Asr, Immediate(s)),
+        Ror(s) => (Shift::Ror, Immediate(s)),
+        LslReg(reg) => (Shift::Lsl, Register(reg)),
+        LsrReg(reg) => (Shift::Lsr, Register(reg)),
+        AsrReg(reg) => (Shift::Asr, Register(reg)),
+        RorReg(reg) => (Shift::Ror, Register(reg)),
+        Rrx(_) | RrxReg(_) => (Shift::Rrx, Immediate(1)),
+        Invalid => return None,
+    })
+}
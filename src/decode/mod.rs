@@ -0,0 +1,238 @@
+mod arm;
+mod from_capstone;
+mod thumb;
+
+use crate::{
+    alu::{Opcode, Shift},
+    status_flags::StatusFlags,
+};
+pub use arm::decode;
+pub use from_capstone::from_capstone;
+pub use thumb::{decode_thumb, thumb_size};
+
+/// Architectural register number, `r0` is 0 and `pc` is 15
+pub type RegNum = u16;
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    /// Equal
+    Eq,
+    /// Not equal
+    Ne,
+    /// Carry set / Unsigned higher or same
+    CsHs,
+    /// Carry clear / Unsigned lower
+    CcLo,
+    /// Minus
+    Mi,
+    /// Plus
+    Pl,
+    /// Overflow
+    Vs,
+    /// No overflow
+    Vc,
+    /// Unsigned higher
+    Hi,
+    /// Unsigned lower or same
+    Ls,
+    /// Signed greater than or equal
+    Ge,
+    /// Signed less than
+    Lt,
+    /// Signed greater than
+    Gt,
+    /// Signed less than or equal
+    Le,
+    /// Always
+    #[default]
+    Al,
+}
+
+impl TryFrom<&str> for Condition {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        use Condition::*;
+        Ok(match value.to_ascii_lowercase().as_str() {
+            "eq" => Eq,
+            "ne" => Ne,
+            "cs" | "hs" => CsHs,
+            "cc" | "lo" => CcLo,
+            "mi" => Mi,
+            "pl" => Pl,
+            "vs" => Vs,
+            "vc" => Vc,
+            "hi" => Hi,
+            "ls" => Ls,
+            "ge" => Ge,
+            "lt" => Lt,
+            "gt" => Gt,
+            "le" => Le,
+            "" | "al" => Al,
+            _ => return Err(format!("Unrecognised condition {}", value)),
+        })
+    }
+}
+
+impl Condition {
+    /// `true` if an instruction with this condition executes, A3.2.1, p112 from DDI01001 spec
+    pub fn passes(self, flags: &StatusFlags) -> bool {
+        use Condition::*;
+        match self {
+            Eq => flags.zero,
+            Ne => !flags.zero,
+            CsHs => flags.carry,
+            CcLo => !flags.carry,
+            Mi => flags.negative,
+            Pl => !flags.negative,
+            Vs => flags.overflow,
+            Vc => !flags.overflow,
+            Hi => flags.carry && !flags.zero,
+            Ls => !flags.carry || flags.zero,
+            Ge => flags.negative == flags.overflow,
+            Lt => flags.negative != flags.overflow,
+            Gt => !flags.zero && flags.negative == flags.overflow,
+            Le => flags.zero || flags.negative != flags.overflow,
+            Al => true,
+        }
+    }
+}
+
+/// Shift applied to a register operand by the barrel shifter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShiftAmount {
+    Immediate(u32),
+    /// The bottom byte of a register
+    Register(RegNum),
+}
+
+/// Second operand of a data processing instruction, A5.1 from DDI01001 spec
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand2 {
+    /// `immed_8` rotated right by twice `rotate_imm`, the rotation decides the carry out
+    Immediate { immed_8: u32, rotate_imm: u32 },
+    Register {
+        rm: RegNum,
+        shift: Shift,
+        amount: ShiftAmount,
+    },
+}
+
+/// Offset added to the base register of a single data transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Offset {
+    Immediate(i32),
+    /// `rm` shifted by an immediate, subtracted from the base if `subtract`
+    Register {
+        rm: RegNum,
+        shift: Shift,
+        amount: u32,
+        subtract: bool,
+    },
+}
+
+/// How the address of a single data transfer is formed, A5.2 and A5.3 from DDI01001 spec
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Indexing {
+    /// `[Rn, offset]`
+    Offset,
+    /// `[Rn, offset]!`, the address is written back to the base
+    PreIndexed,
+    /// `[Rn], offset`, the base is the address and base plus offset is written back
+    PostIndexed,
+}
+
+/// Width of a single data transfer and whether loads sign extend
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferSize {
+    Word,
+    Byte,
+    Halfword,
+    SignedByte,
+    SignedHalfword,
+}
+
+/// A typed instruction, from its ARM encoding with [`decode`], its Thumb
+/// encoding with [`decode_thumb`] or capstone's disassembly with [`from_capstone`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodedInsn {
+    /// `rn` is ignored by `mov` and `mvn`, `rd` by comparisons
+    DataProcessing {
+        opcode: Opcode,
+        set_flags: bool,
+        rd: RegNum,
+        rn: RegNum,
+        operand2: Operand2,
+    },
+    /// `mul`, or `mla` when there is an `accumulate` register
+    Multiply {
+        set_flags: bool,
+        rd: RegNum,
+        rm: RegNum,
+        rs: RegNum,
+        accumulate: Option<RegNum>,
+    },
+    /// `umull`, `umlal`, `smull` and `smlal`
+    MultiplyLong {
+        set_flags: bool,
+        signed: bool,
+        accumulate: bool,
+        rd_lo: RegNum,
+        rd_hi: RegNum,
+        rm: RegNum,
+        rs: RegNum,
+    },
+    /// `ldr` and `str` with their byte, halfword and signed variants
+    Transfer {
+        load: bool,
+        size: TransferSize,
+        rd: RegNum,
+        rn: RegNum,
+        offset: Offset,
+        indexing: Indexing,
+    },
+    /// `ldm`, `stm`, `push` and `pop`, `registers` has bit n set for register n
+    Block {
+        load: bool,
+        increment: bool,
+        before: bool,
+        writeback: bool,
+        rn: RegNum,
+        registers: u16,
+    },
+    /// `swp` and `swpb`
+    Swap {
+        byte: bool,
+        rd: RegNum,
+        rm: RegNum,
+        rn: RegNum,
+    },
+    /// `b` and `bl`, including the Thumb `bl` pair
+    Branch {
+        link: bool,
+        target: u32,
+    },
+    /// `bx`, bit 0 of `rm` selects Thumb state
+    BranchExchange {
+        rm: RegNum,
+    },
+    /// Thumb `add rd, pc, #imm`, `pc` is word aligned first so the result only
+    /// depends on where the instruction is
+    Adr {
+        rd: RegNum,
+        address: u32,
+    },
+    /// Reads the CPSR, or the SPSR of the current mode
+    Mrs {
+        rd: RegNum,
+        spsr: bool,
+    },
+    /// Writes the bytes of a status register selected by `fields`, bit 0 is the
+    /// control byte `c` and bit 3 the flags byte `f`
+    Msr {
+        spsr: bool,
+        fields: u32,
+        operand2: Operand2,
+    },
+    Swi(u32),
+}
@@ -0,0 +1,332 @@
+use super::{
+    Condition, DecodedInsn, Indexing, Offset, Operand2, RegNum, ShiftAmount, TransferSize,
+};
+use crate::{
+    Fault,
+    alu::{Opcode, Shift},
+};
+
+const SP: RegNum = 13;
+const LR: RegNum = 14;
+const PC: RegNum = 15;
+
+/// Decodes the Thumb instruction at `address`, following the encoding tables
+/// in A6.2, p497 from DDI01001 spec. `encoding` holds the instruction in its
+/// bottom halfword and the halfword after it in the top, the second half of a
+/// `bl` pair. Only conditional branches have a condition other than `al`.
+pub fn decode_thumb(encoding: u32, address: u32) -> (Condition, Result<DecodedInsn, Fault>) {
+    let h = encoding & 0xffff;
+    if h >> 12 == 0b1101 && (h >> 8) & 0xf < 0b1110 {
+        let condition = super::arm::condition((h >> 8) & 0xf).unwrap();
+        // signed 8 bit halfword offset from the instruction after next
+        let offset = (((h << 24) as i32) >> 23) as u32;
+        let target = address.wrapping_add(4).wrapping_add(offset);
+        return (
+            condition,
+            Ok(DecodedInsn::Branch {
+                link: false,
+                target,
+            }),
+        );
+    }
+    (Condition::Al, decode_unconditional(encoding, address))
+}
+
+/// Bytes taken by the Thumb instruction in the bottom halfword of `encoding`,
+/// 4 for a `bl` pair whose second half is in the top halfword
+pub fn thumb_size(encoding: u32) -> u32 {
+    match (encoding >> 11) & 0x1f == 0b11110 && encoding >> 27 == 0b11111 {
+        true => 4,
+        false => 2,
+    }
+}
+
+fn unimplemented(what: &str) -> Fault {
+    Fault::Unimplemented(what.to_string())
+}
+
+fn bit(h: u32, n: u32) -> bool {
+    h & (1 << n) != 0
+}
+
+/// The low register number in the three bits starting at bit `n`
+fn low_reg(h: u32, n: u32) -> RegNum {
+    ((h >> n) & 7) as RegNum
+}
+
+fn register(rm: RegNum) -> Operand2 {
+    Operand2::Register {
+        rm,
+        shift: Shift::Lsl,
+        amount: ShiftAmount::Immediate(0),
+    }
+}
+
+/// An immediate that needs no rotation
+fn immediate(value: u32) -> Operand2 {
+    Operand2::Immediate {
+        immed_8: value,
+        rotate_imm: 0,
+    }
+}
+
+/// `value` shifted left by 2, as a rotation right by 30
+fn word_immediate(value: u32) -> Operand2 {
+    Operand2::Immediate {
+        immed_8: value,
+        rotate_imm: 15,
+    }
+}
+
+fn data_processing(
+    opcode: Opcode,
+    set_flags: bool,
+    rd: RegNum,
+    rn: RegNum,
+    operand2: Operand2,
+) -> DecodedInsn {
+    DecodedInsn::DataProcessing {
+        opcode,
+        set_flags,
+        rd: if opcode.is_comparison() { 0 } else { rd },
+        rn,
+        operand2,
+    }
+}
+
+fn transfer(load: bool, size: TransferSize, rd: RegNum, rn: RegNum, offset: Offset) -> DecodedInsn {
+    DecodedInsn::Transfer {
+        load,
+        size,
+        rd,
+        rn,
+        offset,
+        indexing: Indexing::Offset,
+    }
+}
+
+fn decode_unconditional(encoding: u32, address: u32) -> Result<DecodedInsn, Fault> {
+    let h = encoding & 0xffff;
+    let rd = low_reg(h, 0);
+    let rs = low_reg(h, 3);
+
+    Ok(match h >> 11 {
+        // shift by immediate, `lsl rd, rm, #imm`
+        0b00000..=0b00010 => {
+            let amount = (h >> 6) & 0x1f;
+            let (shift, amount) = match (h >> 11, amount) {
+                (0b00, _) => (Shift::Lsl, amount),
+                (0b01, 0) => (Shift::Lsr, 32),
+                (0b01, _) => (Shift::Lsr, amount),
+                (_, 0) => (Shift::Asr, 32),
+                _ => (Shift::Asr, amount),
+            };
+            let operand2 = Operand2::Register {
+                rm: rs,
+                shift,
+                amount: ShiftAmount::Immediate(amount),
+            };
+            data_processing(Opcode::Mov, true, rd, 0, operand2)
+        }
+
+        // `add rd, rn, rm` and `sub rd, rn, #imm3`
+        0b00011 => {
+            let opcode = match bit(h, 9) {
+                true => Opcode::Sub,
+                false => Opcode::Add,
+            };
+            let operand2 = match bit(h, 10) {
+                true => immediate((h >> 6) & 7),
+                false => register(low_reg(h, 6)),
+            };
+            data_processing(opcode, true, rd, rs, operand2)
+        }
+
+        // `mov`, `cmp`, `add` and `sub` with an 8 bit immediate
+        0b00100..=0b00111 => {
+            let rd = low_reg(h, 8);
+            let opcode =
+                [Opcode::Mov, Opcode::Cmp, Opcode::Add, Opcode::Sub][(h >> 11) as usize & 3];
+            data_processing(opcode, true, rd, rd, immediate(h & 0xff))
+        }
+
+        0b01000 if !bit(h, 10) => alu_operation(h),
+        0b01000 => high_register_operation(h)?,
+
+        // `ldr rd, [pc, #imm]`, the base is `pc` rounded down to a word
+        0b01001 => {
+            let base_adjust = address.wrapping_add(4) & 2;
+            let offset = Offset::Immediate(((h & 0xff) * 4) as i32 - base_adjust as i32);
+            transfer(true, TransferSize::Word, low_reg(h, 8), PC, offset)
+        }
+
+        // register offset, `ldr rd, [rn, rm]`
+        0b01010 | 0b01011 => {
+            let (load, size) = match (h >> 9) & 7 {
+                0b000 => (false, TransferSize::Word),
+                0b001 => (false, TransferSize::Halfword),
+                0b010 => (false, TransferSize::Byte),
+                0b011 => (true, TransferSize::SignedByte),
+                0b100 => (true, TransferSize::Word),
+                0b101 => (true, TransferSize::Halfword),
+                0b110 => (true, TransferSize::Byte),
+                _ => (true, TransferSize::SignedHalfword),
+            };
+            let offset = Offset::Register {
+                rm: low_reg(h, 6),
+                shift: Shift::Lsl,
+                amount: 0,
+                subtract: false,
+            };
+            transfer(load, size, rd, rs, offset)
+        }
+
+        // immediate offset, scaled by the size of the transfer
+        0b01100..=0b10001 => {
+            let (size, scale) = match h >> 12 {
+                0b0110 => (TransferSize::Word, 4),
+                0b0111 => (TransferSize::Byte, 1),
+                _ => (TransferSize::Halfword, 2),
+            };
+            let offset = Offset::Immediate((((h >> 6) & 0x1f) * scale) as i32);
+            transfer(bit(h, 11), size, rd, rs, offset)
+        }
+
+        // `ldr rd, [sp, #imm]`
+        0b10010 | 0b10011 => {
+            let offset = Offset::Immediate(((h & 0xff) * 4) as i32);
+            transfer(bit(h, 11), TransferSize::Word, low_reg(h, 8), SP, offset)
+        }
+
+        0b10100 => DecodedInsn::Adr {
+            rd: low_reg(h, 8),
+            address: (address.wrapping_add(4) & !3).wrapping_add((h & 0xff) * 4),
+        },
+        0b10101 => data_processing(
+            Opcode::Add,
+            false,
+            low_reg(h, 8),
+            SP,
+            word_immediate(h & 0xff),
+        ),
+
+        // `add sp, #imm` and `sub sp, #imm`
+        0b10110 | 0b10111 if (h >> 8) & 0xf == 0 => {
+            let opcode = match bit(h, 7) {
+                true => Opcode::Sub,
+                false => Opcode::Add,
+            };
+            data_processing(opcode, false, SP, SP, word_immediate(h & 0x7f))
+        }
+
+        // `push {rlist, lr}` and `pop {rlist, pc}`
+        0b10110 | 0b10111 if (h >> 9) & 3 == 0b10 => {
+            let load = bit(h, 11);
+            let extra = match (bit(h, 8), load) {
+                (false, _) => 0,
+                (true, false) => 1 << LR,
+                (true, true) => 1 << PC,
+            };
+            DecodedInsn::Block {
+                load,
+                increment: load,
+                before: !load,
+                writeback: true,
+                rn: SP,
+                registers: (h & 0xff) as u16 | extra,
+            }
+        }
+
+        // `ldmia rn!, {rlist}` and `stmia rn!, {rlist}`
+        0b11000 | 0b11001 => DecodedInsn::Block {
+            load: bit(h, 11),
+            increment: true,
+            before: false,
+            writeback: true,
+            rn: low_reg(h, 8),
+            registers: (h & 0xff) as u16,
+        },
+
+        0b11011 if (h >> 8) & 0xf == 0xf => DecodedInsn::Swi(h & 0xff),
+
+        0b11100 => {
+            // signed 11 bit halfword offset from the instruction after next
+            let offset = (((h << 21) as i32) >> 20) as u32;
+            DecodedInsn::Branch {
+                link: false,
+                target: address.wrapping_add(4).wrapping_add(offset),
+            }
+        }
+
+        // `bl` is a pair, the first half holds the top of the offset
+        0b11110 if thumb_size(encoding) == 4 => {
+            let high = (((h << 21) as i32) >> 9) as u32;
+            let low = ((encoding >> 16) & 0x7ff) << 1;
+            DecodedInsn::Branch {
+                link: true,
+                target: address.wrapping_add(4).wrapping_add(high).wrapping_add(low),
+            }
+        }
+        0b11110 | 0b11111 => return Err(unimplemented("half of a `bl` pair")),
+
+        _ => return Err(unimplemented("undefined instruction")),
+    })
+}
+
+/// Format 4, the ALU operations on low registers, `op rd, rs`
+fn alu_operation(h: u32) -> DecodedInsn {
+    use Opcode::*;
+    let rd = low_reg(h, 0);
+    let rs = low_reg(h, 3);
+    let shift = |shift| Operand2::Register {
+        rm: rd,
+        shift,
+        amount: ShiftAmount::Register(rs),
+    };
+
+    match (h >> 6) & 0xf {
+        0x2 => data_processing(Mov, true, rd, 0, shift(Shift::Lsl)),
+        0x3 => data_processing(Mov, true, rd, 0, shift(Shift::Lsr)),
+        0x4 => data_processing(Mov, true, rd, 0, shift(Shift::Asr)),
+        0x7 => data_processing(Mov, true, rd, 0, shift(Shift::Ror)),
+        // `neg rd, rs` is `rsbs rd, rs, #0`
+        0x9 => data_processing(Rsb, true, rd, rs, immediate(0)),
+        0xd => DecodedInsn::Multiply {
+            set_flags: true,
+            rd,
+            rm: rs,
+            rs: rd,
+            accumulate: None,
+        },
+        0xf => data_processing(Mvn, true, rd, 0, register(rs)),
+        op => {
+            let opcode = match op {
+                0x0 => And,
+                0x1 => Eor,
+                0x5 => Adc,
+                0x6 => Sbc,
+                0x8 => Tst,
+                0xa => Cmp,
+                0xb => Cmn,
+                0xc => Orr,
+                _ => Bic,
+            };
+            data_processing(opcode, true, rd, rd, register(rs))
+        }
+    }
+}
+
+/// Format 5, `add`, `cmp` and `mov` with high registers, and `bx`
+fn high_register_operation(h: u32) -> Result<DecodedInsn, Fault> {
+    let rd = low_reg(h, 0) | (bit(h, 7) as RegNum) << 3;
+    let rm = ((h >> 3) & 0xf) as RegNum;
+
+    Ok(match (h >> 8) & 3 {
+        0b00 => data_processing(Opcode::Add, false, rd, rd, register(rm)),
+        0b01 => data_processing(Opcode::Cmp, true, rd, rd, register(rm)),
+        0b10 => data_processing(Opcode::Mov, false, rd, 0, register(rm)),
+        // `blx` was added in ARMv5
+        _ if bit(h, 7) => return Err(unimplemented("instruction `blx`")),
+        _ => DecodedInsn::BranchExchange { rm },
+    })
+}
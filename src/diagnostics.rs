@@ -0,0 +1,72 @@
+use std::fmt;
+
+/// How serious a [`Diagnostic`] is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A message the assembler reported against a line of the source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Line number, starting at 1
+    pub line: u32,
+    /// Column, starting at 1, if the assembler reported one
+    pub column: Option<u32>,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "Error"),
+            Severity::Warning => write!(f, "Warning"),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
+
+impl Diagnostic {
+    /// Parses one `file:line: Error: message` line, as printed by GAS and the
+    /// built-in assembler. `file:line:column:` locations are also accepted.
+    pub fn parse(text: &str) -> Option<Diagnostic> {
+        let (location, severity, message) = [
+            (": Error: ", Severity::Error),
+            (": Warning: ", Severity::Warning),
+        ]
+        .into_iter()
+        .find_map(|(tag, severity)| {
+            text.split_once(tag)
+                .map(|(location, message)| (location, severity, message))
+        })?;
+
+        // the file name may itself contain `:`, so the location is read from the right
+        let mut parts = location.rsplitn(3, ':');
+        let last = parts.next()?.parse::<u32>().ok()?;
+        let before = parts.next()?;
+        let (line, column) = match before.parse::<u32>() {
+            Ok(line) if parts.next().is_some() => (line, Some(last)),
+            _ => (last, None),
+        };
+
+        Some(Diagnostic {
+            line,
+            column,
+            severity,
+            message: message.trim_end().to_string(),
+        })
+    }
+}
+
+/// Every diagnostic in the output of an assembler, lines that are not
+/// diagnostics, e.g. `file: Assembler messages:`, are skipped
+pub fn parse_diagnostics(output: &str) -> Vec<Diagnostic> {
+    output.lines().filter_map(Diagnostic::parse).collect()
+}
@@ -0,0 +1,55 @@
+use std::fmt;
+
+/// Why an instruction could not be executed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// The instruction, or one of its operands, is not supported by the emulator
+    Unimplemented(String),
+    /// A halfword access to an odd address, or a branch to a non word aligned address
+    UnalignedAccess { address: u32 },
+    /// An access outside every mapped region
+    OutOfBounds { address: u32 },
+    /// `swi` with a number that has no handler
+    UnknownSwi(u32),
+    /// An access to the SPSR in User or System mode, which have none
+    NoSpsr,
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::Unimplemented(what) => write!(f, "Unimplemented {}", what),
+            Fault::UnalignedAccess { address } => {
+                write!(f, "Unaligned memory access at {:#010x}", address)
+            }
+            Fault::OutOfBounds { address } => {
+                write!(f, "Out of bounds memory access at {:#010x}", address)
+            }
+            Fault::UnknownSwi(n) => write!(f, "Unknown software interrupt `swi {}`", n),
+            Fault::NoSpsr => write!(f, "The current mode has no SPSR"),
+        }
+    }
+}
+
+/// A [`Fault`] along with the instruction that raised it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecError {
+    /// Address of the faulting instruction
+    pub address: u32,
+    /// Disassembly of the faulting instruction, e.g. `ldr r0, [r1]`, empty if
+    /// it could not be fetched
+    pub instruction: String,
+    pub fault: Fault,
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {:#010x}", self.fault, self.address)?;
+        match self.instruction.is_empty() {
+            true => Ok(()),
+            false => write!(f, ": `{}`", self.instruction),
+        }
+    }
+}
+
+impl std::error::Error for ExecError {}
@@ -0,0 +1,65 @@
+use crate::{
+    Registers,
+    status_flags::{ProcessorMode, THUMB_BIT},
+};
+
+/// The I bit of the CPSR, IRQs are not taken while it is set
+pub(crate) const IRQ_DISABLE: i32 = 1 << 7;
+/// The F bit of the CPSR, FIQs are not taken while it is set
+pub(crate) const FIQ_DISABLE: i32 = 1 << 6;
+
+/// The exceptions of A2.6, p54 from DDI01001 spec, in the order of their vectors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    Reset,
+    Undefined,
+    SoftwareInterrupt,
+    PrefetchAbort,
+    DataAbort,
+    Irq,
+    Fiq,
+}
+
+impl Exception {
+    /// Address the exception branches to, `0x14` is unused
+    pub fn vector(self) -> u32 {
+        match self {
+            Exception::Reset => 0x00,
+            Exception::Undefined => 0x04,
+            Exception::SoftwareInterrupt => 0x08,
+            Exception::PrefetchAbort => 0x0c,
+            Exception::DataAbort => 0x10,
+            Exception::Irq => 0x18,
+            Exception::Fiq => 0x1c,
+        }
+    }
+
+    /// The mode the exception is handled in
+    pub fn mode(self) -> ProcessorMode {
+        match self {
+            Exception::Reset | Exception::SoftwareInterrupt => ProcessorMode::Supervisor,
+            Exception::Undefined => ProcessorMode::Undefined,
+            Exception::PrefetchAbort | Exception::DataAbort => ProcessorMode::Abort,
+            Exception::Irq => ProcessorMode::Irq,
+            Exception::Fiq => ProcessorMode::Fiq,
+        }
+    }
+
+    /// Saves the CPSR to the SPSR of the exception's mode and `link` to its
+    /// `lr`, then branches to the vector in ARM state with IRQs disabled, and
+    /// FIQs too for reset and FIQ
+    pub(crate) fn enter(self, regs: &mut Registers, link: u32) {
+        let cpsr = regs.apsr;
+        regs.switch_mode(self.mode());
+        if let Some(spsr) = regs.spsr_mut() {
+            *spsr = cpsr;
+        }
+        regs.r14_lr = link as i32;
+
+        regs.apsr = (regs.apsr & !THUMB_BIT) | IRQ_DISABLE;
+        if matches!(self, Exception::Reset | Exception::Fiq) {
+            regs.apsr |= FIQ_DISABLE;
+        }
+        regs.r15_pc = self.vector() as i32;
+    }
+}
@@ -0,0 +1,61 @@
+use crate::{Cycles, Registers};
+use std::collections::VecDeque;
+
+/// What a step changed, enough to undo it
+#[derive(Debug, Clone)]
+pub(crate) struct Delta {
+    pub(crate) regs: Registers,
+    pub(crate) cycles: Cycles,
+    /// Address and previous contents of each store, in the order they were made
+    pub(crate) writes: Vec<(u32, Vec<u8>)>,
+}
+
+/// The last steps of a [`crate::Machine`], which can be undone in reverse
+/// order. Devices are not part of the history.
+#[derive(Debug, Clone)]
+pub struct History {
+    deltas: VecDeque<Delta>,
+    /// The oldest step is forgotten beyond this many
+    limit: usize,
+}
+
+impl History {
+    /// Keeps the last `limit` steps
+    pub fn new(limit: usize) -> History {
+        History {
+            deltas: VecDeque::new(),
+            limit,
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Steps that can be undone
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.deltas.clear();
+    }
+
+    pub(crate) fn push(&mut self, delta: Delta) {
+        if self.limit == 0 {
+            return;
+        }
+        if self.deltas.len() >= self.limit {
+            self.deltas.pop_front();
+        }
+        self.deltas.push_back(delta);
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<Delta> {
+        self.deltas.pop_back()
+    }
+}
@@ -1,92 +1,88 @@
+mod alu;
+mod assembler;
+mod cache;
+mod cycles;
+mod decode;
+mod diagnostics;
+mod error;
+mod exception;
+mod history;
+mod line_table;
+mod memory;
+mod peripherals;
 mod registers;
 mod status_flags;
-
-use capstone::{
-    Capstone, Insn,
-    arch::{
-        ArchOperand,
-        arm::{
-            ArchMode, ArmOperand,
-            ArmOperandType::{self, Imm, Mem, Reg},
-            ArmReg::{ARM_REG_APSR, ARM_REG_PC, ARM_REG_SPSR},
-            ArmShift,
-        },
-    },
-    prelude::*,
+mod symbols;
+mod toolchain;
+mod trace;
+mod watch;
+
+pub use alu::{Opcode, Shift};
+pub use cache::AssemblyCache;
+use capstone::{Capstone, Insn, arch::arm::ArchMode, prelude::*};
+pub use cycles::Cycles;
+pub use decode::{
+    Condition, DecodedInsn, Indexing, Offset, Operand2, RegNum, ShiftAmount, TransferSize, decode,
+    decode_thumb, from_capstone, thumb_size,
+};
+pub use diagnostics::{Diagnostic, Severity, parse_diagnostics};
+pub use error::{ExecError, Fault};
+pub use exception::Exception;
+use exception::{FIQ_DISABLE, IRQ_DISABLE};
+use history::Delta;
+pub use history::History;
+pub use line_table::LineTable;
+pub use memory::{Memory, Region, STACK_SIZE, STACK_TOP, TEXT_BASE};
+pub use peripherals::{
+    BUTTON_COUNT, Board, GPIO_BASE, Gpio, LED_COUNT, Peripheral, SharedPeripheral, TIMER_BASE,
+    Timer, UART_BASE, Uart,
 };
-use core::ops;
 pub use registers::{RegTuple, Registers};
+use status_flags::THUMB_BIT;
 pub use status_flags::{ProcessorMode, StatusFlags, update_from_flags};
-use std::{
-    ffi::OsString,
-    io::{self, Read},
-    panic,
-    process::{self},
-};
-use tempfile::{self, NamedTempFile};
-
-#[derive(Default, Debug)]
-struct Instr {
-    mnemonic: String,
-    update_status_flags: Option<bool>,
-    condition: Condition,
+use std::{collections::HashSet, ffi::OsString, fs, io::Read, path::Path};
+pub use symbols::{Mapping, Symbol, SymbolTable};
+use tempfile::NamedTempFile;
+pub use toolchain::{CANDIDATES, TOOLCHAIN_ENV, Toolchain, ToolchainError, ToolchainSource};
+pub use trace::{MemoryAccess, TRACE_LIMIT, Trace, TraceEntry, TraceFormat};
+pub use watch::{Access, Flag, Watch};
+
+/// Information from the assembler output that is not needed to execute the program
+#[derive(Debug, Clone, Default)]
+pub struct DebugInfo {
+    pub lines: LineTable,
+    pub symbols: SymbolTable,
 }
 
-#[derive(Default, Debug)]
-enum Condition {
-    /// Equal
-    Eq,
-    /// Not equal
-    Ne,
-    /// Carry set / Unsigned higher or same
-    CsHs,
-    /// Carry clear / Unsigned lower
-    CcLo,
-    /// Minus
-    Mi,
-    /// Plus
-    Pl,
-    /// Overflow
-    Vs,
-    /// No overflow
-    Vc,
-    /// Unsigned higher
-    Hi,
-    /// Unsigned lower or same
-    Ls,
-    /// Signed greater than or equal
-    Ge,
-    /// Signed less than
-    Lt,
-    /// Signed greataer than
-    Gt,
-    /// Signed less than or equal
-    Le,
-    /// Always
+/// Which assembler turns source files into machine code
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Assembler {
+    /// The in-process assembler, supports the common subset of GAS syntax
     #[default]
-    Al,
+    Builtin,
+    /// GNU `as` from binutils, needed for macros and conditional assembly
+    Gnu(Toolchain),
 }
 
-impl From<&str> for Condition {
-    fn from(value: &str) -> Self {
-        use Condition::*;
-        match value.to_ascii_lowercase().as_str() {
-            "eq" => Eq,
-            "ne" => Ne,
-            "cs" | "hs" => CsHs,
-            "cc" | "lo" => CcLo,
-            "mi" => Mi,
-            "pl" => Pl,
-            "vs" => Vs,
-            "vc" => Vc,
-            "hi" => Hi,
-            "ls" => Ls,
-            "ge" => Ge,
-            "lt" => Lt,
-            "gt" => Gt,
-            "le" => Le,
-            "" | "al" => Al,
-            _ => panic!("Unrecognised condition {}", value),
+impl Assembler {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Assembler::Builtin => "builtin",
+            Assembler::Gnu(_) => "gnu",
+        }
+    }
+
+    /// `builtin` or `gnu`, the GNU assembler is found with [`Toolchain::find`]
+    pub fn from_name(name: &str, configured: Option<&Path>) -> Result<Assembler, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "builtin" => Ok(Assembler::Builtin),
+            "gnu" | "gas" => Toolchain::find(configured)
+                .map(Assembler::Gnu)
+                .map_err(|e| e.to_string()),
+            _ => Err(format!(
+                "Unrecognised assembler `{}`, expected `builtin` or `gnu`",
+                name
+            )),
         }
     }
 }
@@ -94,353 +90,816 @@ impl From<&str> for Condition {
 pub fn disassemble<'a>(
     cs: &'a Capstone,
     input_path: OsString,
-) -> Result<(Vec<u8>, Vec<u8>, capstone::Instructions<'a>), String> {
-    let mut output_file = tempfile::NamedTempFile::new().unwrap();
-    let output_path = output_file.path().as_os_str().to_os_string();
+    assembler: &Assembler,
+) -> Result<(Memory, capstone::Instructions<'a>, DebugInfo), String> {
+    let (memory, debug_info) = match assembler {
+        Assembler::Builtin => {
+            let source = fs::read_to_string(&input_path).map_err(|e| e.to_string())?;
+            assembler::assemble(&source, &input_path.to_string_lossy())?
+        }
+        Assembler::Gnu(toolchain) => load_object(&gnu_object(toolchain, input_path)?)?,
+    };
 
-    run_gnu_gas(input_path, output_path)?;
+    let instrs = disassemble_text(cs, &memory)?;
 
-    let (data_section, text_section) = extract_sections(&mut output_file);
+    Ok((memory, instrs, debug_info))
+}
 
-    let instrs = cs.disasm_all(text_section.as_slice(), 0).unwrap();
+/// Disassembles the `.text` region of an assembled program
+pub fn disassemble_text<'a>(
+    cs: &'a Capstone,
+    memory: &Memory,
+) -> Result<capstone::Instructions<'a>, String> {
+    let text = memory
+        .region(".text")
+        .ok_or("Program has no .text section")?;
+    cs.disasm_all(&text.bytes, text.base as u64)
+        .map_err(|e| e.to_string())
+}
 
-    Ok((data_section, text_section, instrs))
+/// Disassembles `.text` like [`disassemble_text`], switching to Thumb
+/// between the `$t` and `$a` mapping symbols in `symbols`. `thumb` is a
+/// capstone from [`new_thumb_capstone`].
+pub fn disassemble_mapped<'a>(
+    arm: &'a Capstone,
+    thumb: &'a Capstone,
+    memory: &Memory,
+    symbols: &SymbolTable,
+) -> Result<Vec<capstone::Instructions<'a>>, String> {
+    let text = memory
+        .region(".text")
+        .ok_or("Program has no .text section")?;
+
+    symbols
+        .mapping_ranges(text.base..text.end())
+        .into_iter()
+        .map(|(range, mapping)| {
+            let cs = match mapping {
+                Mapping::Thumb => thumb,
+                Mapping::Arm | Mapping::Data => arm,
+            };
+            let bytes =
+                &text.bytes[(range.start - text.base) as usize..(range.end - text.base) as usize];
+            cs.disasm_all(bytes, range.start as u64)
+                .map_err(|e| e.to_string())
+        })
+        .collect()
 }
 
-pub fn run_program(
-    cs: &Capstone,
-    data_section: Vec<u8>,
-    text_section: Vec<u8>,
-    instrs: capstone::Instructions,
-    regs: &mut Registers,
-    read_char: &impl Fn() -> char,
-    print: &mut impl FnMut(String),
-    force_stop: impl Fn() -> bool,
-) {
-    while (regs.r15_pc as usize) < instrs.len() * 4 {
-        let insn = &instrs[(regs.r15_pc / 4) as usize];
-        let mnemonic = extract_mnemonic(insn);
-
-        let detail: InsnDetail = cs.insn_detail(insn).unwrap();
-        let arch_detail: ArchDetail = detail.arch_detail();
-        let ops = arch_detail.operands();
-
-        let halts = execute_instruction(
-            &data_section,
-            &text_section,
-            ops,
-            regs,
-            &mnemonic,
-            read_char,
-            print,
-        );
+/// Why execution stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// A single instruction was executed and execution can continue
+    Step,
+    /// `pc` reached a breakpoint, the instruction there has not been executed
+    Breakpoint(u32),
+    /// `swi 2` was executed
+    Halted,
+    /// `pc` moved past the last instruction
+    EndOfProgram,
+    /// `force_stop` returned `true`
+    Stopped,
+    /// Stepping back reached the oldest step kept in the history
+    StartOfHistory,
+    /// The step from `address` met the watch at `index` of [`Machine::watches`]
+    Watchpoint { index: usize, address: u32 },
+}
+
+/// Register number of `pc`
+const PC: RegNum = 15;
+
+/// An instruction from `.text`, decoded once when the program is loaded
+#[derive(Debug, Clone)]
+struct Decoded {
+    address: u32,
+    /// The word it was decoded from, if memory no longer holds it the
+    /// instruction has been overwritten
+    encoding: u32,
+    /// 4 for ARM instructions, 2 or 4 for Thumb
+    size: u32,
+    /// Disassembly, e.g. `ldr r0, [r1]`
+    text: String,
+    condition: Condition,
+    /// Instructions that cannot be decoded only fault when executed
+    insn: Result<DecodedInsn, Fault>,
+}
 
-        regs.r15_pc += 4;
+/// A loaded program that can be run, single stepped and reset
+#[derive(Debug, Clone)]
+pub struct Machine {
+    pub regs: Registers,
+    pub memory: Memory,
+    initial_memory: Memory,
+    program: Vec<Decoded>,
+    text_base: u32,
+    /// Exceptions branch to the vectors at `0x00`-`0x1c` instead of `swi`
+    /// calling the Komodo services and faults stopping execution
+    pub bare_metal: bool,
+    /// Interrupt request lines, in bare metal mode an interrupt is taken
+    /// before the next instruction unless the I or F bit of the CPSR masks it.
+    /// Mapped devices can also request an IRQ.
+    pub irq: bool,
+    pub fiq: bool,
+    /// Cycles taken by the instructions executed since the last reset
+    pub cycles: Cycles,
+    /// Records the instructions executed since the last reset while `Some`
+    pub trace: Option<Trace>,
+    /// Keeps the last steps so they can be undone while `Some`. The trace
+    /// keeps the instructions that were undone.
+    pub history: Option<History>,
+    /// Stop execution after the instruction that meets one of them
+    pub watches: Vec<Watch>,
+}
 
-        if force_stop() {
-            break;
+impl Machine {
+    /// Decodes the `.text` region of `memory`, registers start as [`Registers::new`]
+    pub fn new(cs: &Capstone, memory: Memory) -> Result<Machine, String> {
+        let instrs = disassemble_text(cs, &memory)?;
+        let (text_base, program) = decode_program(&instrs, &memory);
+
+        Ok(Machine {
+            regs: Registers::new(),
+            initial_memory: memory.clone(),
+            program,
+            text_base,
+            memory,
+            bare_metal: false,
+            irq: false,
+            fiq: false,
+            cycles: Cycles::default(),
+            trace: None,
+            history: None,
+            watches: Vec::new(),
+        })
+    }
+
+    pub fn pc(&self) -> u32 {
+        self.regs.r15_pc as u32
+    }
+
+    /// Executes the instruction at `pc`, returns [`StopReason::Step`] if
+    /// execution can continue
+    pub fn step(
+        &mut self,
+        read_char: &impl Fn() -> char,
+        print: &mut impl FnMut(String),
+    ) -> Result<StopReason, ExecError> {
+        let watching = !self.watches.is_empty();
+        if self.history.is_none() && !watching {
+            if self.trace.is_some() {
+                self.memory.record_accesses();
+            }
+            let result = self.step_forward(read_char, print);
+            self.memory.take_accesses();
+            return result;
         }
 
-        if halts {
-            break;
+        let (regs, cycles) = (self.regs.clone(), self.cycles);
+        self.memory.record_accesses();
+        if self.history.is_some() {
+            self.memory.record_writes();
+        }
+        let result = self.step_forward(read_char, print);
+        let accesses = self.memory.take_accesses();
+        let writes = self.memory.take_writes();
+
+        let met = match result {
+            Ok(StopReason::Step) => self
+                .watches
+                .iter()
+                .position(|watch| watch.is_met(&regs, &self.regs, &accesses)),
+            _ => None,
+        };
+        let address = regs.r15_pc as u32;
+        if let Some(history) = &mut self.history
+            && result != Ok(StopReason::EndOfProgram)
+        {
+            history.push(Delta {
+                regs,
+                cycles,
+                writes,
+            });
+        }
+        match met {
+            Some(index) => Ok(StopReason::Watchpoint { index, address }),
+            None => result,
         }
     }
-}
 
-pub fn new_capstone() -> Capstone {
-    Capstone::new()
-        .arm()
-        .mode(ArchMode::Arm)
-        .detail(true)
-        .build()
-        .unwrap()
-}
+    /// Undoes the last step kept in the history, `false` if there is none.
+    /// Devices are left as they are.
+    pub fn step_back(&mut self) -> bool {
+        let Some(delta) = self.history.as_mut().and_then(History::pop) else {
+            return false;
+        };
+        self.memory.undo_writes(delta.writes);
+        self.regs = delta.regs;
+        self.cycles = delta.cycles;
+        true
+    }
 
-fn extract_sections(output_file: &mut NamedTempFile) -> (Vec<u8>, Vec<u8>) {
-    let mut buf: Vec<u8> = Vec::new();
-    output_file.read_to_end(&mut buf).unwrap();
+    /// Steps back until `pc` reaches one of `breakpoints` or the start of the
+    /// history. The last step is always undone, so calling this again
+    /// continues back past the breakpoint that stopped it.
+    pub fn reverse_until(
+        &mut self,
+        breakpoints: &HashSet<u32>,
+        force_stop: impl Fn() -> bool,
+    ) -> StopReason {
+        loop {
+            if !self.step_back() {
+                return StopReason::StartOfHistory;
+            }
+
+            if force_stop() {
+                return StopReason::Stopped;
+            }
 
-    let mut data_section: Option<Vec<u8>> = None;
-    let mut text_section: Option<Vec<u8>> = None;
+            if breakpoints.contains(&self.pc()) {
+                return StopReason::Breakpoint(self.pc());
+            }
+        }
+    }
 
-    let parsed = goblin::Object::parse(&buf).unwrap();
-    if let goblin::Object::Elf(elf) = parsed {
-        for header in elf.section_headers.iter() {
-            let name = elf.shdr_strtab.get_at(header.sh_name).unwrap();
+    fn step_forward(
+        &mut self,
+        read_char: &impl Fn() -> char,
+        print: &mut impl FnMut(String),
+    ) -> Result<StopReason, ExecError> {
+        if self.bare_metal {
+            let irq = self.irq || self.memory.bus().irq();
+            let interrupt = if self.fiq && self.regs.apsr & FIQ_DISABLE == 0 {
+                Some(Exception::Fiq)
+            } else if irq && self.regs.apsr & IRQ_DISABLE == 0 {
+                Some(Exception::Irq)
+            } else {
+                None
+            };
+            // taking the interrupt is a step of its own, `subs pc, lr, #4` returns to `pc`
+            if let Some(interrupt) = interrupt {
+                let link = self.pc().wrapping_add(4);
+                interrupt.enter(&mut self.regs, link);
+                self.cycles += Cycles::BRANCH;
+                return Ok(StopReason::Step);
+            }
+        }
 
-            if name == ".data" || name == ".text" {
-                let start = header.sh_offset as usize;
-                let end = header.sh_offset as usize + header.sh_size as usize;
-                let bytes = buf[start..end].to_owned();
+        let result = step_program(
+            &self.program,
+            self.text_base,
+            &mut self.memory,
+            &mut self.regs,
+            &mut self.cycles,
+            self.bare_metal,
+            self.trace.as_mut(),
+            read_char,
+            print,
+        );
 
-                if name == ".data" {
-                    data_section = Some(bytes);
-                } else {
-                    text_section = Some(bytes);
-                }
+        let bus = self.memory.bus();
+        if !bus.is_empty() {
+            let output = bus.tick();
+            if !output.is_empty() {
+                print(output);
             }
         }
+        result
     }
 
-    (data_section.unwrap(), text_section.unwrap())
-}
+    /// Maps `device` at `base`, it stays mapped when the machine is reset
+    pub fn map_peripheral(&mut self, base: u32, device: SharedPeripheral) {
+        self.memory.map_peripheral(base, device.clone());
+        self.initial_memory.map_peripheral(base, device);
+    }
 
-fn run_gnu_gas(input_path: OsString, output_path: OsString) -> Result<(), String> {
-    let command = match os_info::get().os_type() {
-        os_info::Type::Ubuntu | os_info::Type::Debian => "arm-linux-gnueabi-as",
-        os_info::Type::Fedora => "arm-linux-gnu-as",
-        os_info::Type::Linux => panic!("Unsupported linux distro"),
-        os => panic!("Unsupported OS {}", os),
-    };
+    /// Runs until the program halts or `pc` reaches one of `breakpoints`.
+    /// The first instruction is always executed, so calling this again
+    /// continues past the breakpoint that stopped it.
+    pub fn run_until(
+        &mut self,
+        breakpoints: &HashSet<u32>,
+        read_char: &impl Fn() -> char,
+        print: &mut impl FnMut(String),
+        force_stop: impl Fn() -> bool,
+    ) -> Result<StopReason, ExecError> {
+        loop {
+            match self.step(read_char, print)? {
+                StopReason::Step => {}
+                reason => return Ok(reason),
+            }
 
-    let output = process::Command::new(command)
-        .arg("-march=armv4")
-        .arg(input_path)
-        .arg("-o")
-        .arg(output_path.clone())
-        .output();
-
-    match output {
-        Err(e) => {
-            if let io::ErrorKind::NotFound = e.kind() {
-                match os_info::get().os_type() {
-                    os_info::Type::Ubuntu | os_info::Type::Debian => panic!(
-                        "Cannot find `arm-linux-gnueabi-as`, try running:\n\tapt install binutils-arm-linux-gnueabi"
-                    ),
-                    os_info::Type::Fedora => panic!(
-                        "Cannot find `arm-linux-gnu-as`, try running:\n\tdnf install binutils-arm-linux-gnu"
-                    ),
-                    os_info::Type::Linux => panic!("Unsupported linux distro"),
-                    _ => panic!("Unsupported OS"),
-                }
-            } else {
-                panic!("{}", e);
+            if force_stop() {
+                return Ok(StopReason::Stopped);
             }
-        }
-        Ok(output) => {
-            if output.status.success() {
-                return Ok(());
+
+            if breakpoints.contains(&self.pc()) {
+                return Ok(StopReason::Breakpoint(self.pc()));
             }
-            Err(String::from_utf8_lossy(&output.stderr).to_string())
         }
     }
-}
 
-fn extract_mnemonic(insn: &Insn) -> Instr {
-    // A4.2, p436 from DDI01001 spec
-
-    let instr_s_cond = [
-        "add", "sub", "adc", "and", "bic", "eor", "mla", "mov", "mul", "mvn", "orr", "rsb", "rsc",
-        "smlal", "smull", "umlal", "umull", "lsl", "lsr", "asr", "ror", "rrx",
-    ];
-
-    let instr_cond_instr = [
-        ("ldr", "b"),
-        ("ldr", "bt"),
-        ("ldr", "h"),
-        ("ldr", "sb"),
-        ("ldr", "sh"),
-        ("ldr", "t"),
-        ("str", "b"),
-        ("str", "bt"),
-        ("str", "h"),
-        ("str", "t"),
-        ("swp", "b"),
-    ];
-
-    let instr_cond = [
-        "bl", "b", "cmn", "cmp", "ldr", "mrs", "msr", "str", "svc", "swp", "teq", "tst",
-    ];
-
-    let target = insn.mnemonic().unwrap();
-    {
-        let instr = match_instr(&instr_s_cond, target);
-
-        if !instr.is_empty() {
-            let rest = &target[instr.len()..];
-            let is_s = rest.starts_with("s");
-            let condition_str = if is_s { &rest[1..] } else { rest };
-            return Instr {
-                mnemonic: instr,
-                update_status_flags: Some(is_s),
-                condition: Condition::from(condition_str),
-            };
+    /// Restores memory to its state when loaded and clears the registers. In
+    /// bare metal mode execution starts from the reset vector in Supervisor mode.
+    pub fn reset(&mut self) {
+        self.memory = self.initial_memory.clone();
+        self.regs = Registers::new();
+        (self.irq, self.fiq) = (false, false);
+        self.cycles = Cycles::default();
+        if let Some(trace) = &mut self.trace {
+            trace.clear();
+        }
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+        self.memory.bus().reset();
+        if self.bare_metal {
+            Exception::Reset.enter(&mut self.regs, 0);
         }
     }
+}
 
-    for (instr_start, instr_end) in instr_cond_instr {
-        if target.starts_with(instr_start) && target.ends_with(&instr_end) {
-            let instr = String::from(instr_start) + instr_end;
-            let rest = &target[instr_start.len()..(target.len() - instr_end.len())];
-            return Instr {
-                mnemonic: instr,
-                update_status_flags: None,
-                condition: Condition::from(rest),
-            };
+/// Runs until the program halts, runs off the end of `.text` or is stopped.
+/// An instruction that cannot be executed returns an [`ExecError`] and leaves
+/// `pc` pointing at it.
+pub fn run_program(
+    memory: &mut Memory,
+    instrs: capstone::Instructions,
+    regs: &mut Registers,
+    read_char: &impl Fn() -> char,
+    print: &mut impl FnMut(String),
+    force_stop: impl Fn() -> bool,
+) -> Result<StopReason, ExecError> {
+    let (text_base, program) = decode_program(&instrs, memory);
+    // only counted by a `Machine`
+    let mut cycles = Cycles::default();
+
+    loop {
+        match step_program(
+            &program,
+            text_base,
+            memory,
+            regs,
+            &mut cycles,
+            false,
+            None,
+            read_char,
+            print,
+        )? {
+            StopReason::Step => {}
+            reason => return Ok(reason),
+        }
+
+        if force_stop() {
+            return Ok(StopReason::Stopped);
         }
     }
+}
 
-    {
-        let instr = match_instr(&instr_cond, target);
+/// Decodes every word of `.text`, returning its base address. `instrs` only
+/// provides the disassembly, words capstone stopped at are still decoded.
+fn decode_program(instrs: &capstone::Instructions, memory: &Memory) -> (u32, Vec<Decoded>) {
+    let Some(text) = memory.region(".text") else {
+        return (TEXT_BASE, Vec::new());
+    };
+    let texts = instrs
+        .iter()
+        .map(|insn| {
+            format!(
+                "{} {}",
+                insn.mnemonic().unwrap_or_default(),
+                insn.op_str().unwrap_or_default()
+            )
+            .trim_end()
+            .to_string()
+        })
+        .chain(std::iter::repeat_with(String::new));
+
+    let program = text
+        .bytes
+        .chunks_exact(4)
+        .zip(texts)
+        .enumerate()
+        .map(|(i, (bytes, disasm))| {
+            let address = text.base + 4 * i as u32;
+            Decoded::new(
+                address,
+                u32::from_le_bytes(bytes.try_into().unwrap()),
+                disasm,
+            )
+        })
+        .collect();
+    (text.base, program)
+}
 
-        if !instr.is_empty() {
-            let rest = &target[instr.len()..];
-            return Instr {
-                mnemonic: instr,
-                update_status_flags: None,
-                condition: Condition::from(rest),
-            };
+impl Decoded {
+    /// `text` is the disassembly, if empty the encoding is shown instead
+    fn new(address: u32, encoding: u32, text: String) -> Decoded {
+        let (condition, insn) = decode(encoding, address);
+        Decoded {
+            address,
+            encoding,
+            size: 4,
+            text: match text.is_empty() {
+                true => format!(".inst {:#010x}", encoding),
+                false => text,
+            },
+            condition,
+            insn,
         }
     }
 
-    panic!("Unrecognised mnemonic {}", target);
+    /// A Thumb instruction, `encoding` also holds the halfword after it
+    fn thumb(address: u32, encoding: u32) -> Decoded {
+        let (condition, insn) = decode_thumb(encoding, address);
+        let size = thumb_size(encoding);
+        Decoded {
+            address,
+            encoding,
+            size,
+            text: match size {
+                4 => format!(".inst.w {:#010x}", encoding.rotate_left(16)),
+                _ => format!(".inst.n {:#06x}", encoding & 0xffff),
+            },
+            condition,
+            insn,
+        }
+    }
 }
 
-/// Returns `true` if execution is halted, (`SWI 2`)
-fn execute_instruction(
-    data_section: &[u8],
-    text_section: &[u8],
-    ops: Vec<ArchOperand>,
+/// Executes the instruction at `pc`. On a fault `pc` is left pointing at the
+/// faulting instruction.
+///
+/// Instructions are fetched from memory, so code that has been overwritten,
+/// or written outside `.text`, is decoded again before it is executed.
+///
+/// In `bare_metal` mode `swi` and the faults an ARM7TDMI raises take the
+/// exception instead: instructions that cannot be executed are undefined,
+/// fetches from unmapped memory are prefetch aborts and loads and stores to
+/// it are data aborts, with the registers left as before the instruction.
+///
+/// The cycles the instruction takes are added to `cycles`, unless it faults,
+/// and what it did to `trace`, with its loads and stores if `memory` is
+/// recording them.
+#[allow(clippy::too_many_arguments)]
+fn step_program(
+    program: &[Decoded],
+    text_base: u32,
+    memory: &mut Memory,
     regs: &mut Registers,
-    instr: &Instr,
+    cycles: &mut Cycles,
+    bare_metal: bool,
+    trace: Option<&mut Trace>,
     read_char: &impl Fn() -> char,
     print: &mut impl FnMut(String),
-) -> bool {
-    let op_types: Vec<ArmOperandType> = ops
-        .iter()
-        .map(|op| {
-            if let ArchOperand::ArmOperand(arm_op) = op {
-                arm_op.op_type.clone()
-            } else {
-                panic!();
+) -> Result<StopReason, ExecError> {
+    let pc = regs.r15_pc as u32;
+    let text_end = text_base.wrapping_add(4 * program.len() as u32);
+    let thumb = regs.apsr & THUMB_BIT != 0;
+    let fetched;
+    let decoded = if thumb {
+        // Thumb code is decoded as it is executed, `.text` is decoded as ARM
+        match memory.read_u16(pc) {
+            Ok(first) if pc != text_end => {
+                let second = memory.read_u16(pc.wrapping_add(2)).unwrap_or(0);
+                fetched = Decoded::thumb(pc, first as u32 | (second as u32) << 16);
+                Some(&fetched)
             }
-        })
-        .collect();
+            _ => None,
+        }
+    } else {
+        match (
+            memory.read_u32(pc),
+            program.get(pc.wrapping_sub(text_base) as usize / 4),
+        ) {
+            (Ok(encoding), Some(decoded)) if decoded.encoding == encoding => Some(decoded),
+            (Ok(encoding), _) if pc != text_end => {
+                fetched = Decoded::new(pc, encoding, String::new());
+                Some(&fetched)
+            }
+            _ => None,
+        }
+    };
+    let decoded = match decoded {
+        Some(decoded) => decoded,
+        // running off the end of `.text` stops, even if another region follows it
+        None if pc == text_end => return Ok(StopReason::EndOfProgram),
+        None if bare_metal => {
+            Exception::PrefetchAbort.enter(regs, pc.wrapping_add(4));
+            *cycles += Cycles::BRANCH;
+            return Ok(StopReason::Step);
+        }
+        None => {
+            return Err(ExecError {
+                address: pc,
+                instruction: String::new(),
+                fault: Fault::OutOfBounds { address: pc },
+            });
+        }
+    };
 
-    // A3.2.1, p112 from DDI01001 spec
-    let flags = StatusFlags::from(regs.apsr);
-    use Condition::*;
-    let condition_matches = match instr.condition {
-        Eq => flags.zero,
-        Ne => !flags.zero,
-        CsHs => flags.carry,
-        CcLo => !flags.carry,
-        Mi => flags.negative,
-        Vs => flags.overflow,
-        Vc => !flags.overflow,
-        Hi => flags.carry && !flags.zero,
-        Ls => flags.carry || flags.zero,
-        Ge => flags.negative == flags.overflow,
-        Lt => flags.negative != flags.overflow,
-        Gt => !flags.zero || flags.negative == flags.overflow,
-        Le => flags.zero || flags.negative != flags.overflow,
-        Al => true,
-        _ => panic!(),
+    // the fetch is not a load made by the instruction
+    memory.clear_accesses();
+    let Some(trace) = trace else {
+        return execute_decoded(decoded, memory, regs, cycles, bare_metal, read_char, print);
     };
+    let before = regs.clone();
+    let result = execute_decoded(decoded, memory, regs, cycles, bare_metal, read_char, print);
+    if result.is_ok() {
+        trace.push(TraceEntry::new(decoded, (&before, regs), memory.accesses()));
+    }
+    result
+}
+
+/// Executes `decoded`, the instruction at `pc`, see [`step_program`]
+fn execute_decoded(
+    decoded: &Decoded,
+    memory: &mut Memory,
+    regs: &mut Registers,
+    cycles: &mut Cycles,
+    bare_metal: bool,
+    read_char: &impl Fn() -> char,
+    print: &mut impl FnMut(String),
+) -> Result<StopReason, ExecError> {
+    let thumb = regs.apsr & THUMB_BIT != 0;
+
+    // pc points at the next instruction while executing, so branches and
+    // other writes to pc take effect without further adjustment
+    regs.r15_pc += decoded.size as i32;
 
-    if !condition_matches {
-        return false;
+    if !decoded.condition.passes(&StatusFlags::from(regs.apsr)) {
+        *cycles += Cycles::SKIPPED;
+        return Ok(StopReason::Step);
     }
 
-    match (instr.mnemonic.as_str(), op_types.as_slice()) {
-        ("add" | "sub" | "and" | "bic" | "eor" | "orr", [Reg(rd), Reg(rn), _shifter]) => {
-            if let ArchOperand::ArmOperand(shifter_operand) = &ops[2] {
-                let value = binary_op(&instr.mnemonic)(regs[rn], value_of(shifter_operand, regs));
-                regs[rd] = value;
+    if bare_metal && matches!(decoded.insn, Ok(DecodedInsn::Swi(_))) {
+        Exception::SoftwareInterrupt.enter(regs, regs.r15_pc as u32);
+        *cycles += Cycles::BRANCH;
+        return Ok(StopReason::Step);
+    }
 
-                if instr.update_status_flags.unwrap() {
-                    update_status_flags(&mut regs.apsr, value);
-                }
-            }
-        }
+    let cost = match &decoded.insn {
+        // the first half of a Thumb `bl` pair takes an S cycle of its own
+        Ok(insn) if thumb && decoded.size == 4 => Cycles::of(insn, regs) + Cycles::new(1, 0, 0),
+        Ok(insn) => Cycles::of(insn, regs),
+        Err(_) => Cycles::UNDEFINED,
+    };
 
-        ("mul", [Reg(rd), Reg(rm), Reg(rn)]) => regs[rd] = regs[rm] * regs[rn],
+    // an aborted instruction leaves the registers unchanged
+    let before = bare_metal.then(|| regs.clone());
+    let result = decoded
+        .insn
+        .as_ref()
+        .map_err(Fault::clone)
+        .and_then(|insn| execute_instruction(memory, regs, insn, read_char, print));
+
+    let exception = match (&result, &decoded.insn) {
+        (Err(_), Err(_)) => Some(Exception::Undefined),
+        (Err(Fault::OutOfBounds { .. }), Ok(_)) => Some(Exception::DataAbort),
+        _ => None,
+    };
+    if let (Some(exception), Some(before)) = (exception, before) {
+        *regs = before;
+        let (link, cost) = match exception {
+            Exception::DataAbort => (decoded.address.wrapping_add(8), cost + Cycles::BRANCH),
+            _ => (regs.r15_pc as u32, cost),
+        };
+        exception.enter(regs, link);
+        *cycles += cost;
+        return Ok(StopReason::Step);
+    }
 
-        ("mla", [Reg(rd), Reg(rm), Reg(rs), Reg(rn)]) => regs[rd] = regs[rm] * regs[rs] + regs[rn],
+    if result.is_ok() {
+        *cycles += cost;
+    }
+    match result {
+        Ok(true) => Ok(StopReason::Halted),
+        Ok(false) => Ok(StopReason::Step),
+        Err(fault) => {
+            regs.r15_pc = decoded.address as i32;
+            Err(ExecError {
+                address: decoded.address,
+                instruction: decoded.text.clone(),
+                fault,
+            })
+        }
+    }
+}
 
-        ("lsl" | "lsr" | "asr" | "ror" | "rrx" | "mov", [Reg(rd), _shifter]) => {
-            if let ArchOperand::ArmOperand(shifter_operand) = &ops[1] {
-                let value = value_of(shifter_operand, regs);
-                regs[rd] = value;
+/// Disassembly of `insn` with branch targets and literal pool loads shown as
+/// labels, `b loop` and `ldr r0, =hello`, instead of `b #0x1c` and `ldr r0, [pc, #4]`.
+/// `insn` is decoded as Thumb if `symbols` maps its address to Thumb code.
+pub fn symbolic_disasm(insn: &Insn, memory: &Memory, symbols: &SymbolTable) -> String {
+    let mnemonic = insn.mnemonic().unwrap_or_default();
+    let op_str = insn.op_str().unwrap_or_default();
+    let plain = format!("{} {}", mnemonic, op_str).trim_end().to_string();
+
+    let address = insn.address() as u32;
+    let encoding = insn
+        .bytes()
+        .iter()
+        .rev()
+        .fold(0, |word, &byte| word << 8 | byte as u32);
+    // where `pc` reads relative to the instruction
+    let (pc_offset, decoded) = match symbols.mapping(address) {
+        Mapping::Thumb => (4, decode_thumb(encoding, address).1),
+        Mapping::Arm | Mapping::Data => (8, decode(encoding, address).1),
+    };
+
+    match decoded {
+        Ok(DecodedInsn::Branch { target, .. }) => match symbols.name(target) {
+            Some(name) => format!("{} {}", mnemonic, name),
+            None => plain,
+        },
 
-                if instr.update_status_flags.unwrap() {
-                    update_status_flags(&mut regs.apsr, value);
+        // `ldr rX, =value` is assembled as a load from a literal pool after the code
+        Ok(DecodedInsn::Transfer {
+            load: true,
+            size: TransferSize::Word,
+            rn: PC,
+            offset: Offset::Immediate(disp),
+            indexing: Indexing::Offset,
+            ..
+        }) => {
+            let literal = address.wrapping_add(pc_offset).wrapping_add(disp as u32);
+
+            match (memory.read_u32(literal), op_str.split_once(", ")) {
+                (Ok(value), Some((rd, _))) => {
+                    let value = symbols
+                        .name(value)
+                        .map_or(format!("{:#x}", value), str::to_string);
+                    format!("{} {}, ={}", mnemonic, rd, value)
                 }
+                _ => plain,
             }
         }
-        ("lsl" | "lsr" | "asr" | "ror" | "rrx" | "mov", [Reg(rd), Reg(rm), Reg(rn)]) => {
-            let shift = match instr.mnemonic.as_str() {
-                "lsl" => ArmShift::LslReg(*rn),
-                "lsr" => ArmShift::LsrReg(*rn),
-                "asr" => ArmShift::AsrReg(*rn),
-                "ror" => ArmShift::RorReg(*rn),
-                "rrx" => ArmShift::RrxReg(*rn),
-                _ => panic!(),
-            };
 
-            let value = apply_shift(regs, regs[rm], &shift);
-            regs[rd] = value;
+        _ => plain,
+    }
+}
 
-            if instr.update_status_flags.unwrap() {
-                update_status_flags(&mut regs.apsr, value);
-            }
-        }
+pub fn new_capstone() -> Capstone {
+    Capstone::new()
+        .arm()
+        .mode(ArchMode::Arm)
+        .detail(true)
+        .build()
+        .unwrap()
+}
 
-        ("mvn", [Reg(rd), _shifter]) => {
-            if let ArchOperand::ArmOperand(shifter_operand) = &ops[1] {
-                let value = !value_of(shifter_operand, regs);
-                regs[rd] = value;
-                if instr.update_status_flags.unwrap() {
-                    update_status_flags(&mut regs.apsr, value);
-                }
-            }
+/// Capstone for the Thumb regions of a program, see [`disassemble_mapped`]
+pub fn new_thumb_capstone() -> Capstone {
+    Capstone::new()
+        .arm()
+        .mode(ArchMode::Thumb)
+        .detail(true)
+        .build()
+        .unwrap()
+}
+
+/// Assembles `input_path` with GNU `as`, returning the object file
+fn gnu_object(toolchain: &Toolchain, input_path: OsString) -> Result<Vec<u8>, String> {
+    let mut output_file = NamedTempFile::new().unwrap();
+    let output_path = output_file.path().as_os_str().to_os_string();
+
+    toolchain.assemble(input_path, output_path)?;
+
+    let mut buf: Vec<u8> = Vec::new();
+    output_file
+        .read_to_end(&mut buf)
+        .map_err(|e| e.to_string())?;
+    Ok(buf)
+}
+
+fn load_object(buf: &[u8]) -> Result<(Memory, DebugInfo), String> {
+    match goblin::Object::parse(buf).map_err(|e| e.to_string())? {
+        goblin::Object::Elf(elf) => {
+            let memory = Memory::from_elf(buf, &elf)?;
+            let debug_info = DebugInfo {
+                lines: LineTable::from_elf(buf, &elf)?,
+                symbols: SymbolTable::from_elf(&elf, &memory),
+            };
+            Ok((memory, debug_info))
         }
+        _ => Err("Assembler output is not an ELF file".to_string()),
+    }
+}
 
-        ("cmp", [Reg(rn), _shifter]) => {
-            if let ArchOperand::ArmOperand(shifter_operand) = &ops[1] {
-                let shifter_operand_value = value_of(shifter_operand, regs);
-                let mut flags = StatusFlags::from(regs.apsr);
+/// Returns `true` if execution is halted, (`SWI 2`)
+fn execute_instruction(
+    memory: &mut Memory,
+    regs: &mut Registers,
+    insn: &DecodedInsn,
+    read_char: &impl Fn() -> char,
+    print: &mut impl FnMut(String),
+) -> Result<bool, Fault> {
+    match *insn {
+        DecodedInsn::DataProcessing {
+            opcode,
+            set_flags,
+            rd,
+            rn,
+            operand2,
+        } => {
+            let shifter = shifter_operand(regs, &operand2);
+            let rn = read_reg(regs, rn) as u32;
+            let flags = StatusFlags::from(regs.apsr);
+            let (value, new_flags) = alu::data_processing(opcode, rn, shifter, &flags);
+
+            // `movs pc, lr` and `subs pc, lr, #4` return from an exception by
+            // restoring the CPSR, in User mode they only set the flags
+            let spsr = match set_flags && rd == PC && !opcode.is_comparison() {
+                true => regs.spsr(),
+                false => None,
+            };
+            if let Some(spsr) = spsr {
+                regs.set_cpsr(spsr);
+            }
 
-                let (alu_out, should_overflow) = regs[rn].overflowing_sub(shifter_operand_value);
-                flags.negative = alu_out < 0;
-                flags.zero = alu_out == 0;
-                flags.carry = !(regs[rn] as u32)
-                    .overflowing_sub(shifter_operand_value as u32)
-                    .1;
-                flags.overflow = should_overflow;
+            if !opcode.is_comparison() {
+                let thumb = regs.apsr & THUMB_BIT != 0;
+                // ARM instructions are word aligned, `add pc, pc, #2` cannot be
+                // executed, Thumb `mov pc, rm` ignores bit 0
+                if rd == PC && !thumb && value & 3 != 0 {
+                    return Err(Fault::UnalignedAccess { address: value });
+                }
+                match rd == PC {
+                    true => write_pc(regs, value as i32),
+                    false => regs[rd] = value as i32,
+                }
+            }
 
-                regs.apsr = status_flags::update_from_flags(regs.apsr, &flags);
+            if (opcode.is_comparison() || set_flags) && spsr.is_none() {
+                regs.apsr = update_from_flags(regs.apsr, &new_flags);
             }
         }
 
-        ("cmn", [Reg(rn), _shifter]) => {
-            if let ArchOperand::ArmOperand(shifter_operand) = &ops[1] {
-                let shifter_operand_value = value_of(shifter_operand, regs);
-                let mut flags = StatusFlags::new();
+        DecodedInsn::Multiply {
+            set_flags,
+            rd,
+            rm,
+            rs,
+            accumulate,
+        } => {
+            let value = (regs[rm] as u32)
+                .wrapping_mul(regs[rs] as u32)
+                .wrapping_add(accumulate.map_or(0, |rn| regs[rn] as u32));
+            regs[rd] = value as i32;
+            multiply_flags(regs, set_flags, value & (1 << 31) != 0, value == 0);
+        }
 
-                let (alu_out, should_overflow) = regs[rn].overflowing_add(shifter_operand_value);
-                flags.negative = alu_out < 0;
-                flags.zero = alu_out == 0;
-                flags.carry = (regs[rn] as u32)
-                    .overflowing_add(shifter_operand_value as u32)
-                    .1;
-                flags.overflow = should_overflow;
+        DecodedInsn::MultiplyLong {
+            set_flags,
+            signed,
+            accumulate,
+            rd_lo,
+            rd_hi,
+            rm,
+            rs,
+        } => {
+            let accumulate = match accumulate {
+                true => ((regs[rd_hi] as u32 as u64) << 32) | regs[rd_lo] as u32 as u64,
+                false => 0,
+            };
+            let value = alu::multiply_long(regs[rm] as u32, regs[rs] as u32, signed, accumulate);
 
-                regs.apsr = status_flags::update_from_flags(regs.apsr, &flags);
-            }
+            regs[rd_lo] = value as u32 as i32;
+            regs[rd_hi] = (value >> 32) as u32 as i32;
+            multiply_flags(regs, set_flags, value & (1 << 63) != 0, value == 0);
         }
 
-        ("mrs", [Reg(rd), Reg(rn)]) => {
-            assert!(rn.0 == ARM_REG_APSR as u16 || rn.0 == ARM_REG_SPSR as u16);
-            regs[rd] = regs[rn];
+        DecodedInsn::Mrs { rd, spsr: false } => regs[rd] = regs.apsr,
+        DecodedInsn::Mrs { rd, spsr: true } => regs[rd] = regs.spsr().ok_or(Fault::NoSpsr)?,
+
+        DecodedInsn::Msr {
+            spsr,
+            fields,
+            operand2,
+        } => {
+            let value = shifter_operand(regs, &operand2).0;
+            let mask = (0..4)
+                .filter(|field| fields & (1 << field) != 0)
+                .fold(0u32, |mask, field| mask | (0xff << (8 * field)));
+
+            if spsr {
+                let spsr = regs.spsr_mut().ok_or(Fault::NoSpsr)?;
+                *spsr = ((*spsr as u32 & !mask) | (value & mask)) as i32;
+            } else {
+                // User mode can only change the flags, and the T bit is only
+                // changed by `bx`, A4.1.39, p225 from DDI01001 spec
+                let mask = match regs.mode() {
+                    ProcessorMode::User => mask & 0xff00_0000,
+                    _ => mask & !(THUMB_BIT as u32),
+                };
+                regs.set_cpsr(((regs.apsr as u32 & !mask) | (value & mask)) as i32);
+            }
         }
 
-        ("svc", [Imm(n)]) => {
+        DecodedInsn::Swi(n) => {
             /*
              * see: 'Emulator SWIs' section
              *   https://studentnet.cs.manchester.ac.uk/resources/software/komodo/manual.html
              */
-            match *n {
+            match n {
                 // print the least significant byte of r0 as char
                 0 => print(format!("{}", regs.r0 as u8 as char)),
 
@@ -448,114 +907,276 @@ fn execute_instruction(
                 1 => regs.r0 = read_char() as i32,
 
                 // halts execution
-                2 => return true,
+                2 => return Ok(true),
 
                 // prints a string, pointed to by r0
-                3 => {
-                    let mut out = String::new();
-                    for &b in &data_section[regs.r0 as usize..] {
-                        if b == 0 {
-                            break;
-                        }
-                        out.push(b as char);
-                    }
-                    print(out);
-                }
+                3 => print(memory.read_cstring(regs.r0 as u32)?),
 
                 // print the value of r0 as decimal
                 4 => print(format!("{}", regs.r0)),
 
-                _ => panic!(),
+                n => return Err(Fault::UnknownSwi(n)),
             }
         }
 
-        ("ldr", [Reg(rd), Mem(addressing_mode)]) => {
-            let addr = if addressing_mode.base().0 as u32 == ARM_REG_PC {
-                (regs.r15_pc + addressing_mode.disp() + 8) as usize
-            } else {
-                (regs[&addressing_mode.base()] + addressing_mode.disp()) as usize
+        DecodedInsn::Transfer {
+            load: true,
+            size,
+            rd,
+            rn,
+            offset,
+            indexing,
+        } => {
+            let addr = transfer_address(regs, rn, &offset, indexing);
+
+            let value = match size {
+                // A4.1.23, p193 from DDI01001 spec, unaligned words are rotated
+                TransferSize::Word => {
+                    memory.read_u32(addr & !3)?.rotate_right(8 * (addr & 3)) as i32
+                }
+                TransferSize::Byte => memory.read_u8(addr)? as i32,
+                TransferSize::Halfword => memory.read_u16(halfword_aligned(addr)?)? as i32,
+                TransferSize::SignedByte => memory.read_u8(addr)? as i8 as i32,
+                TransferSize::SignedHalfword => {
+                    memory.read_u16(halfword_aligned(addr)?)? as i16 as i32
+                }
             };
 
-            let bytes = &text_section[addr..addr + 4];
-            let ptr = i32::from_ne_bytes(bytes.try_into().unwrap());
+            if rd == PC {
+                write_pc(regs, value);
+            } else {
+                regs[rd] = value;
+            }
+        }
 
-            regs[rd] = ptr;
+        DecodedInsn::Transfer {
+            load: false,
+            size,
+            rd,
+            rn,
+            offset,
+            indexing,
+        } => {
+            let value = read_reg(regs, rd);
+            let addr = transfer_address(regs, rn, &offset, indexing);
+
+            match size {
+                TransferSize::Word => memory.write_u32(addr & !3, value as u32)?,
+                TransferSize::Byte => memory.write_u8(addr, value as u8)?,
+                TransferSize::Halfword => {
+                    memory.write_u16(halfword_aligned(addr)?, value as u16)?
+                }
+                TransferSize::SignedByte | TransferSize::SignedHalfword => {
+                    unreachable!("Signed stores do not exist")
+                }
+            }
         }
 
-        ("b", [Imm(n)]) => {
-            // The -4 subtraction cancels out pc+=4 in main loop
-            regs.r15_pc = n - 4
+        DecodedInsn::Swap { byte, rd, rm, rn } => {
+            let addr = regs[rn] as u32;
+            let value = regs[rm];
+
+            if byte {
+                regs[rd] = memory.read_u8(addr)? as i32;
+                memory.write_u8(addr, value as u8)?;
+            } else {
+                regs[rd] = memory.read_u32(addr & !3)?.rotate_right(8 * (addr & 3)) as i32;
+                memory.write_u32(addr & !3, value as u32)?;
+            }
         }
 
-        ("bl", [Imm(n)]) => {
-            regs.r14_lr = regs.r15_pc;
-            // The -4 subtraction cancels out pc+=4 in main loop
-            regs.r15_pc = n - 4;
+        DecodedInsn::Block {
+            load,
+            increment,
+            before,
+            writeback,
+            rn,
+            registers,
+        } => block_transfer(
+            memory, regs, load, increment, before, writeback, rn, registers,
+        )?,
+
+        DecodedInsn::Branch { link, target } => {
+            if link {
+                // bit 0 of the return address is set in Thumb state, so `bx lr` returns to it
+                regs.r14_lr = regs.r15_pc | (regs.apsr & THUMB_BIT != 0) as i32;
+            }
+            regs.r15_pc = target as i32;
         }
 
-        (
-            "adc" | "bl" | "ldrb" | "ldrbt" | "ldrh" | "ldrsb" | "ldrsh" | "ldrt" | "msr" | "rsb"
-            | "rsc" | "smlal" | "smull" | "str" | "strb" | "strbt" | "strh" | "strt" | "swp"
-            | "swpb" | "teq" | "tst" | "umlal" | "umull",
-            _,
-        ) => {
-            todo!("{} mnemonic", instr.mnemonic);
+        DecodedInsn::BranchExchange { rm } => {
+            let target = read_reg(regs, rm);
+            if target & 3 == 2 {
+                return Err(Fault::UnalignedAccess {
+                    address: target as u32,
+                });
+            }
+            match target & 1 != 0 {
+                true => regs.apsr |= THUMB_BIT,
+                false => regs.apsr &= !THUMB_BIT,
+            }
+            regs.r15_pc = target & !1;
         }
 
-        _ => panic!("Unrecognised mnemonic {}", instr.mnemonic),
+        DecodedInsn::Adr { rd, address } => regs[rd] = address as i32,
     };
-    false
+    Ok(false)
 }
 
-fn update_status_flags(apsr: &mut i32, value: i32) {
-    let mut flags = StatusFlags::from(*apsr);
-    flags.negative = value < 0;
-    flags.zero = value == 0;
-    *apsr = status_flags::update_from_flags(*apsr, &flags);
+/// Halfword transfers to odd addresses are UNPREDICTABLE, A4.1.28, p204 from DDI01001 spec
+fn halfword_aligned(addr: u32) -> Result<u32, Fault> {
+    if addr & 1 == 0 {
+        Ok(addr)
+    } else {
+        Err(Fault::UnalignedAccess { address: addr })
+    }
+}
+
+/// Updates N and Z for `S` suffixed multiplies, C and V are left unchanged
+// A4.1.40, p230 from DDI01001 spec
+fn multiply_flags(regs: &mut Registers, set_flags: bool, negative: bool, zero: bool) {
+    if set_flags {
+        let mut flags = StatusFlags::from(regs.apsr);
+        flags.negative = negative;
+        flags.zero = zero;
+        regs.apsr = update_from_flags(regs.apsr, &flags);
+    }
 }
 
-fn match_instr(instrs: &[&'static str], target: &str) -> String {
-    for i in instrs.iter() {
-        if target.starts_with(i) {
-            let string = i.to_string();
-            return string;
+/// Reads a register as an operand, `pc` reads as the address of the current instruction plus 8
+fn read_reg(regs: &Registers, reg: RegNum) -> i32 {
+    if reg == PC {
+        // pc already points at the next instruction, two instructions ahead is
+        // 8 bytes in ARM state and 4 in Thumb state
+        match regs.apsr & THUMB_BIT != 0 {
+            true => regs.r15_pc + 2,
+            false => regs.r15_pc + 4,
         }
+    } else {
+        regs[reg]
     }
-    String::new()
 }
 
-fn binary_op(mneomonic: &str) -> fn(i32, i32) -> i32 {
-    match mneomonic {
-        "add" => ops::Add::add,
-        "sub" => ops::Sub::sub,
-        "and" => ops::BitAnd::bitand,
-        "eor" => ops::BitXor::bitxor,
-        "orr" => ops::BitOr::bitor,
-        _ => panic!(),
+/// Loads into `pc` ignore the bits below the instruction size, loads never
+/// change between ARM and Thumb state on ARMv4T, only `bx` does
+fn write_pc(regs: &mut Registers, value: i32) {
+    regs.r15_pc = match regs.apsr & THUMB_BIT != 0 {
+        true => value & !1,
+        false => value & !3,
+    };
+}
+
+/// Address of a single data transfer, `ldr`/`str` and their byte, halfword and
+/// signed variants. The base register is updated for pre-indexed with writeback,
+/// `[Rn, offset]!`, and post-indexed, `[Rn], offset`, addressing modes.
+// A5.2 and A5.3, p458 from DDI01001 spec
+fn transfer_address(regs: &mut Registers, rn: RegNum, offset: &Offset, indexing: Indexing) -> u32 {
+    let base = read_reg(regs, rn);
+    let offset = match *offset {
+        Offset::Immediate(n) => n,
+        Offset::Register {
+            rm,
+            shift,
+            amount,
+            subtract,
+        } => {
+            let carry = StatusFlags::from(regs.apsr).carry;
+            let index = alu::shift(shift, regs[rm] as u32, amount, carry).0 as i32;
+            if subtract {
+                index.wrapping_neg()
+            } else {
+                index
+            }
+        }
+    };
+
+    let addr = base.wrapping_add(offset);
+    match indexing {
+        Indexing::Offset => addr as u32,
+        Indexing::PreIndexed => {
+            regs[rn] = addr;
+            addr as u32
+        }
+        Indexing::PostIndexed => {
+            regs[rn] = addr;
+            base as u32
+        }
     }
 }
 
-fn value_of(operand: &ArmOperand, registers: &registers::Registers) -> i32 {
-    match operand.op_type {
-        Reg(reg_id) => apply_shift(registers, registers[&reg_id], &operand.shift),
-        Imm(n) => n,
-        _ => panic!(),
+/// Load and store multiple, `ldm`/`stm` in all four addressing modes and the
+/// `push`/`pop` aliases. The lowest numbered register uses the lowest address.
+// A5.4, p481 from DDI01001 spec
+#[allow(clippy::too_many_arguments)]
+fn block_transfer(
+    memory: &mut Memory,
+    regs: &mut Registers,
+    load: bool,
+    increment: bool,
+    before: bool,
+    writeback: bool,
+    rn: RegNum,
+    registers: u16,
+) -> Result<(), Fault> {
+    let reg_list = (0..16).filter(|reg| registers & (1 << reg) != 0);
+
+    let base_value = regs[rn] as u32;
+    let size = 4 * registers.count_ones();
+    let start = match (increment, before) {
+        (true, false) => base_value,
+        (true, true) => base_value.wrapping_add(4),
+        (false, false) => base_value.wrapping_sub(size).wrapping_add(4),
+        (false, true) => base_value.wrapping_sub(size),
+    };
+    let new_base = if increment {
+        base_value.wrapping_add(size)
+    } else {
+        base_value.wrapping_sub(size)
+    };
+
+    if load {
+        // a loaded base register takes priority over writeback
+        if writeback {
+            regs[rn] = new_base as i32;
+        }
+
+        for (i, reg) in reg_list.enumerate() {
+            let value = memory.read_u32((start + 4 * i as u32) & !3)? as i32;
+            if reg == PC {
+                write_pc(regs, value);
+            } else {
+                regs[reg] = value;
+            }
+        }
+    } else {
+        for (i, reg) in reg_list.enumerate() {
+            let value = read_reg(regs, reg) as u32;
+            memory.write_u32((start + 4 * i as u32) & !3, value)?;
+        }
+
+        if writeback {
+            regs[rn] = new_base as i32;
+        }
     }
+    Ok(())
 }
 
-fn apply_shift(registers: &registers::Registers, num: i32, shift: &ArmShift) -> i32 {
-    use ArmShift::*;
-    match shift {
-        Lsl(s) => ((num as u32) << s) as i32,
-        Lsr(s) => ((num as u32) >> s) as i32,
-        Asr(s) => num >> s,
-        Ror(s) => num.rotate_right(*s),
-        LslReg(reg) => ((num as u32) << registers[reg]) as i32,
-        LsrReg(reg) => ((num as u32) >> registers[reg]) as i32,
-        AsrReg(reg) => num >> registers[reg],
-        RorReg(reg) => num.rotate_right(registers[reg] as u32),
-        Rrx(_) | RrxReg(_) => num.rotate_right(1),
-        Invalid => num,
+/// Value and carry out of a data processing shifter operand, A5.1 from DDI01001 spec
+fn shifter_operand(regs: &Registers, operand2: &Operand2) -> (u32, bool) {
+    let carry = StatusFlags::from(regs.apsr).carry;
+    match *operand2 {
+        Operand2::Immediate {
+            immed_8,
+            rotate_imm,
+        } => alu::rotated_immediate(immed_8, rotate_imm, carry),
+        Operand2::Register { rm, shift, amount } => {
+            let amount = match amount {
+                ShiftAmount::Immediate(amount) => amount,
+                // register shifts use the bottom byte of the register
+                ShiftAmount::Register(rs) => regs[rs] as u32 & 0xff,
+            };
+            alu::shift(shift, read_reg(regs, rm) as u32, amount, carry)
+        }
     }
 }
 
@@ -0,0 +1,270 @@
+use crate::TEXT_BASE;
+use goblin::elf::Elf;
+use std::ops::Range;
+
+/// A row of the DWARF line number matrix, `line` covers the addresses from
+/// `address` up to the next row
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Row {
+    address: u32,
+    end: u32,
+    line: u32,
+}
+
+/// Maps instruction addresses to the source lines that produced them, read from
+/// the `.debug_line` section `as -g` emits. A line can cover several
+/// instructions, e.g. a macro, and literal pools and data are not covered.
+#[derive(Debug, Clone, Default)]
+pub struct LineTable {
+    rows: Vec<Row>,
+}
+
+impl LineTable {
+    /// An ELF file without a `.debug_line` section gives an empty table
+    pub fn from_elf(buf: &[u8], elf: &Elf) -> Result<LineTable, String> {
+        let Some(header) = elf
+            .section_headers
+            .iter()
+            .find(|header| elf.shdr_strtab.get_at(header.sh_name) == Some(".debug_line"))
+        else {
+            return Ok(LineTable::default());
+        };
+
+        let start = header.sh_offset as usize;
+        let section = buf
+            .get(start..start + header.sh_size as usize)
+            .ok_or("Truncated .debug_line section")?;
+
+        // object files address `.text` relative to the start of the section
+        let base = if elf.is_object_file() { TEXT_BASE } else { 0 };
+
+        LineTable::from_debug_line(section, base).ok_or("Malformed .debug_line section".into())
+    }
+
+    /// Parses the contents of a `.debug_line` section, `base` is added to every address
+    pub(crate) fn from_debug_line(section: &[u8], base: u32) -> Option<LineTable> {
+        let mut reader = Reader {
+            bytes: section,
+            pos: 0,
+        };
+        let mut rows = Vec::new();
+        while reader.pos < section.len() {
+            read_unit(&mut reader, base, &mut rows)?;
+        }
+        rows.sort_by_key(|row| row.address);
+
+        Some(LineTable { rows })
+    }
+
+    /// Builds a table from `(addresses, line)` pairs, used by the built-in assembler
+    pub(crate) fn from_ranges(ranges: impl IntoIterator<Item = (Range<u32>, u32)>) -> LineTable {
+        let mut rows: Vec<Row> = ranges
+            .into_iter()
+            .filter(|(range, _)| !range.is_empty())
+            .map(|(range, line)| Row {
+                address: range.start,
+                end: range.end,
+                line,
+            })
+            .collect();
+        rows.sort_by_key(|row| row.address);
+
+        LineTable { rows }
+    }
+
+    /// Source line of the instruction at `address`, line numbers start at 1
+    pub fn line(&self, address: u32) -> Option<u32> {
+        self.rows
+            .iter()
+            .find(|row| (row.address..row.end).contains(&address))
+            .map(|row| row.line)
+    }
+
+    /// Addresses of the instructions generated by `line`, in ascending order
+    pub fn addresses(&self, line: u32) -> Vec<u32> {
+        self.rows
+            .iter()
+            .filter(|row| row.line == line)
+            .flat_map(|row| (row.address..row.end).step_by(4))
+            .collect()
+    }
+
+    /// `(address, line)` of the first instruction of every line, in address order
+    pub fn iter(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        self.rows.iter().map(|row| (row.address, row.line))
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Option<&[u8]> {
+        let bytes = self.bytes.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn uleb128(&mut self) -> Option<u64> {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                value |= ((byte & 0x7f) as u64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+    }
+
+    fn sleb128(&mut self) -> Option<i64> {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                value |= ((byte & 0x7f) as i64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    value |= -1 << shift;
+                }
+                return Some(value);
+            }
+        }
+    }
+}
+
+/// Runs the line number program of one unit, appending a row for every line.
+/// The file and column registers are ignored, `as` only emits one file.
+/// Rows are emitted when the next row or the end of the sequence gives their end.
+// 6.2, p110 from the DWARF 4 spec
+fn read_unit(reader: &mut Reader, base: u32, rows: &mut Vec<Row>) -> Option<()> {
+    // 64 bit DWARF is never emitted for 32 bit targets
+    let unit_length = reader.u32()? as usize;
+    let end = reader.pos + unit_length;
+
+    let version = reader.u16()?;
+    if version >= 5 {
+        // address_size and segment_selector_size
+        reader.take(2)?;
+    }
+    let header_length = reader.u32()? as usize;
+    let program = reader.pos + header_length;
+
+    let min_inst_length = reader.u8()? as u32;
+    if version >= 4 {
+        // maximum_operations_per_instruction, always 1 for ARM
+        reader.u8()?;
+    }
+    let _default_is_stmt = reader.u8()?;
+    let line_base = reader.u8()? as i8 as i64;
+    let line_range = reader.u8()? as u32;
+    let opcode_base = reader.u8()?;
+    let opcode_lengths = reader
+        .take(opcode_base.saturating_sub(1) as usize)?
+        .to_vec();
+    if line_range == 0 {
+        return None;
+    }
+
+    // the directory and file tables are skipped
+    reader.pos = program;
+
+    let mut address: u32 = 0;
+    let mut line: i64 = 1;
+    // the row waiting for its end address
+    let mut pending: Option<(u32, i64)> = None;
+    let mut emit = |pending: &mut Option<(u32, i64)>, next: Option<(u32, i64)>, end: u32| {
+        if let Some((start, line)) = pending.take()
+            && start < end
+        {
+            rows.push(Row {
+                address: base.wrapping_add(start),
+                end: base.wrapping_add(end),
+                line: line as u32,
+            });
+        }
+        *pending = next;
+    };
+
+    while reader.pos < end {
+        let opcode = reader.u8()?;
+
+        // special opcodes advance the address and line then append a row
+        if opcode >= opcode_base {
+            let adjusted = (opcode - opcode_base) as u32;
+            address = address.wrapping_add(adjusted / line_range * min_inst_length);
+            line += line_base + (adjusted % line_range) as i64;
+            emit(&mut pending, Some((address, line)), address);
+            continue;
+        }
+
+        match opcode {
+            // extended opcodes
+            0 => {
+                let len = reader.uleb128()? as usize;
+                let next = reader.pos + len;
+                match reader.u8()? {
+                    // DW_LNE_end_sequence
+                    1 => {
+                        emit(&mut pending, None, address);
+                        address = 0;
+                        line = 1;
+                    }
+                    // DW_LNE_set_address
+                    2 => address = reader.u32()?,
+                    _ => {}
+                }
+                reader.pos = next;
+            }
+            // DW_LNS_copy
+            1 => emit(&mut pending, Some((address, line)), address),
+            // DW_LNS_advance_pc
+            2 => address = address.wrapping_add(reader.uleb128()? as u32 * min_inst_length),
+            // DW_LNS_advance_line
+            3 => line += reader.sleb128()?,
+            // DW_LNS_set_file, DW_LNS_set_column and DW_LNS_set_isa
+            4 | 5 | 12 => {
+                reader.uleb128()?;
+            }
+            // DW_LNS_negate_stmt, DW_LNS_set_basic_block, DW_LNS_set_prologue_end
+            // and DW_LNS_set_epilogue_begin
+            6 | 7 | 10 | 11 => {}
+            // DW_LNS_const_add_pc
+            8 => {
+                let adjusted = (255 - opcode_base) as u32;
+                address = address.wrapping_add(adjusted / line_range * min_inst_length);
+            }
+            // DW_LNS_fixed_advance_pc
+            9 => address = address.wrapping_add(reader.u16()? as u32),
+            // opcodes from later versions are skipped using their operand counts
+            n => {
+                for _ in 0..*opcode_lengths.get(n as usize - 1)? {
+                    reader.uleb128()?;
+                }
+            }
+        }
+    }
+
+    reader.pos = end;
+    Some(())
+}
@@ -0,0 +1,350 @@
+use crate::{
+    error::Fault,
+    peripherals::{Bus, SharedPeripheral},
+    trace::MemoryAccess,
+};
+use goblin::elf::{
+    Elf,
+    reloc::{R_ARM_ABS32, R_ARM_CALL, R_ARM_JUMP24, R_ARM_PC24, R_ARM_REL32, R_ARM_V4BX},
+    section_header::{SHF_ALLOC, SHT_NOBITS},
+};
+use std::cell::RefCell;
+
+/// Address `.text` is placed at when loading an unlinked object file
+pub const TEXT_BASE: u32 = 0x0000_0000;
+/// Sections following `.text` start on the next multiple of this
+pub const SECTION_ALIGN: u32 = 0x1000;
+/// Initial value of `sp`, the stack grows down from here
+pub const STACK_TOP: u32 = 0x0010_0000;
+pub const STACK_SIZE: u32 = 0x0001_0000;
+
+/// A contiguous block of bytes mapped at `base`
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub name: String,
+    pub base: u32,
+    pub bytes: Vec<u8>,
+}
+
+impl Region {
+    /// First address after the end of the region
+    pub fn end(&self) -> u32 {
+        self.base + self.bytes.len() as u32
+    }
+
+    fn contains(&self, addr: u32, len: u32) -> bool {
+        addr >= self.base && addr as u64 + len as u64 <= self.end() as u64
+    }
+}
+
+/// Flat, byte addressable, little endian address space shared by code, data,
+/// the stack and memory mapped devices
+#[derive(Debug, Clone, Default)]
+pub struct Memory {
+    regions: Vec<Region>,
+    /// Decoded first by the `read_` and `write_` methods, clones of the memory
+    /// share the devices
+    bus: Bus,
+    /// Loads and stores made since [`Memory::record_accesses`], for a trace
+    accesses: RefCell<Option<Vec<MemoryAccess>>>,
+    /// Address and previous contents of the bytes written since
+    /// [`Memory::record_writes`], to undo a step
+    writes: Option<Vec<(u32, Vec<u8>)>>,
+}
+
+impl Memory {
+    pub fn new() -> Memory {
+        Memory::default()
+    }
+
+    /// Maps the allocated sections of an ELF file followed by a stack region.
+    ///
+    /// Linked executables are mapped at their section addresses. Object files
+    /// produced by `as` have every section at address 0, so `.text` is placed at
+    /// [`TEXT_BASE`], the remaining sections after it, and relocations are applied
+    /// so that `ldr r0, =label` yields the address of `label`.
+    pub fn from_elf(buf: &[u8], elf: &Elf) -> Result<Memory, String> {
+        let mut memory = Memory::new();
+        let mut bases: Vec<Option<u32>> = vec![None; elf.section_headers.len()];
+        let mut next_base = TEXT_BASE;
+
+        let mut order: Vec<usize> = (0..elf.section_headers.len()).collect();
+        // `.text` goes first so instruction addresses start at `TEXT_BASE`
+        order.sort_by_key(|&i| {
+            elf.shdr_strtab.get_at(elf.section_headers[i].sh_name) != Some(".text")
+        });
+
+        for i in order {
+            let header = &elf.section_headers[i];
+            if header.sh_flags as u32 & SHF_ALLOC == 0 {
+                continue;
+            }
+            let name = elf.shdr_strtab.get_at(header.sh_name).unwrap();
+
+            let bytes = if header.sh_type == SHT_NOBITS {
+                vec![0; header.sh_size as usize]
+            } else {
+                let start = header.sh_offset as usize;
+                let end = start + header.sh_size as usize;
+                buf[start..end].to_owned()
+            };
+
+            let base = if elf.is_object_file() {
+                let base = align_up(next_base, header.sh_addralign.max(1) as u32);
+                next_base = align_up(base + bytes.len() as u32, SECTION_ALIGN);
+                base
+            } else {
+                header.sh_addr as u32
+            };
+
+            bases[i] = Some(base);
+            memory.map(name, base, bytes);
+        }
+
+        for (reloc_idx, relocs) in elf.shdr_relocs.iter() {
+            let target = elf.section_headers[*reloc_idx].sh_info as usize;
+            let Some(target_base) = bases[target] else {
+                continue;
+            };
+
+            for reloc in relocs.iter() {
+                // marks a `bx` for linkers targeting ARMv4, which has none,
+                // the instruction is left as it is
+                if reloc.r_type == R_ARM_V4BX {
+                    continue;
+                }
+                let sym = elf.syms.get(reloc.r_sym).unwrap();
+                let sym_name = elf.strtab.get_at(sym.st_name).unwrap_or("");
+                let sym_base = match bases.get(sym.st_shndx) {
+                    Some(Some(base)) => *base,
+                    _ => return Err(format!("Undefined symbol `{}`", sym_name)),
+                };
+
+                let s = sym_base.wrapping_add(sym.st_value as u32);
+                let p = target_base + reloc.r_offset as u32;
+                let word = memory.read_u32(p).map_err(|fault| fault.to_string())?;
+
+                let value = match reloc.r_type {
+                    R_ARM_ABS32 => {
+                        let a = reloc.r_addend.map_or(word, |a| a as u32);
+                        s.wrapping_add(a)
+                    }
+                    R_ARM_REL32 => {
+                        let a = reloc.r_addend.map_or(word, |a| a as u32);
+                        s.wrapping_add(a).wrapping_sub(p)
+                    }
+                    R_ARM_PC24 | R_ARM_CALL | R_ARM_JUMP24 => {
+                        let a = reloc
+                            .r_addend
+                            .map_or((((word << 8) as i32) >> 6) as u32, |a| a as u32);
+                        let offset = s.wrapping_add(a).wrapping_sub(p) >> 2;
+                        (word & 0xff00_0000) | (offset & 0x00ff_ffff)
+                    }
+                    r_type => return Err(format!("Unsupported relocation type {}", r_type)),
+                };
+
+                memory
+                    .write_u32(p, value)
+                    .map_err(|fault| fault.to_string())?;
+            }
+        }
+
+        memory.map_stack();
+        Ok(memory)
+    }
+
+    /// Maps the stack, `STACK_SIZE` bytes below [`STACK_TOP`]
+    pub(crate) fn map_stack(&mut self) {
+        self.map(
+            "stack",
+            STACK_TOP - STACK_SIZE,
+            vec![0; STACK_SIZE as usize],
+        );
+    }
+
+    pub fn map(&mut self, name: &str, base: u32, bytes: Vec<u8>) {
+        self.regions.push(Region {
+            name: name.to_string(),
+            base,
+            bytes,
+        });
+    }
+
+    /// Routes accesses to the `device.size()` bytes from `base` to `device`
+    pub fn map_peripheral(&mut self, base: u32, device: SharedPeripheral) {
+        self.bus.map(base, device);
+    }
+
+    pub(crate) fn bus(&self) -> &Bus {
+        &self.bus
+    }
+
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    pub fn region(&self, name: &str) -> Option<&Region> {
+        self.regions.iter().find(|r| r.name == name)
+    }
+
+    pub fn read_bytes(&self, addr: u32, len: u32) -> Result<&[u8], Fault> {
+        let region = self
+            .regions
+            .iter()
+            .find(|r| r.contains(addr, len))
+            .ok_or(Fault::OutOfBounds { address: addr })?;
+        let start = (addr - region.base) as usize;
+        Ok(&region.bytes[start..start + len as usize])
+    }
+
+    pub fn write_bytes(&mut self, addr: u32, bytes: &[u8]) -> Result<(), Fault> {
+        let len = bytes.len() as u32;
+        let region = self
+            .regions
+            .iter_mut()
+            .find(|r| r.contains(addr, len))
+            .ok_or(Fault::OutOfBounds { address: addr })?;
+        let start = (addr - region.base) as usize;
+        let bytes_written = &mut region.bytes[start..start + bytes.len()];
+        if let Some(writes) = &mut self.writes {
+            writes.push((addr, bytes_written.to_vec()));
+        }
+        bytes_written.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Starts keeping what each write overwrites, until [`Memory::take_writes`].
+    /// Writes to devices are not kept.
+    pub(crate) fn record_writes(&mut self) {
+        self.writes = Some(Vec::new());
+    }
+
+    /// The writes made since [`Memory::record_writes`], which stops recording
+    pub(crate) fn take_writes(&mut self) -> Vec<(u32, Vec<u8>)> {
+        self.writes.take().unwrap_or_default()
+    }
+
+    /// Puts back the bytes `writes` overwrote, latest first
+    pub(crate) fn undo_writes(&mut self, writes: Vec<(u32, Vec<u8>)>) {
+        for (addr, bytes) in writes.into_iter().rev() {
+            // the region was written to, so it is still mapped
+            self.write_bytes(addr, &bytes).unwrap();
+        }
+    }
+
+    /// Reads from a device if one is mapped at `addr`
+    fn read_device(&self, addr: u32) -> Option<u32> {
+        match self.bus.is_empty() {
+            true => None,
+            false => self.bus.read(addr),
+        }
+    }
+
+    /// Writes to the device mapped at `addr`, `false` if there is none
+    fn write_device(&self, addr: u32, value: u32, size: u32) -> bool {
+        !self.bus.is_empty() && self.bus.write(addr, value, size)
+    }
+
+    /// Starts keeping the loads and stores made, until [`Memory::take_accesses`]
+    pub(crate) fn record_accesses(&self) {
+        *self.accesses.borrow_mut() = Some(Vec::new());
+    }
+
+    /// Forgets the accesses kept so far, if recording
+    pub(crate) fn clear_accesses(&self) {
+        if let Some(accesses) = self.accesses.borrow_mut().as_mut() {
+            accesses.clear();
+        }
+    }
+
+    /// The loads and stores made since recording started or was cleared
+    pub(crate) fn accesses(&self) -> Vec<MemoryAccess> {
+        self.accesses.borrow().clone().unwrap_or_default()
+    }
+
+    /// The loads and stores made since [`Memory::record_accesses`], which stops recording
+    pub(crate) fn take_accesses(&self) -> Vec<MemoryAccess> {
+        self.accesses.take().unwrap_or_default()
+    }
+
+    fn record(&self, address: u32, size: u32, value: u32, write: bool) {
+        if let Some(accesses) = self.accesses.borrow_mut().as_mut() {
+            accesses.push(MemoryAccess {
+                address,
+                size,
+                value,
+                write,
+            });
+        }
+    }
+
+    pub fn read_u8(&self, addr: u32) -> Result<u8, Fault> {
+        let value = match self.read_device(addr) {
+            Some(value) => value as u8,
+            None => self.read_bytes(addr, 1)?[0],
+        };
+        self.record(addr, 1, value as u32, false);
+        Ok(value)
+    }
+
+    pub fn read_u16(&self, addr: u32) -> Result<u16, Fault> {
+        let value = match self.read_device(addr) {
+            Some(value) => value as u16,
+            None => u16::from_le_bytes(self.read_bytes(addr, 2)?.try_into().unwrap()),
+        };
+        self.record(addr, 2, value as u32, false);
+        Ok(value)
+    }
+
+    pub fn read_u32(&self, addr: u32) -> Result<u32, Fault> {
+        let value = match self.read_device(addr) {
+            Some(value) => value,
+            None => u32::from_le_bytes(self.read_bytes(addr, 4)?.try_into().unwrap()),
+        };
+        self.record(addr, 4, value, false);
+        Ok(value)
+    }
+
+    pub fn write_u8(&mut self, addr: u32, value: u8) -> Result<(), Fault> {
+        if !self.write_device(addr, value as u32, 1) {
+            self.write_bytes(addr, &[value])?;
+        }
+        self.record(addr, 1, value as u32, true);
+        Ok(())
+    }
+
+    pub fn write_u16(&mut self, addr: u32, value: u16) -> Result<(), Fault> {
+        if !self.write_device(addr, value as u32, 2) {
+            self.write_bytes(addr, &value.to_le_bytes())?;
+        }
+        self.record(addr, 2, value as u32, true);
+        Ok(())
+    }
+
+    pub fn write_u32(&mut self, addr: u32, value: u32) -> Result<(), Fault> {
+        if !self.write_device(addr, value, 4) {
+            self.write_bytes(addr, &value.to_le_bytes())?;
+        }
+        self.record(addr, 4, value, true);
+        Ok(())
+    }
+
+    /// Reads a null terminated string starting at `addr`
+    pub fn read_cstring(&self, addr: u32) -> Result<String, Fault> {
+        let mut out = String::new();
+        let mut addr = addr;
+        loop {
+            let b = self.read_u8(addr)?;
+            if b == 0 {
+                break;
+            }
+            out.push(b as char);
+            addr += 1;
+        }
+        Ok(out)
+    }
+}
+
+pub(crate) fn align_up(value: u32, align: u32) -> u32 {
+    value.div_ceil(align) * align
+}
@@ -0,0 +1,58 @@
+use super::{Peripheral, merge_lanes};
+
+/// Offset of the LED register, bit `n` lights LED `n`
+pub const GPIO_LEDS: u32 = 0x0;
+/// Offset of the button register, bit `n` is set while button `n` is held
+pub const GPIO_BUTTONS: u32 = 0x4;
+pub const LED_COUNT: u32 = 8;
+pub const BUTTON_COUNT: u32 = 4;
+
+/// A bank of LEDs the program lights and buttons the user presses
+#[derive(Debug, Clone, Default)]
+pub struct Gpio {
+    leds: u32,
+    buttons: u32,
+}
+
+impl Gpio {
+    /// Bit `n` is set while LED `n` is lit
+    pub fn leds(&self) -> u32 {
+        self.leds
+    }
+
+    pub fn set_button(&mut self, button: u32, pressed: bool) {
+        match pressed {
+            true => self.buttons |= 1 << button,
+            false => self.buttons &= !(1 << button),
+        }
+    }
+}
+
+impl Peripheral for Gpio {
+    fn name(&self) -> &str {
+        "gpio"
+    }
+
+    fn size(&self) -> u32 {
+        8
+    }
+
+    fn read(&mut self, offset: u32) -> u32 {
+        match offset {
+            GPIO_LEDS => self.leds,
+            GPIO_BUTTONS => self.buttons,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: u32, value: u32, mask: u32) {
+        if offset == GPIO_LEDS {
+            self.leds = merge_lanes(self.leds, value, mask) & ((1 << LED_COUNT) - 1);
+        }
+    }
+
+    /// The LEDs go out, the buttons are held by the user so stay as they are
+    fn reset(&mut self) {
+        self.leds = 0;
+    }
+}
@@ -0,0 +1,169 @@
+mod gpio;
+mod timer;
+mod uart;
+
+pub use gpio::{BUTTON_COUNT, Gpio, LED_COUNT};
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+pub use timer::Timer;
+pub use uart::Uart;
+
+/// Base addresses of the devices of a [`Board`], above the program and its stack
+pub const UART_BASE: u32 = 0x1000_0000;
+pub const TIMER_BASE: u32 = 0x1000_1000;
+pub const GPIO_BASE: u32 = 0x1000_2000;
+
+/// A memory mapped device. Its registers are words at offsets from the base
+/// address it is mapped at, byte and halfword accesses see the lanes of the
+/// word they fall in and stores leave the other lanes alone.
+pub trait Peripheral: Send {
+    /// Identifies the device when debugging, e.g. `uart`
+    fn name(&self) -> &str;
+
+    /// Bytes of address space the device decodes
+    fn size(&self) -> u32;
+
+    /// Reads the register at `offset`, which is word aligned
+    fn read(&mut self, offset: u32) -> u32;
+
+    /// Writes the bits of `value` set in `mask` to the register at `offset`,
+    /// which is word aligned. `mask` covers the lanes of the store.
+    fn write(&mut self, offset: u32, value: u32, mask: u32);
+
+    /// Advances the device by one executed instruction
+    fn tick(&mut self) {}
+
+    /// `true` while the device requests an interrupt
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Text written to the console since the last call
+    fn take_output(&mut self) -> String {
+        String::new()
+    }
+
+    /// Returns the device to its state when the program was loaded
+    fn reset(&mut self) {}
+}
+
+/// `old` with the bits set in `mask` replaced by those of `value`
+pub(crate) fn merge_lanes(old: u32, value: u32, mask: u32) -> u32 {
+    old & !mask | value & mask
+}
+
+/// A device shared by the memory it is mapped into and whoever else needs to
+/// see it, such as a front end drawing LEDs while the program runs
+pub type SharedPeripheral = Arc<Mutex<dyn Peripheral>>;
+
+/// Devices mapped into the address space, decoded before the memory regions
+#[derive(Clone, Default)]
+pub(crate) struct Bus {
+    /// Base address, size and the device
+    devices: Vec<(u32, u32, SharedPeripheral)>,
+}
+
+impl Bus {
+    pub(crate) fn map(&mut self, base: u32, device: SharedPeripheral) {
+        let size = device.lock().unwrap().size();
+        self.devices.push((base, size, device));
+    }
+
+    /// The device decoding `addr` and the offset of `addr` into it
+    fn device(&self, addr: u32) -> Option<(&SharedPeripheral, u32)> {
+        self.devices.iter().find_map(|(base, size, device)| {
+            let offset = addr.wrapping_sub(*base);
+            (offset < *size).then_some((device, offset))
+        })
+    }
+
+    /// The word holding `addr` shifted so that `addr` is its lowest byte,
+    /// `None` if no device decodes `addr`
+    pub(crate) fn read(&self, addr: u32) -> Option<u32> {
+        let (device, offset) = self.device(addr)?;
+        let word = device.lock().unwrap().read(offset & !3);
+        Some(word >> (8 * (offset & 3)))
+    }
+
+    /// Writes the low `size` bytes of `value` to the lanes starting at
+    /// `addr`, `false` if no device decodes `addr`
+    pub(crate) fn write(&self, addr: u32, value: u32, size: u32) -> bool {
+        let Some((device, offset)) = self.device(addr) else {
+            return false;
+        };
+        let mask = match size {
+            1 => 0xff,
+            2 => 0xffff,
+            _ => u32::MAX,
+        };
+        let shift = 8 * (offset & 3);
+        device
+            .lock()
+            .unwrap()
+            .write(offset & !3, value << shift, mask << shift);
+        true
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    /// `true` if any device requests an interrupt
+    pub(crate) fn irq(&self) -> bool {
+        self.devices
+            .iter()
+            .any(|(_, _, device)| device.lock().unwrap().irq())
+    }
+
+    /// Ticks every device, returning the console output they produced
+    pub(crate) fn tick(&self) -> String {
+        let mut output = String::new();
+        for (_, _, device) in &self.devices {
+            let mut device = device.lock().unwrap();
+            device.tick();
+            output.push_str(&device.take_output());
+        }
+        output
+    }
+
+    pub(crate) fn reset(&self) {
+        for (_, _, device) in &self.devices {
+            device.lock().unwrap().reset();
+        }
+    }
+}
+
+impl fmt::Debug for Bus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.devices.iter().map(|(base, _, device)| {
+                format!("{} at {:#010x}", device.lock().unwrap().name(), base)
+            }))
+            .finish()
+    }
+}
+
+/// The devices of the lab board, a UART, a timer and a bank of LEDs and buttons
+#[derive(Clone, Default)]
+pub struct Board {
+    pub uart: Arc<Mutex<Uart>>,
+    pub timer: Arc<Mutex<Timer>>,
+    pub gpio: Arc<Mutex<Gpio>>,
+}
+
+impl Board {
+    pub fn new() -> Board {
+        Board::default()
+    }
+
+    /// Every device of the board with the address it is mapped at
+    pub fn devices(&self) -> [(u32, SharedPeripheral); 3] {
+        [
+            (UART_BASE, self.uart.clone()),
+            (TIMER_BASE, self.timer.clone()),
+            (GPIO_BASE, self.gpio.clone()),
+        ]
+    }
+}
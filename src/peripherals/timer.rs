@@ -0,0 +1,66 @@
+use super::{Peripheral, merge_lanes};
+
+/// Offset of the counter, incremented after every instruction
+pub const TIMER_COUNT: u32 = 0x0;
+/// Offset of the compare register, the timer matches when the counter reaches it
+pub const TIMER_COMPARE: u32 = 0x4;
+/// Offset of the control register, see [`TIMER_IRQ_ENABLE`]
+pub const TIMER_CONTROL: u32 = 0x8;
+/// Offset of the status register, bit 0 is set on a match and cleared by writing 1
+pub const TIMER_STATUS: u32 = 0xc;
+/// Set in the control register to request an interrupt while a match is pending
+pub const TIMER_IRQ_ENABLE: u32 = 1 << 0;
+
+/// A free running counter that can interrupt when it reaches a value
+#[derive(Debug, Clone, Default)]
+pub struct Timer {
+    count: u32,
+    compare: u32,
+    control: u32,
+    matched: bool,
+}
+
+impl Peripheral for Timer {
+    fn name(&self) -> &str {
+        "timer"
+    }
+
+    fn size(&self) -> u32 {
+        16
+    }
+
+    fn read(&mut self, offset: u32) -> u32 {
+        match offset {
+            TIMER_COUNT => self.count,
+            TIMER_COMPARE => self.compare,
+            TIMER_CONTROL => self.control,
+            TIMER_STATUS => self.matched as u32,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: u32, value: u32, mask: u32) {
+        match offset {
+            TIMER_COUNT => self.count = merge_lanes(self.count, value, mask),
+            TIMER_COMPARE => self.compare = merge_lanes(self.compare, value, mask),
+            TIMER_CONTROL => self.control = merge_lanes(self.control, value, mask),
+            TIMER_STATUS if value & mask & 1 != 0 => self.matched = false,
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self) {
+        self.count = self.count.wrapping_add(1);
+        if self.count == self.compare {
+            self.matched = true;
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.matched && self.control & TIMER_IRQ_ENABLE != 0
+    }
+
+    fn reset(&mut self) {
+        *self = Timer::default();
+    }
+}
@@ -0,0 +1,71 @@
+use super::Peripheral;
+use std::collections::VecDeque;
+
+/// Offset of the data register, writing it transmits a byte and reading it
+/// takes the oldest received byte
+pub const UART_DATA: u32 = 0x0;
+/// Offset of the status register, see [`RX_READY`] and [`TX_READY`]
+pub const UART_STATUS: u32 = 0x4;
+/// Set in the status register while a received byte is waiting
+pub const RX_READY: u32 = 1 << 0;
+/// Set in the status register when a byte can be transmitted, always
+pub const TX_READY: u32 = 1 << 1;
+
+/// Bytes the receive FIFO holds, later ones are dropped
+const FIFO_SIZE: usize = 16;
+
+/// A serial port connected to the console
+#[derive(Debug, Clone, Default)]
+pub struct Uart {
+    received: VecDeque<u8>,
+    transmitted: String,
+}
+
+impl Uart {
+    /// Queues a byte typed into the console, dropped if the FIFO is full
+    pub fn receive(&mut self, byte: u8) {
+        if self.received.len() < FIFO_SIZE {
+            self.received.push_back(byte);
+        }
+    }
+}
+
+impl Peripheral for Uart {
+    fn name(&self) -> &str {
+        "uart"
+    }
+
+    fn size(&self) -> u32 {
+        8
+    }
+
+    fn read(&mut self, offset: u32) -> u32 {
+        match offset {
+            UART_DATA => self.received.pop_front().unwrap_or(0) as u32,
+            UART_STATUS => {
+                TX_READY
+                    | if self.received.is_empty() {
+                        0
+                    } else {
+                        RX_READY
+                    }
+            }
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: u32, value: u32, mask: u32) {
+        // the character is the lowest lane
+        if offset == UART_DATA && mask & 0xff != 0 {
+            self.transmitted.push(value as u8 as char);
+        }
+    }
+
+    fn take_output(&mut self) -> String {
+        std::mem::take(&mut self.transmitted)
+    }
+
+    fn reset(&mut self) {
+        *self = Uart::default();
+    }
+}
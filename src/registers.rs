@@ -1,3 +1,4 @@
+use crate::status_flags::ProcessorMode;
 use capstone::{
     RegId,
     arch::arm::ArmReg::{
@@ -6,7 +7,9 @@ use capstone::{
 };
 use std::ops::{Index, IndexMut};
 
-#[derive(Default, Debug)]
+/// The registers of the current mode, the copies belonging to the other modes
+/// are kept in `banks` and swapped in by [`Registers::switch_mode`]
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
 pub struct Registers {
     pub r0: i32,
     pub r1: i32,
@@ -25,13 +28,81 @@ pub struct Registers {
     pub r14_lr: i32,
     pub r15_pc: i32,
     pub apsr: i32,
+    banks: Banks,
+}
+
+/// Registers not visible in the current mode, A2.3, p43 from DDI01001 spec
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+struct Banks {
+    /// `r13` and `r14` of every mode, indexed by [`ProcessorMode::bank`]
+    sp_lr: [(i32, i32); 6],
+    /// `r8` to `r12` of FIQ mode, or of every other mode while in FIQ mode
+    r8_r12: [i32; 5],
+    /// The SPSR of every exception mode, User and System mode have none
+    spsr: [i32; 6],
 }
 
 pub type RegTuple = (String, i32);
 
 impl Registers {
+    /// Starts in User mode, every mode with its stack pointer at [`crate::STACK_TOP`]
     pub fn new() -> Registers {
-        Registers::default()
+        let stack_top = crate::STACK_TOP as i32;
+        Registers {
+            r13_sp: stack_top,
+            apsr: ProcessorMode::User as i32,
+            banks: Banks {
+                sp_lr: [(stack_top, 0); 6],
+                ..Banks::default()
+            },
+            ..Registers::default()
+        }
+    }
+
+    /// The mode in the bottom bits of the CPSR
+    pub fn mode(&self) -> ProcessorMode {
+        ProcessorMode::from(self.apsr & 0b11111)
+    }
+
+    /// Changes the mode bits of the CPSR, banking the registers of the old mode
+    /// and bringing in those of `mode`
+    pub fn switch_mode(&mut self, mode: ProcessorMode) {
+        let old = self.mode();
+        let (from, to) = (old.bank(), mode.bank());
+        if from != to {
+            self.banks.sp_lr[from] = (self.r13_sp, self.r14_lr);
+            (self.r13_sp, self.r14_lr) = self.banks.sp_lr[to];
+
+            if (old == ProcessorMode::Fiq) != (mode == ProcessorMode::Fiq) {
+                let banked = &mut self.banks.r8_r12;
+                std::mem::swap(&mut self.r8, &mut banked[0]);
+                std::mem::swap(&mut self.r9, &mut banked[1]);
+                std::mem::swap(&mut self.r10, &mut banked[2]);
+                std::mem::swap(&mut self.r11, &mut banked[3]);
+                std::mem::swap(&mut self.r12, &mut banked[4]);
+            }
+        }
+        self.apsr = (self.apsr & !0b11111) | mode as i32;
+    }
+
+    /// Writes the whole CPSR, bringing in the banked registers of its mode
+    pub fn set_cpsr(&mut self, value: i32) {
+        self.switch_mode(ProcessorMode::from(value & 0b11111));
+        self.apsr = value;
+    }
+
+    /// The SPSR of the current mode, `None` in User and System mode
+    pub fn spsr(&self) -> Option<i32> {
+        let mode = self.mode();
+        mode.has_spsr().then(|| self.banks.spsr[mode.bank()])
+    }
+
+    pub fn spsr_mut(&mut self) -> Option<&mut i32> {
+        let mode = self.mode();
+        match mode.has_spsr() {
+            true => Some(&mut self.banks.spsr[mode.bank()]),
+            false => None,
+        }
     }
 
     pub fn to_ui_format(&self) -> Vec<RegTuple> {
@@ -53,6 +124,7 @@ impl Registers {
             ("r14/lr".into(), self.r14_lr),
             ("r15/pc".into(), self.r15_pc),
             ("apsr".into(), self.apsr),
+            ("spsr".into(), self.spsr().unwrap_or(0)),
         ]
     }
 
@@ -73,10 +145,40 @@ impl Registers {
         self.r13_sp = ui_regs[13].1;
         self.r14_lr = ui_regs[14].1;
         self.r15_pc = ui_regs[15].1;
-        self.apsr = ui_regs[16].1;
+
+        // a new mode brings in its own banked registers and SPSR
+        let apsr = ui_regs[16].1;
+        let mode = ProcessorMode::from(apsr & 0b11111);
+        if mode == self.mode() {
+            if let Some(spsr) = self.spsr_mut() {
+                *spsr = ui_regs[17].1;
+            }
+        } else {
+            self.switch_mode(mode);
+        }
+        self.apsr = apsr;
     }
 }
 
+/// Architectural register number of `reg_id`, `r0` is 0 and `pc` is 15
+pub fn reg_number(reg_id: &RegId) -> u16 {
+    match reg_id.0 as u32 {
+        reg if (ARM_REG_R0..=ARM_REG_R12).contains(&reg) => (reg - ARM_REG_R0) as u16,
+        ARM_REG_R13 => 13,
+        ARM_REG_R14 => 14,
+        ARM_REG_R15 => 15,
+        reg => panic!("Register {} is not a general purpose register", reg),
+    }
+}
+
+/// `true` if `reg_id` can be used to index [`Registers`]
+pub fn is_mapped(reg_id: &RegId) -> bool {
+    matches!(
+        reg_id.0 as u32,
+        ARM_REG_R13 | ARM_REG_R14 | ARM_REG_R15 | ARM_REG_APSR | ARM_REG_SPSR
+    ) || (ARM_REG_R0..=ARM_REG_R12).contains(&(reg_id.0 as u32))
+}
+
 impl Index<u16> for Registers {
     type Output = i32;
 
@@ -148,7 +250,9 @@ impl Index<&RegId> for Registers {
             ARM_REG_R13 => &self.r13_sp,
             ARM_REG_R14 => &self.r14_lr,
             ARM_REG_R15 => &self.r15_pc,
-            ARM_REG_APSR | ARM_REG_SPSR => &self.apsr,
+            ARM_REG_APSR => &self.apsr,
+            // User and System mode have no SPSR, reading it is unpredictable
+            ARM_REG_SPSR => &self.banks.spsr[self.mode().bank()],
             _ => panic!(
                 "index out of bounds: the len is 16 but the index is {}",
                 reg
@@ -168,7 +272,11 @@ impl IndexMut<&RegId> for Registers {
             ARM_REG_R13 => &mut self.r13_sp,
             ARM_REG_R14 => &mut self.r14_lr,
             ARM_REG_R15 => &mut self.r15_pc,
-            ARM_REG_APSR | ARM_REG_SPSR => &mut self.apsr,
+            ARM_REG_APSR => &mut self.apsr,
+            ARM_REG_SPSR => {
+                let bank = self.mode().bank();
+                &mut self.banks.spsr[bank]
+            }
             _ => panic!(
                 "index out of bounds: the len is 16 but the index is {}",
                 reg
@@ -6,10 +6,16 @@ pub struct StatusFlags {
     pub carry: bool,
     pub overflow: bool,
 
+    /// The T bit, set while executing Thumb instructions
+    pub thumb: bool,
+
     pub processor_mode: ProcessorMode,
 }
 
-#[derive(Debug, Clone, Copy)]
+/// The T bit of the CPSR
+pub(crate) const THUMB_BIT: i32 = 1 << 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ProcessorMode {
     User = 0b10000,
     Fiq = 0b10001,
@@ -35,6 +41,26 @@ impl From<i32> for ProcessorMode {
     }
 }
 
+impl ProcessorMode {
+    /// Index of the set of banked registers used by the mode, User and System
+    /// mode share theirs
+    pub(crate) fn bank(self) -> usize {
+        match self {
+            ProcessorMode::User | ProcessorMode::System => 0,
+            ProcessorMode::Fiq => 1,
+            ProcessorMode::Irq => 2,
+            ProcessorMode::Supervisor => 3,
+            ProcessorMode::Abort => 4,
+            ProcessorMode::Undefined => 5,
+        }
+    }
+
+    /// `true` for the modes entered on an exception, which have an SPSR
+    pub fn has_spsr(self) -> bool {
+        self.bank() != 0
+    }
+}
+
 impl StatusFlags {
     pub fn new() -> StatusFlags {
         StatusFlags {
@@ -42,11 +68,18 @@ impl StatusFlags {
             zero: false,
             carry: false,
             overflow: false,
+            thumb: false,
             processor_mode: ProcessorMode::User,
         }
     }
 }
 
+impl Default for StatusFlags {
+    fn default() -> Self {
+        StatusFlags::new()
+    }
+}
+
 impl From<i32> for StatusFlags {
     fn from(n: i32) -> Self {
         StatusFlags {
@@ -54,6 +87,7 @@ impl From<i32> for StatusFlags {
             zero: n & (1 << 30) != 0,
             carry: n & (1 << 29) != 0,
             overflow: n & (1 << 28) != 0,
+            thumb: n & THUMB_BIT != 0,
             processor_mode: ProcessorMode::from(n & 0b11111),
         }
     }
@@ -86,6 +120,12 @@ pub fn update_from_flags(apsr: i32, flags: &StatusFlags) -> i32 {
         ans &= -1 ^ (1 << 28)
     }
 
+    if flags.thumb {
+        ans |= THUMB_BIT;
+    } else {
+        ans &= -1 ^ THUMB_BIT
+    }
+
     ans &= -1 ^ 0b11111;
     ans |= flags.processor_mode as i32;
     ans
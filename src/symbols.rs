@@ -0,0 +1,149 @@
+use crate::Memory;
+use goblin::elf::{
+    Elf,
+    sym::{STT_FILE, STT_SECTION},
+};
+use std::ops::Range;
+
+/// A label and the address it refers to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub address: u32,
+}
+
+/// What follows a `$a`, `$t` or `$d` mapping symbol, which `as` emits where
+/// ARM code, Thumb code and data start
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mapping {
+    Arm,
+    Thumb,
+    Data,
+}
+
+/// Labels from the ELF `.symtab`, ordered by address
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+    /// Addresses of the mapping symbols, ordered by address
+    mappings: Vec<(u32, Mapping)>,
+}
+
+impl SymbolTable {
+    /// Reads the labels defined in sections mapped by `memory`. Section and
+    /// file symbols are skipped, the `$a`/`$t`/`$d` mapping symbols are kept
+    /// apart from the labels.
+    pub fn from_elf(elf: &Elf, memory: &Memory) -> SymbolTable {
+        let mut mappings = Vec::new();
+        let mut symbols = elf
+            .syms
+            .iter()
+            .filter(|sym| !matches!(sym.st_type(), STT_SECTION | STT_FILE))
+            .filter_map(|sym| {
+                let name = elf.strtab.get_at(sym.st_name)?;
+                if name.is_empty() {
+                    return None;
+                }
+
+                let address = if elf.is_object_file() {
+                    let header = elf.section_headers.get(sym.st_shndx)?;
+                    let section = elf.shdr_strtab.get_at(header.sh_name)?;
+                    memory
+                        .region(section)?
+                        .base
+                        .wrapping_add(sym.st_value as u32)
+                } else {
+                    sym.st_value as u32
+                };
+
+                // mapping symbols may have a suffix, `$d.1`
+                if let Some(kind) = name.strip_prefix('$') {
+                    match kind.split('.').next() {
+                        Some("a") => mappings.push((address, Mapping::Arm)),
+                        Some("t") => mappings.push((address, Mapping::Thumb)),
+                        Some("d") => mappings.push((address, Mapping::Data)),
+                        _ => {}
+                    }
+                    return None;
+                }
+
+                Some(Symbol {
+                    name: name.to_string(),
+                    address,
+                })
+            })
+            .collect::<Vec<Symbol>>();
+        symbols.sort_by_key(|symbol| symbol.address);
+        mappings.sort_by_key(|(address, _)| *address);
+
+        SymbolTable { symbols, mappings }
+    }
+
+    /// A table of `symbols` defined outside of an ELF file, e.g. by the
+    /// built-in assembler, all code is ARM
+    pub(crate) fn new(mut symbols: Vec<Symbol>) -> SymbolTable {
+        symbols.sort_by_key(|symbol| symbol.address);
+        SymbolTable {
+            symbols,
+            mappings: Vec::new(),
+        }
+    }
+
+    /// What `address` holds according to the last mapping symbol before it,
+    /// ARM code if there is none
+    pub fn mapping(&self, address: u32) -> Mapping {
+        let index = self
+            .mappings
+            .partition_point(|(start, _)| *start <= address);
+        match index {
+            0 => Mapping::Arm,
+            _ => self.mappings[index - 1].1,
+        }
+    }
+
+    /// Splits `range` where the mapping changes
+    pub fn mapping_ranges(&self, range: Range<u32>) -> Vec<(Range<u32>, Mapping)> {
+        let starts = self
+            .mappings
+            .iter()
+            .filter(|(start, _)| range.contains(start))
+            .map(|(start, _)| *start);
+
+        let mut ranges: Vec<(Range<u32>, Mapping)> = Vec::new();
+        for start in std::iter::once(range.start).chain(starts) {
+            let mapping = self.mapping(start);
+            if ranges.last().is_some_and(|(_, last)| *last == mapping) {
+                continue;
+            }
+            if let Some((last, _)) = ranges.last_mut() {
+                last.end = start;
+            }
+            ranges.push((start..range.end, mapping));
+        }
+        ranges
+    }
+
+    /// Labels placed at `address`, in the order they were defined
+    pub fn labels_at(&self, address: u32) -> impl Iterator<Item = &str> {
+        self.symbols
+            .iter()
+            .filter(move |symbol| symbol.address == address)
+            .map(|symbol| symbol.name.as_str())
+    }
+
+    /// The first label placed at `address`
+    pub fn name(&self, address: u32) -> Option<&str> {
+        self.labels_at(address).next()
+    }
+
+    pub fn address(&self, name: &str) -> Option<u32> {
+        self.symbols
+            .iter()
+            .find(|symbol| symbol.name == name)
+            .map(|symbol| symbol.address)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter()
+    }
+}
@@ -1,19 +1,59 @@
-use crate::{Registers, new_capstone};
-use std::io::Write;
+use crate::{
+    Access, Assembler, AssemblyCache, Board, Cycles, Diagnostic, ExecError, Fault, Flag, History,
+    LineTable, Machine, Memory, MemoryAccess, ProcessorMode, Registers, Severity, StatusFlags,
+    StopReason, Toolchain, ToolchainError, ToolchainSource, Trace, TraceFormat, Watch,
+    alu::{self, Opcode, Shift},
+    decode::{
+        Condition, DecodedInsn, Indexing, Offset, Operand2, ShiftAmount, TransferSize, decode,
+        from_capstone,
+    },
+    new_capstone, parse_diagnostics,
+};
+use std::{collections::HashSet, io::Write};
 use tempfile::{self, NamedTempFile};
 
-fn mock_print_program(buf: &'static str, print: &mut impl FnMut(String)) -> Registers {
+fn mock_run_program(
+    buf: &'static str,
+    print: &mut impl FnMut(String),
+) -> (Registers, Result<StopReason, ExecError>) {
     let cs = new_capstone();
     let mut input_file: NamedTempFile = NamedTempFile::new().unwrap();
     write!(input_file, "{}", buf).unwrap();
     let input_path = input_file.path().as_os_str().to_owned();
-    let print_dism = |str| eprint!("{str}");
-    let (data_section, text_section, instrs) = crate::disassemble(&cs, input_path, print_dism);
+    let (mut memory, instrs, _) =
+        crate::disassemble(&cs, input_path, &Assembler::default()).unwrap();
 
     let mut regs = Registers::new();
-    crate::run_program(&cs, data_section, text_section, &mut regs, instrs, print);
+    let result = crate::run_program(&mut memory, instrs, &mut regs, &|| '\0', print, || false);
+
+    (regs, result)
+}
+
+fn mock_print_program(buf: &'static str, print: &mut impl FnMut(String)) -> Registers {
+    let (regs, result) = mock_run_program(buf, print);
+    result.unwrap();
+    regs
+}
+
+fn mock_program_error(buf: &'static str) -> (Registers, ExecError) {
+    let (regs, result) = mock_run_program(buf, &mut |_| {});
+    (regs, result.unwrap_err())
+}
 
-    return regs;
+fn mock_machine(buf: &'static str) -> Machine {
+    let cs = new_capstone();
+    let mut input_file: NamedTempFile = NamedTempFile::new().unwrap();
+    write!(input_file, "{}", buf).unwrap();
+    let input_path = input_file.path().as_os_str().to_owned();
+    let (memory, _, _) = crate::disassemble(&cs, input_path, &Assembler::default()).unwrap();
+
+    Machine::new(&cs, memory).unwrap()
+}
+
+/// The GNU assembler found on `PATH`. Tests using it are ignored, run them
+/// with `cargo test -- --ignored` where ARM binutils are installed.
+fn gnu_assembler() -> Assembler {
+    Assembler::Gnu(Toolchain::find(None).unwrap())
 }
 
 fn mock_program(buf: &'static str) -> Registers {
@@ -70,7 +110,7 @@ fn test_mov_shift() {
         mov r8, r1, ROR #2 // 0b01000000...
         mov r9, r1, ROR r1 // 0b10000000...
 
-        mov r10, r1, RRX // 0b1000000...
+        mov r10, r1, RRX // carry flag is shifted in
         ",
     );
 
@@ -89,11 +129,7 @@ fn test_mov_shift() {
     assert_eq!(regs.r8, 1073741824);
     assert_eq!(regs.r9, -2147483648);
 
-    // assert_eq!(regs[&RegId(ARM_REG_R10 as u16)], -2147483648);
-    /*
-    incorrect,
-    need to use carry flag as a 33rd bit
-    */
+    assert_eq!(regs.r10, 0);
 }
 
 #[test]
@@ -316,7 +352,7 @@ fn test_mrs() {
         ",
     );
     assert_eq!(regs.r0, 0x40000010);
-    assert_eq!(regs.r1, 0xc0000010u32 as i32);
+    assert_eq!(regs.r1, 0x80000010u32 as i32);
 }
 
 #[test]
@@ -341,3 +377,3018 @@ fn test_hello() {
     assert_eq!(out.len(), 1);
     assert_eq!(out[0], "hello\n");
 }
+
+#[test]
+fn test_ldr_pointer_arithmetic() {
+    let mut out: Vec<String> = Vec::new();
+
+    mock_print_program(
+        "
+        .section .data
+        hello:
+            .asciz \"Hello World!\\n\"
+
+        .section .text
+            ldr r0, =hello
+            add r0, r0, #6
+            swi 3
+            swi 2
+        ",
+        &mut |str| out.push(str),
+    );
+
+    assert_eq!(out, ["World!\n"]);
+}
+
+#[test]
+fn test_ldr_data_word() {
+    let regs = mock_program(
+        "
+        .section .data
+        values:
+            .word 42, -7
+
+        .section .text
+            ldr r0, =values
+            ldr r1, [r0]
+            ldr r2, [r0, #4]
+        ",
+    );
+
+    assert_eq!(regs.r1, 42);
+    assert_eq!(regs.r2, -7);
+    assert!(regs.r0 as u32 >= crate::memory::SECTION_ALIGN);
+}
+
+#[test]
+fn test_memory_little_endian() {
+    let mut memory = Memory::new();
+    memory.map(".data", 0x1000, vec![0; 8]);
+
+    memory.write_u32(0x1000, 0x12345678).unwrap();
+    assert_eq!(memory.read_u8(0x1000), Ok(0x78));
+    assert_eq!(memory.read_u16(0x1002), Ok(0x1234));
+
+    memory.write_u16(0x1004, 0x6968).unwrap();
+    assert_eq!(memory.read_cstring(0x1004), Ok("hi".to_string()));
+}
+
+#[test]
+fn test_memory_unmapped() {
+    let mut memory = Memory::new();
+    memory.map(".data", 0x1000, vec![0; 8]);
+    assert_eq!(
+        memory.read_u32(0x1006),
+        Err(Fault::OutOfBounds { address: 0x1006 })
+    );
+    assert_eq!(
+        memory.write_u8(0x2000, 0),
+        Err(Fault::OutOfBounds { address: 0x2000 })
+    );
+}
+
+#[test]
+fn test_str_ldr_offset() {
+    let regs = mock_program(
+        "
+        .section .data
+        buf:
+            .space 16
+
+        .section .text
+            ldr r0, =buf
+            mov r1, #0x12
+            mov r2, #0x34
+            str r1, [r0]
+            str r2, [r0, #4]
+            ldr r3, [r0]
+            ldr r4, [r0, #4]
+        ",
+    );
+
+    assert_eq!(regs.r3, 0x12);
+    assert_eq!(regs.r4, 0x34);
+}
+
+#[test]
+fn test_ldr_negative_offset() {
+    let regs = mock_program(
+        "
+        .section .data
+            .word 1, 2
+        end:
+
+        .section .text
+            ldr r0, =end
+            ldr r1, [r0, #-4]
+            ldr r2, [r0, #-8]
+        ",
+    );
+
+    assert_eq!(regs.r1, 2);
+    assert_eq!(regs.r2, 1);
+}
+
+#[test]
+fn test_ldr_pre_indexed_writeback() {
+    let regs = mock_program(
+        "
+        .section .data
+        values:
+            .word 1, 2, 3
+
+        .section .text
+            ldr r0, =values
+            mov r5, r0
+            ldr r1, [r0, #4]!
+            ldr r2, [r0, #4]!
+            sub r6, r0, r5
+        ",
+    );
+
+    assert_eq!(regs.r1, 2);
+    assert_eq!(regs.r2, 3);
+    assert_eq!(regs.r6, 8);
+}
+
+#[test]
+fn test_ldr_post_indexed() {
+    let regs = mock_program(
+        "
+        .section .data
+        values:
+            .word 1, 2, 3
+
+        .section .text
+            ldr r0, =values
+            mov r5, r0
+            ldr r1, [r0], #4
+            ldr r2, [r0], #4
+            sub r6, r0, r5
+            ldr r3, [r0], #-8
+            sub r7, r0, r5
+        ",
+    );
+
+    assert_eq!(regs.r1, 1);
+    assert_eq!(regs.r2, 2);
+    assert_eq!(regs.r3, 3);
+    assert_eq!(regs.r6, 8);
+    assert_eq!(regs.r7, 0);
+}
+
+#[test]
+fn test_ldr_register_offset() {
+    let regs = mock_program(
+        "
+        .section .data
+        values:
+            .word 10, 20, 30, 40
+        end:
+
+        .section .text
+            ldr r0, =values
+            ldr r6, =end
+            mov r2, #4
+            ldr r1, [r0, r2]
+            ldr r3, [r0, r2, lsl #1]
+            ldr r4, [r6, -r2]
+            ldr r5, [r6, -r2, lsl #2]
+        ",
+    );
+
+    assert_eq!(regs.r1, 20);
+    assert_eq!(regs.r3, 30);
+    assert_eq!(regs.r4, 40);
+    assert_eq!(regs.r5, 10);
+}
+
+#[test]
+fn test_ldr_register_offset_most_negative() {
+    let regs = mock_program(
+        "
+        .section .data
+        values:
+            .word 10
+
+        .section .text
+            ldr r0, =values
+            mov r1, #0x80000000
+            add r2, r0, r1
+            ldr r3, [r2, -r1]
+        ",
+    );
+
+    assert_eq!(regs.r3, 10);
+}
+
+#[test]
+fn test_ldr_register_offset_writeback() {
+    let regs = mock_program(
+        "
+        .section .data
+        values:
+            .word 10, 20, 30, 40
+
+        .section .text
+            ldr r0, =values
+            mov r5, r0
+            mov r2, #1
+            ldr r1, [r0], r2, lsl #3
+            ldr r3, [r0, r2, lsl #2]!
+            sub r6, r0, r5
+        ",
+    );
+
+    assert_eq!(regs.r1, 10);
+    assert_eq!(regs.r3, 40);
+    assert_eq!(regs.r6, 12);
+}
+
+#[test]
+fn test_byte() {
+    let regs = mock_program(
+        "
+        .section .data
+        buf:
+            .space 4
+
+        .section .text
+            ldr r0, =buf
+            mov r1, #0xff
+            strb r1, [r0, #1]
+            mov r1, #0x7f
+            strb r1, [r0], #2
+            ldrb r2, [r0, #-1]
+            ldrsb r3, [r0, #-1]
+            ldrb r4, [r0, #-2]!
+            ldrsb r5, [r0]
+            ldr r6, [r0]
+        ",
+    );
+
+    assert_eq!(regs.r2, 0xff);
+    assert_eq!(regs.r3, -1);
+    assert_eq!(regs.r4, 0x7f);
+    assert_eq!(regs.r5, 0x7f);
+    assert_eq!(regs.r6, 0xff7f);
+}
+
+#[test]
+fn test_halfword() {
+    let regs = mock_program(
+        "
+        .section .data
+        buf:
+            .space 8
+
+        .section .text
+            ldr r0, =buf
+            mvn r1, #1 // 0xfffffffe
+            mov r2, #2
+            strh r1, [r0, #2]
+            strh r2, [r0], #4
+            ldrh r3, [r0, #-4]
+            ldrsh r4, [r0, #-2]
+            ldrh r5, [r0, -r2]!
+            ldrsh r6, [r0], -r2
+            ldr r7, [r0]
+        ",
+    );
+
+    assert_eq!(regs.r3, 2);
+    assert_eq!(regs.r4, -2);
+    assert_eq!(regs.r5, 0xfffe);
+    assert_eq!(regs.r6, -2);
+    assert_eq!(regs.r7, 0xfffe0002_u32 as i32);
+}
+
+#[test]
+fn test_ldrt_strt() {
+    let regs = mock_program(
+        "
+        .section .data
+        buf:
+            .space 8
+
+        .section .text
+            ldr r0, =buf
+            mov r5, r0
+            mov r1, #0x45
+            strt r1, [r0], #4
+            strbt r1, [r0], #-4
+            ldrt r2, [r0], #4
+            ldrbt r3, [r0], #4
+            sub r6, r0, r5
+        ",
+    );
+
+    assert_eq!(regs.r2, 0x45);
+    assert_eq!(regs.r3, 0x45);
+    assert_eq!(regs.r6, 8);
+}
+
+#[test]
+fn test_stack_frame() {
+    let regs = mock_program(
+        "
+            mov r0, sp
+            mov r1, #7
+            mov r2, #9
+            str r1, [sp, #-4]!
+            str r2, [sp, #-4]!
+            ldr r3, [sp, #4]
+            ldr r4, [sp], #4
+            ldr r5, [sp], #4
+            sub r6, r0, sp
+        ",
+    );
+
+    assert_eq!(regs.r3, 7);
+    assert_eq!(regs.r4, 9);
+    assert_eq!(regs.r5, 7);
+    assert_eq!(regs.r6, 0);
+    assert_eq!(regs.r13_sp, crate::STACK_TOP as i32);
+}
+
+#[test]
+fn test_swp() {
+    let regs = mock_program(
+        "
+        .section .data
+        value:
+            .word 5
+
+        .section .text
+            ldr r0, =value
+            mov r1, #6
+            swp r2, r1, [r0]
+            mvn r1, #0xf8 // 0xffffff07, only the low byte is stored
+            swpb r3, r1, [r0]
+            ldr r4, [r0]
+        ",
+    );
+
+    assert_eq!(regs.r2, 5);
+    assert_eq!(regs.r3, 6);
+    assert_eq!(regs.r4, 7);
+}
+
+#[test]
+fn test_strcpy() {
+    let mut out: Vec<String> = Vec::new();
+
+    mock_print_program(
+        "
+        .section .data
+        src:
+            .asciz \"copied\"
+        dst:
+            .space 8
+
+        .section .text
+            ldr r0, =src
+            ldr r1, =dst
+        loop:
+            ldrb r2, [r0], #1
+            strb r2, [r1], #1
+            cmp r2, #0
+            bne loop
+
+            ldr r0, =dst
+            swi 3
+            swi 2
+        ",
+        &mut |str| out.push(str),
+    );
+
+    assert_eq!(out, ["copied"]);
+}
+
+#[test]
+fn test_stm_ldm_modes() {
+    let regs = mock_program(
+        "
+        .section .data
+        up:
+            .space 16
+        down:
+            .space 16
+        down_end:
+            .space 4
+
+        .section .text
+            mov r1, #1
+            mov r2, #2
+            ldr r0, =up
+            stmia r0, {r1, r2}
+            ldr r3, [r0, #4]
+
+            stmib r0, {r1, r2}
+            ldr r4, [r0, #8]
+
+            ldr r0, =down_end
+            stmda r0, {r1, r2}
+            ldr r5, [r0, #-4]
+
+            stmdb r0, {r1, r2}
+            ldr r6, [r0, #-4]
+
+            ldr r0, =up
+            ldmib r0, {r7, r8}
+            ldr r0, =down_end
+            ldmdb r0, {r9, r10}
+        ",
+    );
+
+    assert_eq!(regs.r3, 2);
+    assert_eq!(regs.r4, 2);
+    assert_eq!(regs.r5, 1);
+    assert_eq!(regs.r6, 2);
+    assert_eq!((regs.r7, regs.r8), (1, 2));
+    assert_eq!((regs.r9, regs.r10), (1, 2));
+}
+
+#[test]
+fn test_stm_ldm_writeback() {
+    let regs = mock_program(
+        "
+        .section .data
+        buf:
+            .space 16
+
+        .section .text
+            ldr r0, =buf
+            mov r5, r0
+            mov r1, #1
+            mov r2, #2
+            mov r3, #3
+            stmia r0!, {r1-r3}
+            sub r6, r0, r5
+            ldmdb r0!, {r7-r9}
+            sub r10, r0, r5
+        ",
+    );
+
+    assert_eq!(regs.r6, 12);
+    assert_eq!((regs.r7, regs.r8, regs.r9), (1, 2, 3));
+    assert_eq!(regs.r10, 0);
+}
+
+#[test]
+fn test_stack_aliases() {
+    let regs = mock_program(
+        "
+            mov r1, #1
+            mov r2, #2
+            // leave room above sp for the empty stacks
+            sub sp, sp, #16
+
+            stmfd sp!, {r1, r2}
+            ldmfd sp!, {r3, r4}
+
+            stmed sp!, {r1, r2}
+            ldmed sp!, {r5, r6}
+
+            stmfa sp!, {r1, r2}
+            ldmfa sp!, {r7, r8}
+
+            stmea sp!, {r1, r2}
+            ldmea sp!, {r9, r10}
+            add sp, sp, #16
+        ",
+    );
+
+    assert_eq!((regs.r3, regs.r4), (1, 2));
+    assert_eq!((regs.r5, regs.r6), (1, 2));
+    assert_eq!((regs.r7, regs.r8), (1, 2));
+    assert_eq!((regs.r9, regs.r10), (1, 2));
+    assert_eq!(regs.r13_sp, crate::STACK_TOP as i32);
+}
+
+#[test]
+fn test_push_pop() {
+    let regs = mock_program(
+        "
+            mov r4, #4
+            mov r5, #5
+            push {r4, r5}
+            mov r4, #0
+            mov r5, #0
+            ldr r0, [sp]
+            pop {r4, r5}
+        ",
+    );
+
+    assert_eq!(regs.r0, 4);
+    assert_eq!(regs.r4, 4);
+    assert_eq!(regs.r5, 5);
+    assert_eq!(regs.r13_sp, crate::STACK_TOP as i32);
+}
+
+#[test]
+fn test_function_call() {
+    let regs = mock_program(
+        "
+            mov r4, #1
+            mov r0, #5
+            bl double
+            mov r1, r0
+            bl double
+            swi 2
+
+        double:
+            stmfd sp!, {r4, lr}
+            mov r4, r0
+            add r0, r4, r4
+            ldmfd sp!, {r4, pc}
+        ",
+    );
+
+    assert_eq!(regs.r0, 20);
+    assert_eq!(regs.r1, 10);
+    assert_eq!(regs.r4, 1);
+    assert_eq!(regs.r13_sp, crate::STACK_TOP as i32);
+}
+
+#[test]
+fn test_return_mov_pc_lr() {
+    let regs = mock_program(
+        "
+            bl func
+            mov r1, #1
+            swi 2
+        func:
+            mov r0, lr
+            mov pc, lr
+        ",
+    );
+
+    assert_eq!(regs.r0, 4);
+    assert_eq!(regs.r1, 1);
+}
+
+fn nzcv(flags: &StatusFlags) -> u8 {
+    (flags.negative as u8) << 3
+        | (flags.zero as u8) << 2
+        | (flags.carry as u8) << 1
+        | flags.overflow as u8
+}
+
+#[test]
+fn test_alu_shift() {
+    use Shift::*;
+    // (shift, value, amount, carry in, result, carry out)
+    let cases = [
+        (Lsl, 0x1, 0, true, 0x1, true),
+        (Lsl, 0x80000001, 1, false, 0x2, true),
+        (Lsl, 0x1, 31, false, 0x80000000, false),
+        (Lsl, 0x1, 32, false, 0x0, true),
+        (Lsl, 0x1, 33, true, 0x0, false),
+        (Lsr, 0xf0, 0, true, 0xf0, true),
+        (Lsr, 0x3, 1, false, 0x1, true),
+        (Lsr, 0x80000000, 32, false, 0x0, true),
+        (Lsr, 0x80000000, 40, true, 0x0, false),
+        (Asr, 0x80000000, 4, true, 0xf8000000, false),
+        (Asr, 0x80000000, 32, false, 0xffffffff, true),
+        (Asr, 0x7fffffff, 100, true, 0x0, false),
+        (Ror, 0x1, 1, false, 0x80000000, true),
+        (Ror, 0x80000001, 32, false, 0x80000001, true),
+        (Ror, 0x2, 33, true, 0x1, false),
+        (Rrx, 0x1, 1, false, 0x0, true),
+        (Rrx, 0x2, 1, true, 0x80000001, false),
+    ];
+
+    for (kind, value, amount, carry_in, result, carry_out) in cases {
+        assert_eq!(
+            alu::shift(kind, value, amount, carry_in),
+            (result, carry_out),
+            "{:?} {:#x} by {}",
+            kind,
+            value,
+            amount
+        );
+    }
+}
+
+#[test]
+fn test_alu_rotated_immediate() {
+    // (immed_8, rotate_imm, carry in, result, carry out)
+    let cases = [
+        (0xff, 0, true, 0xff, true),
+        (0xff, 0, false, 0xff, false),
+        (0xff, 4, false, 0xff000000, true),
+        (0x1, 1, true, 0x40000000, false),
+        (0x2, 1, false, 0x80000000, true),
+    ];
+
+    for (immed_8, rotate_imm, carry_in, result, carry_out) in cases {
+        assert_eq!(
+            alu::rotated_immediate(immed_8, rotate_imm, carry_in),
+            (result, carry_out)
+        );
+    }
+}
+
+#[test]
+fn test_alu_add_with_carry() {
+    // (a, b, carry in, result, carry out, overflow)
+    let cases = [
+        (1, 1, false, 2, false, false),
+        (0xffffffff, 1, false, 0, true, false),
+        (0x7fffffff, 1, false, 0x80000000, false, true),
+        (0x80000000, 0x80000000, false, 0, true, true),
+        (0xffffffff, 0, true, 0, true, false),
+        (0x7fffffff, 0, true, 0x80000000, false, true),
+    ];
+
+    for (a, b, carry_in, result, carry_out, overflow) in cases {
+        assert_eq!(
+            alu::add_with_carry(a, b, carry_in),
+            (result, carry_out, overflow)
+        );
+    }
+}
+
+#[test]
+fn test_alu_data_processing() {
+    use Opcode::*;
+    // (opcode, rn, shifter operand, shifter carry, C in, V in, result, NZCV)
+    let cases = [
+        (Add, 0xffffffff, 1, false, false, false, 0, 0b0110),
+        (Add, 0x7fffffff, 1, false, false, false, 0x80000000, 0b1001),
+        (Adc, 1, 1, false, true, false, 3, 0b0000),
+        (Adc, 0xffffffff, 0, false, true, false, 0, 0b0110),
+        (Sub, 3, 1, false, false, false, 2, 0b0010),
+        (Sub, 1, 3, false, false, false, 0xfffffffe, 0b1000),
+        (Sub, 0x80000000, 1, false, false, false, 0x7fffffff, 0b0011),
+        (Sub, 5, 5, false, false, false, 0, 0b0110),
+        (Sbc, 3, 1, false, false, false, 1, 0b0010),
+        (Sbc, 3, 1, false, true, false, 2, 0b0010),
+        (Sbc, 0, 0, false, false, false, 0xffffffff, 0b1000),
+        (Rsb, 1, 3, false, false, false, 2, 0b0010),
+        (Rsb, 3, 1, false, false, false, 0xfffffffe, 0b1000),
+        (Rsc, 1, 3, false, false, false, 1, 0b0010),
+        (Rsc, 0x7fffffff, 0x80000000, false, true, false, 1, 0b0011),
+        (Cmp, 0, 1, false, false, false, 0xffffffff, 0b1000),
+        (Cmn, 0x7fffffff, 1, false, false, false, 0x80000000, 0b1001),
+        (And, 0xf0, 0x0f, true, false, true, 0, 0b0111),
+        (
+            Tst, 0x80000000, 0x80000000, false, true, false, 0x80000000, 0b1000,
+        ),
+        (Teq, 0x80000000, 0x80000000, false, false, false, 0, 0b0100),
+        (Eor, 0xff, 0x0f, false, false, false, 0xf0, 0b0000),
+        (Orr, 0, 0, true, false, false, 0, 0b0110),
+        (Bic, 0xff, 0x0f, false, false, true, 0xf0, 0b0001),
+        (Mov, 0, 0x80000000, true, false, true, 0x80000000, 0b1011),
+        (Mvn, 0, 0, false, true, false, 0xffffffff, 0b1000),
+    ];
+
+    for (opcode, rn, shifter, shifter_carry, carry, overflow, result, flags) in cases {
+        let mut flags_in = StatusFlags::new();
+        flags_in.carry = carry;
+        flags_in.overflow = overflow;
+
+        let (value, flags_out) =
+            alu::data_processing(opcode, rn, (shifter, shifter_carry), &flags_in);
+        assert_eq!(
+            (value, nzcv(&flags_out)),
+            (result, flags),
+            "{:?} {:#x}, {:#x}",
+            opcode,
+            rn,
+            shifter
+        );
+    }
+}
+
+#[test]
+fn test_shifter_carry() {
+    let regs = mock_program(
+        "
+        movs r0, #0xff000000
+        mrs r1, cpsr
+        mov r2, #1
+        movs r3, r2, lsr #1
+        mrs r4, cpsr
+        movs r5, r2, lsl #1
+        mrs r6, cpsr
+        ",
+    );
+
+    assert_eq!(regs.r1, 0xa0000010_u32 as i32);
+    assert_eq!(regs.r3, 0);
+    assert_eq!(regs.r4, 0x60000010);
+    assert_eq!(regs.r6, 0x00000010);
+}
+
+#[test]
+fn test_subs_loop() {
+    let regs = mock_program(
+        "
+            mov r0, #5
+            mov r1, #0
+        loop:
+            add r1, r1, #2
+            subs r0, r0, #1
+            bne loop
+        ",
+    );
+
+    assert_eq!(regs.r0, 0);
+    assert_eq!(regs.r1, 10);
+    assert_eq!(regs.apsr, 0x60000010);
+}
+
+#[test]
+fn test_signed_conditions() {
+    let regs = mock_program(
+        "
+            mov r0, #0
+            mov r1, #-1
+            cmp r1, r0
+            movlt r2, #1
+            movgt r3, #1
+            movle r4, #1
+            movge r5, #1
+            movpl r6, #1
+            movmi r7, #1
+            movhi r8, #1
+            movls r9, #1
+        ",
+    );
+
+    assert_eq!((regs.r2, regs.r3, regs.r4, regs.r5), (1, 0, 1, 0));
+    assert_eq!((regs.r6, regs.r7), (0, 1));
+    assert_eq!((regs.r8, regs.r9), (1, 0));
+}
+
+#[test]
+fn test_add_64_bit() {
+    let regs = mock_program(
+        "
+            // r1:r0 = 0x00000001_ffffffff
+            mvn r0, #0
+            mov r1, #1
+            // r3:r2 = 0x00000002_00000001
+            mov r2, #1
+            mov r3, #2
+
+            adds r4, r0, r2
+            adc r5, r1, r3
+        ",
+    );
+
+    assert_eq!(regs.r4, 0);
+    assert_eq!(regs.r5, 4);
+}
+
+#[test]
+fn test_sub_64_bit() {
+    let regs = mock_program(
+        "
+            // r1:r0 = 0x00000001_00000000
+            mov r0, #0
+            mov r1, #1
+            // r3:r2 = 0x00000000_00000001
+            mov r2, #1
+            mov r3, #0
+
+            subs r4, r0, r2
+            sbc r5, r1, r3
+        ",
+    );
+
+    assert_eq!(regs.r4, -1);
+    assert_eq!(regs.r5, 0);
+}
+
+#[test]
+fn test_rsb_rsc() {
+    let regs = mock_program(
+        "
+            mov r0, #5
+            rsb r1, r0, #0
+            rsb r2, r0, r0, lsl #2
+
+            // negate the 64 bit value r4:r3 = 0x00000000_00000001
+            mov r3, #1
+            mov r4, #0
+            rsbs r5, r3, #0
+            rsc r6, r4, #0
+        ",
+    );
+
+    assert_eq!(regs.r1, -5);
+    assert_eq!(regs.r2, 15);
+    assert_eq!(regs.r5, -1);
+    assert_eq!(regs.r6, -1);
+}
+
+#[test]
+fn test_sbcs_rscs_flags() {
+    let regs = mock_program(
+        "
+            mov r0, #0
+            cmp r0, #1 // clear carry
+            sbcs r1, r0, #0
+            mrs r2, cpsr
+
+            mov r3, #0x80000000
+            cmp r0, #0 // set carry
+            rscs r4, r0, r3
+            mrs r5, cpsr
+        ",
+    );
+
+    assert_eq!(regs.r1, -1);
+    assert_eq!(regs.r2, 0x80000010_u32 as i32);
+    assert_eq!(regs.r4, 0x80000000_u32 as i32);
+    assert_eq!(regs.r5, 0xa0000010_u32 as i32);
+}
+
+#[test]
+fn test_tst_teq() {
+    let regs = mock_program(
+        "
+            mov r0, #0b1010
+            tst r0, #0b0100
+            moveq r1, #1
+            tst r0, #0b1000
+            movne r2, #1
+
+            mov r3, #-3
+            mov r4, #-4
+            teq r3, r4
+            movpl r5, #1 // same sign
+            teq r3, r3
+            moveq r6, #1
+        ",
+    );
+
+    assert_eq!(regs.r1, 1);
+    assert_eq!(regs.r2, 1);
+    assert_eq!(regs.r5, 1);
+    assert_eq!(regs.r6, 1);
+}
+
+#[test]
+fn test_bic() {
+    let regs = mock_program(
+        "
+            mov r0, #0xff
+            bic r1, r0, #0x0f
+            mov r2, #0x3
+            bics r3, r0, r0
+            mrs r4, cpsr
+            bic r5, r0, r2, lsl #4
+        ",
+    );
+
+    assert_eq!(regs.r1, 0xf0);
+    assert_eq!(regs.r3, 0);
+    assert_eq!(regs.r4, 0x40000010);
+    assert_eq!(regs.r5, 0xcf);
+}
+
+#[test]
+fn test_alu_multiply_long() {
+    // (rm, rs, signed, accumulate, result)
+    let cases = [
+        (2, 3, false, 0, 6),
+        (0xffffffff, 0xffffffff, false, 0, 0xfffffffe_00000001),
+        (0xffffffff, 0xffffffff, true, 0, 1),
+        (0xffffffff, 2, true, 0, 0xffffffff_fffffffe),
+        (0x80000000, 0x80000000, true, 0, 0x40000000_00000000),
+        (0xffffffff, 1, true, 1, 0),
+        (1, 1, false, u64::MAX, 0),
+    ];
+
+    for (rm, rs, signed, accumulate, result) in cases {
+        assert_eq!(alu::multiply_long(rm, rs, signed, accumulate), result);
+    }
+}
+
+#[test]
+fn test_mul_mla() {
+    let regs = mock_program(
+        "
+            mov r0, #6
+            mov r1, #7
+            mul r2, r0, r1
+            mla r3, r0, r1, r0
+
+            // 0x10000 * 0x10000 wraps to 0
+            mov r4, #0x10000
+            muls r5, r4, r4
+            mrs r6, cpsr
+
+            mvn r7, #0 // -1
+            mlas r8, r7, r0, r1
+            mrs r9, cpsr
+        ",
+    );
+
+    assert_eq!(regs.r2, 42);
+    assert_eq!(regs.r3, 48);
+    assert_eq!(regs.r5, 0);
+    assert_eq!(regs.r6, 0x40000010);
+    assert_eq!(regs.r8, 1);
+    assert_eq!(regs.r9, 0x10);
+}
+
+#[test]
+fn test_long_multiply() {
+    let regs = mock_program(
+        "
+            mvn r0, #0 // 0xffffffff
+            mov r1, #2
+
+            umull r2, r3, r0, r1
+            smull r4, r5, r0, r1
+
+            mov r6, #1
+            mov r7, #0
+            umlal r6, r7, r0, r1
+
+            mov r8, #4
+            mov r9, #0
+            smlal r8, r9, r0, r1
+        ",
+    );
+
+    assert_eq!((regs.r2, regs.r3), (-2, 1));
+    assert_eq!((regs.r4, regs.r5), (-2, -1));
+    assert_eq!((regs.r6, regs.r7), (-1, 1));
+    assert_eq!((regs.r8, regs.r9), (2, 0));
+}
+
+#[test]
+fn test_long_multiply_flags() {
+    let regs = mock_program(
+        "
+            mvn r0, #0
+            mov r1, #1
+            smulls r2, r3, r0, r1
+            mrs r4, cpsr
+
+            mov r5, #0
+            umulls r6, r7, r0, r5
+            mrs r8, cpsr
+
+            umulls r9, r10, r0, r1
+            mrs r11, cpsr
+        ",
+    );
+
+    assert_eq!(regs.r4, 0x80000010_u32 as i32);
+    assert_eq!(regs.r8, 0x40000010);
+    assert_eq!(regs.r11, 0x10);
+}
+
+#[test]
+fn test_stop_reason() {
+    let (_, result) = mock_run_program("mov r0, #1", &mut |_| {});
+    assert_eq!(result, Ok(StopReason::EndOfProgram));
+
+    let (regs, result) = mock_run_program(
+        "
+        swi 2
+        mov r0, #1
+        ",
+        &mut |_| {},
+    );
+    assert_eq!(result, Ok(StopReason::Halted));
+    assert_eq!(regs.r0, 0);
+}
+
+#[test]
+fn test_error_unknown_swi() {
+    let (regs, err) = mock_program_error(
+        "
+        mov r0, #1
+        swi 99
+        mov r0, #2
+        ",
+    );
+    assert_eq!(err.fault, Fault::UnknownSwi(99));
+    assert_eq!(err.address, 4);
+    assert_eq!(err.instruction, "svc #0x63");
+    // pc is left on the faulting instruction
+    assert_eq!(regs.r15_pc, 4);
+    assert_eq!(regs.r0, 1);
+}
+
+#[test]
+fn test_error_out_of_bounds() {
+    let (_, err) = mock_program_error(
+        "
+        mov r0, #0x80000000
+        ldr r1, [r0]
+        ",
+    );
+    assert_eq!(
+        err.fault,
+        Fault::OutOfBounds {
+            address: 0x80000000
+        }
+    );
+    assert_eq!(err.address, 4);
+
+    let (regs, err) = mock_program_error(
+        "
+        mov r0, #0x80000000
+        str r0, [r0, #4]!
+        ",
+    );
+    assert_eq!(
+        err.fault,
+        Fault::OutOfBounds {
+            address: 0x80000004
+        }
+    );
+    assert_eq!(
+        err.to_string(),
+        "Out of bounds memory access at 0x80000004 at 0x00000004: `str r0, [r0, #4]!`"
+    );
+    assert_eq!(regs.r15_pc, 4);
+}
+
+#[test]
+fn test_error_fetch_out_of_bounds() {
+    let (regs, err) = mock_program_error(
+        "
+        mov r0, #0x7ffffffc
+        mov pc, r0
+        ",
+    );
+    assert_eq!(
+        err.fault,
+        Fault::OutOfBounds {
+            address: 0x7ffffffc
+        }
+    );
+    assert_eq!(
+        err.to_string(),
+        "Out of bounds memory access at 0x7ffffffc at 0x7ffffffc"
+    );
+    assert_eq!(regs.r15_pc, 0x7ffffffc);
+}
+
+#[test]
+fn test_error_unaligned() {
+    let (_, err) = mock_program_error(
+        "
+        ldr r0, =data
+        ldrh r1, [r0, #1]
+
+        .section .data
+        data:
+            .word 0
+        ",
+    );
+    assert_eq!(err.fault, Fault::UnalignedAccess { address: 0x1001 });
+
+    let (_, err) = mock_program_error(
+        "
+        mov r0, #6
+        mov pc, r0
+        ",
+    );
+    assert_eq!(err.fault, Fault::UnalignedAccess { address: 6 });
+}
+
+#[test]
+fn test_error_unimplemented() {
+    let (_, err) = mock_program_error(
+        "
+        mov r0, #0
+        .word 0xee010f10
+        ",
+    );
+    assert!(matches!(err.fault, Fault::Unimplemented(_)));
+    assert_eq!(err.address, 4);
+}
+
+#[test]
+fn test_machine_step() {
+    let mut machine = mock_machine(
+        "
+        mov r0, #1
+        mov r1, #2
+        swi 2
+        mov r2, #3
+        ",
+    );
+    let step = |machine: &mut Machine| machine.step(&|| '\0', &mut |_| {});
+
+    assert_eq!(step(&mut machine), Ok(StopReason::Step));
+    assert_eq!(machine.regs.r0, 1);
+    assert_eq!(machine.regs.r1, 0);
+    assert_eq!(machine.pc(), 4);
+
+    assert_eq!(step(&mut machine), Ok(StopReason::Step));
+    assert_eq!(machine.regs.r1, 2);
+
+    assert_eq!(step(&mut machine), Ok(StopReason::Halted));
+    assert_eq!(machine.pc(), 12);
+
+    assert_eq!(step(&mut machine), Ok(StopReason::Step));
+    assert_eq!(machine.regs.r2, 3);
+    assert_eq!(step(&mut machine), Ok(StopReason::EndOfProgram));
+}
+
+#[test]
+fn test_machine_run_until() {
+    let mut machine = mock_machine(
+        "
+            mov r0, #0
+        loop:
+            add r0, r0, #1
+            cmp r0, #3
+            blt loop
+            mov r1, #1
+        ",
+    );
+    let breakpoints = HashSet::from([4, 16]);
+    let run =
+        |machine: &mut Machine| machine.run_until(&breakpoints, &|| '\0', &mut |_| {}, || false);
+
+    for i in 0..3 {
+        assert_eq!(run(&mut machine), Ok(StopReason::Breakpoint(4)));
+        assert_eq!(machine.regs.r0, i);
+    }
+    assert_eq!(run(&mut machine), Ok(StopReason::Breakpoint(16)));
+    assert_eq!(machine.regs.r0, 3);
+    assert_eq!(machine.regs.r1, 0);
+
+    assert_eq!(run(&mut machine), Ok(StopReason::EndOfProgram));
+    assert_eq!(machine.regs.r1, 1);
+}
+
+#[test]
+fn test_machine_reset() {
+    let mut machine = mock_machine(
+        "
+        ldr r0, =data
+        mov r1, #42
+        str r1, [r0]
+        push {r1}
+
+        .section .data
+        data:
+            .word 7
+        ",
+    );
+    let result = machine.run_until(&HashSet::new(), &|| '\0', &mut |_| {}, || false);
+    assert_eq!(result, Ok(StopReason::EndOfProgram));
+    assert_eq!(machine.memory.read_u32(0x1000), Ok(42));
+    assert_eq!(machine.regs.r13_sp, crate::STACK_TOP as i32 - 4);
+
+    machine.reset();
+    assert_eq!(machine.memory.read_u32(0x1000), Ok(7));
+    assert_eq!(machine.regs.r13_sp, crate::STACK_TOP as i32);
+    assert_eq!(machine.regs.r0, 0);
+    assert_eq!(machine.pc(), 0);
+}
+
+/// A version 3 `.debug_line` unit with `line_base` -5, `line_range` 14 and `opcode_base` 13
+fn debug_line_section(program: &[u8]) -> Vec<u8> {
+    #[rustfmt::skip]
+    let header = [
+        1, 1, -5i8 as u8, 14, 13,
+        0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1,
+        0,
+        b'a', b'.', b's', 0, 0, 0, 0,
+        0,
+    ];
+
+    let mut section = Vec::new();
+    section.extend_from_slice(&((2 + 4 + header.len() + program.len()) as u32).to_le_bytes());
+    section.extend_from_slice(&3u16.to_le_bytes());
+    section.extend_from_slice(&(header.len() as u32).to_le_bytes());
+    section.extend_from_slice(&header);
+    section.extend_from_slice(program);
+    section
+}
+
+#[test]
+fn test_line_table_program() {
+    #[rustfmt::skip]
+    let section = debug_line_section(&[
+        0x00, 5, 0x02, 0x00, 0x00, 0x00, 0x00, // DW_LNE_set_address 0
+        0x03, 1,                               // DW_LNS_advance_line 1
+        0x01,                                  // DW_LNS_copy
+        13 + 14 * 4 + (1 + 5),                 // address += 4, line += 1
+        13 + 14 * 4 + (2 + 5),                 // address += 4, line += 2
+        0x02, 4,                               // DW_LNS_advance_pc 4
+        0x00, 1, 0x01,                         // DW_LNE_end_sequence
+    ]);
+
+    let lines = LineTable::from_debug_line(&section, 0x100).unwrap();
+    assert_eq!(lines.line(0x100), Some(2));
+    assert_eq!(lines.line(0x104), Some(3));
+    assert_eq!(lines.line(0x108), Some(5));
+    assert_eq!(lines.line(0x10c), None);
+    assert_eq!(lines.addresses(3), vec![0x104]);
+    assert_eq!(lines.addresses(4), vec![]);
+    assert_eq!(
+        lines.iter().collect::<Vec<_>>(),
+        vec![(0x100, 2), (0x104, 3), (0x108, 5)]
+    );
+
+    assert!(LineTable::from_debug_line(&section[..20], 0).is_none());
+}
+
+#[test]
+fn test_line_table_ranges() {
+    #[rustfmt::skip]
+    let section = debug_line_section(&[
+        0x00, 5, 0x02, 0x00, 0x00, 0x00, 0x00, // DW_LNE_set_address 0
+        0x01,                                  // DW_LNS_copy
+        0x02, 12,                              // DW_LNS_advance_pc 12
+        0x03, 2,                               // DW_LNS_advance_line 2
+        0x01,                                  // DW_LNS_copy, zero length
+        0x03, 1,                               // DW_LNS_advance_line 1
+        0x01,                                  // DW_LNS_copy
+        0x02, 8,                               // DW_LNS_advance_pc 8
+        0x00, 1, 0x01,                         // DW_LNE_end_sequence
+        // a second sequence after a gap, for a literal pool
+        0x00, 5, 0x02, 0x20, 0x00, 0x00, 0x00, // DW_LNE_set_address 0x20
+        0x03, 9,                               // DW_LNS_advance_line 9
+        0x01,                                  // DW_LNS_copy
+        0x09, 4, 0,                            // DW_LNS_fixed_advance_pc 4
+        0x00, 1, 0x01,                         // DW_LNE_end_sequence
+    ]);
+
+    let lines = LineTable::from_debug_line(&section, 0).unwrap();
+    assert_eq!(lines.addresses(1), vec![0, 4, 8]);
+    assert_eq!(lines.line(8), Some(1));
+    assert_eq!(lines.line(12), Some(4));
+    assert_eq!(lines.line(16), Some(4));
+    assert_eq!(lines.addresses(3), vec![]);
+    assert_eq!(lines.addresses(4), vec![12, 16]);
+    assert_eq!(lines.line(20), None);
+    assert_eq!(lines.line(0x20), Some(10));
+    assert_eq!(lines.line(0x24), None);
+}
+
+#[test]
+fn test_line_table_from_assembler() {
+    let cs = new_capstone();
+    let mut input_file: NamedTempFile = NamedTempFile::new().unwrap();
+    write!(
+        input_file,
+        "mov r0, #1
+
+        // comment
+        loop:
+            add r0, r0, #1
+            b loop
+        "
+    )
+    .unwrap();
+    let input_path = input_file.path().as_os_str().to_owned();
+    let (_, _, debug_info) = crate::disassemble(&cs, input_path, &Assembler::default()).unwrap();
+
+    assert_eq!(debug_info.lines.line(0), Some(1));
+    assert_eq!(debug_info.lines.line(4), Some(5));
+    assert_eq!(debug_info.lines.line(8), Some(6));
+    assert_eq!(debug_info.lines.addresses(6), vec![8]);
+    assert_eq!(debug_info.lines.addresses(4), vec![]);
+}
+
+#[test]
+#[ignore = "needs the GNU assembler"]
+fn test_line_table_data_and_macros() {
+    let assembler = gnu_assembler();
+    let cs = new_capstone();
+    let mut input_file: NamedTempFile = NamedTempFile::new().unwrap();
+    write!(
+        input_file,
+        ".macro twice reg
+            add \\reg, \\reg, \\reg
+            add \\reg, \\reg, \\reg
+        .endm
+
+            ldr r0, =value
+            twice r0
+            swi 2
+
+        .section .data
+        value:
+            .word 7
+        "
+    )
+    .unwrap();
+    let input_path = input_file.path().as_os_str().to_owned();
+    let (_, _, debug_info) = crate::disassemble(&cs, input_path, &assembler).unwrap();
+
+    assert_eq!(debug_info.lines.addresses(6), vec![0]);
+    assert_eq!(debug_info.lines.addresses(8), vec![12]);
+    // data and the literal pool have no line
+    assert_eq!(debug_info.lines.addresses(12), vec![]);
+    assert_eq!(debug_info.lines.line(16), None);
+}
+
+#[test]
+fn test_symbol_table() {
+    let cs = new_capstone();
+    let mut input_file: NamedTempFile = NamedTempFile::new().unwrap();
+    write!(
+        input_file,
+        "
+        .global _start
+        _start:
+        main:
+            ldr r0, =hello
+            ldr r1, =0x12345678
+            bl loop
+        loop:
+            bne loop
+
+        .section .data
+        hello:
+            .asciz \"hi\"
+        "
+    )
+    .unwrap();
+    let input_path = input_file.path().as_os_str().to_owned();
+    let (memory, instrs, debug_info) =
+        crate::disassemble(&cs, input_path, &Assembler::default()).unwrap();
+    let symbols = &debug_info.symbols;
+
+    assert_eq!(symbols.address("hello"), Some(0x1000));
+    assert_eq!(symbols.address("loop"), Some(12));
+    assert_eq!(symbols.address("missing"), None);
+    assert_eq!(symbols.name(12), Some("loop"));
+    assert_eq!(
+        symbols.labels_at(0).collect::<Vec<_>>(),
+        vec!["_start", "main"]
+    );
+    // mapping symbols are not labels
+    assert!(symbols.iter().all(|symbol| !symbol.name.starts_with('$')));
+
+    let disasm = instrs
+        .iter()
+        .map(|insn| crate::symbolic_disasm(insn, &memory, symbols))
+        .collect::<Vec<String>>();
+    assert_eq!(disasm[0], "ldr r0, =hello");
+    assert_eq!(disasm[1], "ldr r1, =0x12345678");
+    assert_eq!(disasm[2], "bl loop");
+    assert_eq!(disasm[3], "bne loop");
+}
+
+fn mock_assemble(buf: &str) -> Result<(Memory, crate::DebugInfo), String> {
+    crate::assembler::assemble(buf, "test.s")
+}
+
+fn text_words(memory: &Memory) -> Vec<u32> {
+    memory
+        .region(".text")
+        .unwrap()
+        .bytes
+        .chunks(4)
+        .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+        .collect()
+}
+
+#[test]
+fn test_assembler_encoding() {
+    let (memory, _) = mock_assemble(
+        "
+        start:
+            add r0, r1, r2, lsl #3
+            ldr r0, [r1, #-4]!
+            ldrh r0, [r1, #2]
+            strb r2, [r3], r4
+            ldmfd sp!, {r4-r6, pc}
+            push {r0}
+            stmdb sp!, {r0, r1}
+            mul r0, r1, r2
+            umull r0, r1, r2, r3
+            swi 0x10
+            msr cpsr_f, r0
+            mrs r0, cpsr
+            sub r0, r0, #-1
+            mov r0, #0x3fc
+            nop
+            b start
+        ",
+    )
+    .unwrap();
+
+    assert_eq!(
+        text_words(&memory),
+        vec![
+            0xe0810182, 0xe5310004, 0xe1d100b2, 0xe6c32004, 0xe8bd8070, 0xe52d0004, 0xe92d0003,
+            0xe0000291, 0xe0810392, 0xef000010, 0xe128f000, 0xe10f0000, 0xe2800001, 0xe3a00fff,
+            0xe1a00000, 0xeaffffef,
+        ]
+    );
+}
+
+#[test]
+fn test_assembler_literal_pool() {
+    let (memory, _) = mock_assemble(
+        "
+            ldr r0, =0xff
+            ldr r1, =0xffffff00
+            ldr r2, =0x12345678
+            ldr r3, =0x12345678
+            ldr r4, =value
+        .data
+        value:
+            .word 1
+        ",
+    )
+    .unwrap();
+
+    // constants that fit in an immediate become `mov` and `mvn`, the pool
+    // follows the code and equal constants share an entry
+    assert_eq!(
+        text_words(&memory),
+        vec![
+            0xe3a000ff, 0xe3e010ff, 0xe59f2004, 0xe59f3000, 0xe59f4000, 0x12345678, 0x1000,
+        ]
+    );
+}
+
+#[test]
+fn test_assembler_directives() {
+    let (memory, debug_info) = mock_assemble(
+        "
+        .equ COUNT, 3
+        SIZE = COUNT * 4
+
+        .data
+        bytes:
+            .byte 1, 'a', -1
+            .align 2
+        halves:
+            .hword 0x1234
+            .asciz \"hi\\n\"
+            .balign 4
+        words:
+            .word SIZE, halves - bytes, end
+            .space 2, 0xaa
+        end:
+        length = . - bytes
+
+        .text
+            mov r0, #length
+        1:  subs r0, r0, #1
+            bne 1b
+            b 1f
+            nop
+        1:  swi 2
+        ",
+    )
+    .unwrap();
+
+    let data = &memory.region(".data").unwrap().bytes;
+    assert_eq!(
+        data,
+        &[
+            1, b'a', 0xff, 0, 0x34, 0x12, b'h', b'i', b'\n', 0, 0, 0, 12, 0, 0, 0, 4, 0, 0, 0,
+            0x1a, 0x10, 0, 0, 0xaa, 0xaa
+        ]
+    );
+    assert_eq!(
+        text_words(&memory),
+        vec![
+            0xe3a0001a, 0xe2500001, 0x1afffffd, 0xea000000, 0xe1a00000, 0xef000002
+        ]
+    );
+
+    // numeric local labels and constants are not symbols
+    let symbols = debug_info
+        .symbols
+        .iter()
+        .map(|symbol| symbol.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(symbols, vec!["bytes", "halves", "words", "end"]);
+}
+
+#[test]
+fn test_assembler_errors() {
+    let e = mock_assemble(
+        "
+            mov r0, #0x101
+            ldr r1, [r2, #4096]
+            foo r0, r1
+            b missing
+        ",
+    )
+    .unwrap_err();
+
+    assert_eq!(
+        e,
+        "test.s: Assembler messages:\n\
+         test.s:4: Error: bad instruction `foo`\n"
+    );
+
+    let e = mock_assemble(
+        "
+            mov r0, #0x101
+            ldr r1, [r2, #4096]
+            b missing
+        ",
+    )
+    .unwrap_err();
+
+    assert_eq!(
+        e,
+        "test.s: Assembler messages:\n\
+         test.s:2: Error: invalid constant (101) after fixup\n\
+         test.s:3: Error: bad immediate value for offset (4096)\n\
+         test.s:4: Error: undefined symbol `missing`\n"
+    );
+}
+
+#[test]
+fn test_parse_diagnostics() {
+    let output = "/tmp/.tmpX1: Assembler messages:\n\
+                  /tmp/.tmpX1:3: Error: bad instruction `foo r0,r1'\n\
+                  /tmp/.tmpX1:7: Warning: register range not in ascending order\n\
+                  C:\\labs\\lab1.s:12:5: Error: garbage following instruction -- `mov r0,r1 r2'\n\
+                  /tmp/.tmpX1: Fatal error: can't create output file\n";
+
+    assert_eq!(
+        parse_diagnostics(output),
+        vec![
+            Diagnostic {
+                line: 3,
+                column: None,
+                severity: Severity::Error,
+                message: "bad instruction `foo r0,r1'".to_string(),
+            },
+            Diagnostic {
+                line: 7,
+                column: None,
+                severity: Severity::Warning,
+                message: "register range not in ascending order".to_string(),
+            },
+            Diagnostic {
+                line: 12,
+                column: Some(5),
+                severity: Severity::Error,
+                message: "garbage following instruction -- `mov r0,r1 r2'".to_string(),
+            },
+        ]
+    );
+
+    // the built-in assembler reports errors in the same format
+    let e = mock_assemble(
+        "
+            mov r0, #1
+            foo r0, r1
+        ",
+    )
+    .unwrap_err();
+    let diagnostics = parse_diagnostics(&e);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].line, 3);
+    assert_eq!(diagnostics[0].to_string(), "Error: bad instruction `foo`");
+}
+
+fn mock_toolchain_dir(names: &[&str], mode: u32) -> tempfile::TempDir {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    for name in names {
+        let path = dir.path().join(name);
+        std::fs::write(&path, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+    }
+    dir
+}
+
+#[test]
+fn test_toolchain_search_path() {
+    let empty = tempfile::tempdir().unwrap();
+    let dir = mock_toolchain_dir(&["arm-linux-gnu-as", "arm-none-eabi-as"], 0o755);
+    let path = std::env::join_paths([empty.path(), dir.path()]).unwrap();
+
+    // `arm-none-eabi-as` is preferred
+    let toolchain = Toolchain::search(None, None, Some(&path)).unwrap();
+    assert_eq!(toolchain.assembler, dir.path().join("arm-none-eabi-as"));
+    assert_eq!(toolchain.source, ToolchainSource::Path);
+
+    // files that cannot be executed are skipped
+    let dir = mock_toolchain_dir(&["arm-none-eabi-as"], 0o644);
+    assert_eq!(
+        Toolchain::search(None, None, Some(dir.path().as_os_str())),
+        Err(ToolchainError::NotFound)
+    );
+    assert_eq!(
+        Toolchain::search(None, None, None),
+        Err(ToolchainError::NotFound)
+    );
+}
+
+#[test]
+fn test_toolchain_configured() {
+    let dir = mock_toolchain_dir(&["arm-linux-gnueabi-as", "my-as"], 0o755);
+    let path = dir.path().as_os_str();
+    let my_as = dir.path().join("my-as");
+
+    // a command name from the environment is looked up on `PATH`
+    let toolchain = Toolchain::search(None, Some("my-as".as_ref()), Some(path)).unwrap();
+    assert_eq!(toolchain.assembler, my_as);
+    assert_eq!(toolchain.source, ToolchainSource::Environment);
+
+    // a configured path takes priority over the environment
+    let configured = dir.path().join("arm-linux-gnueabi-as");
+    let toolchain =
+        Toolchain::search(Some(&configured), Some(my_as.as_os_str()), Some(path)).unwrap();
+    assert_eq!(toolchain.assembler, configured);
+    assert_eq!(toolchain.source, ToolchainSource::Configured);
+
+    // a missing assembler is an error rather than falling back to `PATH`
+    let missing = dir.path().join("missing-as");
+    assert_eq!(
+        Toolchain::search(Some(&missing), None, Some(path)),
+        Err(ToolchainError::NotExecutable {
+            assembler: missing,
+            source: ToolchainSource::Configured
+        })
+    );
+}
+
+#[test]
+fn test_assembly_cache() {
+    let mut cache = AssemblyCache::new(None);
+    let (memory, _) = cache.assemble("mov r0, #1", &Assembler::Builtin).unwrap();
+    let (again, _) = cache.assemble("mov r0, #1", &Assembler::Builtin).unwrap();
+    assert_eq!(text_words(&memory), text_words(&again));
+    assert_eq!(cache.len(), 1);
+
+    // errors are kept as well
+    for _ in 0..2 {
+        assert_eq!(
+            cache.assemble("foo r0", &Assembler::Builtin).unwrap_err(),
+            "program.s: Assembler messages:\n\
+             program.s:1: Error: bad instruction `foo`\n"
+        );
+    }
+    assert_eq!(cache.len(), 2);
+
+    // the least recently used program is evicted
+    for i in 0..20 {
+        let source = format!("mov r0, #{}", i);
+        cache.assemble(&source, &Assembler::Builtin).unwrap();
+    }
+    assert_eq!(cache.len(), 16);
+}
+
+/// An ELF object file with `words` as its `.text` section
+fn mock_object(words: &[u32]) -> Vec<u8> {
+    let text: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    let shstrtab = b"\0.text\0.shstrtab\0";
+    let text_offset = 52;
+    let shstrtab_offset = text_offset + text.len();
+    let shoff = (shstrtab_offset + shstrtab.len()).next_multiple_of(4);
+
+    let mut buf = b"\x7fELF\x01\x01\x01".to_vec();
+    buf.resize(16, 0);
+    // ET_REL, EM_ARM, EV_CURRENT
+    for half in [1u16, 40] {
+        buf.extend(half.to_le_bytes());
+    }
+    for word in [1, 0, 0, shoff as u32, 0x0500_0000] {
+        buf.extend(u32::to_le_bytes(word));
+    }
+    // header size, no program headers, 3 section headers, `.shstrtab` is the last
+    for half in [52u16, 0, 0, 40, 3, 2] {
+        buf.extend(half.to_le_bytes());
+    }
+    buf.extend(&text);
+    buf.extend(shstrtab);
+    buf.resize(shoff, 0);
+
+    let headers: [[u32; 10]; 3] = [
+        [0; 10],
+        // SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR
+        [
+            1,
+            1,
+            6,
+            0,
+            text_offset as u32,
+            text.len() as u32,
+            0,
+            0,
+            4,
+            0,
+        ],
+        // SHT_STRTAB
+        [
+            7,
+            3,
+            0,
+            0,
+            shstrtab_offset as u32,
+            shstrtab.len() as u32,
+            0,
+            0,
+            1,
+            0,
+        ],
+    ];
+    for word in headers.as_flattened() {
+        buf.extend(word.to_le_bytes());
+    }
+    buf
+}
+
+#[test]
+fn test_assembly_cache_disk() {
+    use std::os::unix::fs::PermissionsExt;
+
+    // an `as` that counts its calls and outputs `mov r0, #1`
+    let dir = tempfile::tempdir().unwrap();
+    let assembler = dir.path().join("as");
+    std::fs::write(dir.path().join("program.o"), mock_object(&[0xe3a0_0001])).unwrap();
+    std::fs::write(
+        &assembler,
+        "#!/bin/sh\n\
+         echo >> \"$(dirname \"$0\")/calls\"\n\
+         for last; do :; done\n\
+         cp \"$(dirname \"$0\")/program.o\" \"$last\"\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(&assembler, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let calls = || {
+        std::fs::read_to_string(dir.path().join("calls")).map_or(0, |calls| calls.lines().count())
+    };
+
+    let gnu = Assembler::Gnu(Toolchain {
+        assembler,
+        source: ToolchainSource::Configured,
+    });
+    let cache_dir = tempfile::tempdir().unwrap();
+    let cache_dir = cache_dir.path().join("komodo");
+
+    let mut cache = AssemblyCache::new(Some(cache_dir.clone()));
+    let (memory, _) = cache.assemble("mov r0, #1", &gnu).unwrap();
+    assert_eq!(text_words(&memory), [0xe3a0_0001]);
+    cache.assemble("mov r0, #1", &gnu).unwrap();
+    assert_eq!(calls(), 1);
+
+    // a new cache, e.g. after a restart, reads the object file from disk
+    let mut cache = AssemblyCache::new(Some(cache_dir.clone()));
+    let (memory, _) = cache.assemble("mov r0, #1", &gnu).unwrap();
+    assert_eq!(text_words(&memory), [0xe3a0_0001]);
+    assert_eq!(calls(), 1);
+
+    // a different source or assembler is a different program
+    cache.assemble("mov r0, #2", &gnu).unwrap();
+    assert_eq!(calls(), 2);
+    let (memory, _) = cache.assemble("mov r0, #1", &Assembler::Builtin).unwrap();
+    assert_eq!(text_words(&memory), [0xe3a0_0001]);
+    assert_eq!(calls(), 2);
+    assert_eq!(std::fs::read_dir(&cache_dir).unwrap().count(), 2);
+    assert_eq!(cache.take_write_error(), None);
+
+    // a directory that cannot be created under a file still assembles, and
+    // keeps why the object file was not written
+    let mut cache = AssemblyCache::new(Some(dir.path().join("calls").join("komodo")));
+    cache.assemble("mov r0, #1", &gnu).unwrap();
+    assert!(
+        cache
+            .take_write_error()
+            .unwrap()
+            .starts_with("Cannot write")
+    );
+    assert_eq!(cache.take_write_error(), None);
+}
+
+#[test]
+fn test_decoded_insn() {
+    let (memory, _) = mock_assemble(
+        "
+            addne r0, r1, r2, lsl r3
+            movs r4, #0x3f0
+            ldrsh r5, [r6], #-6
+            strb r7, [r8, -r9, asr #2]!
+            pop {r4, r5, pc}
+            bl 0
+            umlals r0, r1, r2, r3
+        ",
+    )
+    .unwrap();
+    let decoded: Vec<(Condition, DecodedInsn)> = text_words(&memory)
+        .into_iter()
+        .enumerate()
+        .map(|(i, word)| {
+            let (condition, decoded) = decode(word, 4 * i as u32);
+            (condition, decoded.unwrap())
+        })
+        .collect();
+
+    assert_eq!(
+        decoded,
+        [
+            (
+                Condition::Ne,
+                DecodedInsn::DataProcessing {
+                    opcode: Opcode::Add,
+                    set_flags: false,
+                    rd: 0,
+                    rn: 1,
+                    operand2: Operand2::Register {
+                        rm: 2,
+                        shift: Shift::Lsl,
+                        amount: ShiftAmount::Register(3),
+                    },
+                },
+            ),
+            (
+                Condition::Al,
+                DecodedInsn::DataProcessing {
+                    opcode: Opcode::Mov,
+                    set_flags: true,
+                    rd: 4,
+                    rn: 0,
+                    operand2: Operand2::Immediate {
+                        immed_8: 0x3f,
+                        rotate_imm: 14,
+                    },
+                },
+            ),
+            (
+                Condition::Al,
+                DecodedInsn::Transfer {
+                    load: true,
+                    size: TransferSize::SignedHalfword,
+                    rd: 5,
+                    rn: 6,
+                    offset: Offset::Immediate(-6),
+                    indexing: Indexing::PostIndexed,
+                },
+            ),
+            (
+                Condition::Al,
+                DecodedInsn::Transfer {
+                    load: false,
+                    size: TransferSize::Byte,
+                    rd: 7,
+                    rn: 8,
+                    offset: Offset::Register {
+                        rm: 9,
+                        shift: Shift::Asr,
+                        amount: 2,
+                        subtract: true,
+                    },
+                    indexing: Indexing::PreIndexed,
+                },
+            ),
+            (
+                Condition::Al,
+                DecodedInsn::Block {
+                    load: true,
+                    increment: true,
+                    before: false,
+                    writeback: true,
+                    rn: 13,
+                    registers: 1 << 4 | 1 << 5 | 1 << 15,
+                },
+            ),
+            (
+                Condition::Al,
+                DecodedInsn::Branch {
+                    link: true,
+                    target: 0,
+                },
+            ),
+            (
+                Condition::Al,
+                DecodedInsn::MultiplyLong {
+                    set_flags: true,
+                    signed: false,
+                    accumulate: true,
+                    rd_lo: 0,
+                    rd_hi: 1,
+                    rm: 2,
+                    rs: 3,
+                },
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_decode_matches_capstone() {
+    let mut sources = vec![
+        "
+        start:
+            and r0, r1, r2
+            eors r3, r4, #0xff000000
+            subne r5, r6, r7, lsl #3
+            rsbs r8, r9, r10, lsr #32
+            add r11, r12, r13, asr r1
+            adc r0, r0, r1, ror #7
+            sbc r0, r0, r1, rrx
+            rsc r2, r3, r4, ror r5
+            tst r0, #1
+            teq r1, r2
+            cmp r3, r4, lsl #2
+            cmn r5, #4
+            orrs r6, r7, r8
+            mov r0, r1
+            movs pc, lr
+            bic r2, r3, #0x3fc
+            mvn r4, r5, asr #31
+            mul r0, r1, r2
+            mlas r3, r4, r5, r6
+            umull r0, r1, r2, r3
+            smlal r4, r5, r6, r7
+            ldr r0, [r1]
+            ldr r0, [r1, #-4]!
+            ldr r0, [r1], r2, lsl #2
+            ldrb r3, [r4, -r5]
+            str r6, [r7, #4]
+            strb r8, [r9], #-1
+            ldrh r0, [r1, #2]
+            strh r2, [r3], -r4
+            ldrsb r5, [r6, r7]!
+            ldrsh r8, [r9, #-0x12]
+            ldmia r0!, {r1, r2}
+            stmdb sp!, {r4-r11, lr}
+            ldmib r3, {r0}
+            stmda r4, {r5, r6}
+            push {r0}
+            pop {r1}
+            push {r0, r1}
+            swp r0, r1, [r2]
+            swpb r3, r4, [r5]
+            mrs r0, cpsr
+            swi 0x123456
+            bl start
+            bgt start
+            b end
+        end:
+            ldr r0, =start
+        ",
+    ];
+    sources.extend([
+        include_str!("../examples/hello.s"),
+        include_str!("../examples/keyboard.s"),
+        include_str!("../examples/loop.s"),
+    ]);
+
+    let cs = new_capstone();
+    for source in sources {
+        let (memory, _) = mock_assemble(source).unwrap();
+        let text = memory.region(".text").unwrap();
+        for insn in cs.disasm_all(&text.bytes, text.base as u64).unwrap().iter() {
+            let word = u32::from_le_bytes(insn.bytes().try_into().unwrap());
+            assert_eq!(
+                decode(word, insn.address() as u32),
+                from_capstone(&cs, insn),
+                "{:#010x} `{} {}`",
+                word,
+                insn.mnemonic().unwrap(),
+                insn.op_str().unwrap()
+            );
+        }
+    }
+}
+
+#[test]
+fn test_decode_unimplemented() {
+    for (word, what) in [
+        (0xe8d0_0003, "block transfer with `^`"),
+        (0xee01_0f10, "coprocessor instruction"),
+        (0xe7f0_00f0, "undefined instruction"),
+        (0xfa00_0000, "unconditional instruction"),
+    ] {
+        assert_eq!(
+            decode(word, 0).1,
+            Err(Fault::Unimplemented(what.to_string())),
+            "{:#010x}",
+            word
+        );
+    }
+}
+
+#[test]
+fn test_self_modifying_code() {
+    // overwrites `mov r0, #1` with the encoding of `mov r0, #2` before running it
+    let regs = mock_program(
+        "
+            ldr r1, =patch
+            ldr r2, =0xe3a00002
+            str r2, [r1]
+        patch:
+            mov r0, #1
+            swi 2
+        ",
+    );
+    assert_eq!(regs.r0, 2);
+}
+
+#[test]
+fn test_execute_data() {
+    // `mov r0, #7` followed by `mov pc, lr` placed in `.data`
+    let regs = mock_program(
+        "
+            ldr r1, =code
+            mov lr, pc
+            mov pc, r1
+            swi 2
+
+            .data
+        code:
+            .word 0xe3a00007, 0xe1a0f00e
+        ",
+    );
+    assert_eq!(regs.r0, 7);
+}
+
+#[test]
+fn test_bx_interworking() {
+    // the Thumb function is given as halfwords, the built-in assembler only emits ARM
+    let regs = mock_program(
+        "
+            adr r0, thumb + 1
+            mov lr, pc
+            bx r0
+            mov r2, r0
+            swi 2
+
+        thumb:
+            .hword 0x2005 @ movs r0, #5
+            .hword 0x3003 @ adds r0, #3
+            .hword 0x0081 @ lsls r1, r0, #2
+            .hword 0x4770 @ bx lr
+        ",
+    );
+    assert_eq!(regs.r0, 8);
+    assert_eq!(regs.r1, 32);
+    assert_eq!(regs.r2, 8);
+    assert!(!StatusFlags::from(regs.apsr).thumb);
+}
+
+#[test]
+fn test_thumb_execution() {
+    let regs = mock_program(
+        "
+            adr r0, thumb + 1
+            bx r0
+
+        thumb:
+            .hword 0xb510         @ push {r4, lr}
+            .hword 0x2406         @ movs r4, #6
+            .hword 0xf000, 0xf808 @ bl square
+            .hword 0x4a05         @ ldr r2, [pc, #20]
+            .hword 0xa303         @ add r3, pc, #12
+            .hword 0x4680         @ mov r8, r0
+            .hword 0x4241         @ negs r1, r0
+            .hword 0x2824         @ cmp r0, #36
+            .hword 0xd000         @ beq done
+            .hword 0x2000         @ movs r0, #0
+        done:
+            .hword 0xdf02         @ swi 2
+        square:
+            .hword 0x1c20         @ adds r0, r4, #0
+            .hword 0x4360         @ muls r0, r4
+            .hword 0x4770         @ bx lr
+            .hword 0x46c0         @ nop
+            .word 0x12345678
+        ",
+    );
+    assert_eq!(regs.r0, 36);
+    assert_eq!(regs.r1, -36);
+    assert_eq!(regs.r2, 0x12345678);
+    // word aligned `pc` of the `add`, plus 12
+    assert_eq!(regs.r3, 0x20);
+    assert_eq!(regs.r8, 36);
+    assert_eq!(regs.r13_sp, crate::STACK_TOP as i32 - 8);
+    assert!(StatusFlags::from(regs.apsr).thumb);
+}
+
+#[test]
+fn test_decode_thumb() {
+    for (encoding, address, expected) in [
+        (
+            0x0081,
+            0,
+            DecodedInsn::DataProcessing {
+                opcode: Opcode::Mov,
+                set_flags: true,
+                rd: 1,
+                rn: 0,
+                operand2: Operand2::Register {
+                    rm: 0,
+                    shift: Shift::Lsl,
+                    amount: ShiftAmount::Immediate(2),
+                },
+            },
+        ),
+        (
+            0xb510,
+            0,
+            DecodedInsn::Block {
+                load: false,
+                increment: false,
+                before: true,
+                writeback: true,
+                rn: 13,
+                registers: 1 << 4 | 1 << 14,
+            },
+        ),
+        // `bl` with its second half, the target is `pc` plus 0x1000 and 0x10
+        (
+            0xf808_f001,
+            0x100,
+            DecodedInsn::Branch {
+                link: true,
+                target: 0x1114,
+            },
+        ),
+        // `ldr r2, [pc, #4]` at a halfword address reads from the word aligned `pc`
+        (
+            0x4a01,
+            0x12,
+            DecodedInsn::Transfer {
+                load: true,
+                size: TransferSize::Word,
+                rd: 2,
+                rn: 15,
+                offset: Offset::Immediate(2),
+                indexing: Indexing::Offset,
+            },
+        ),
+        (0x4770, 0, DecodedInsn::BranchExchange { rm: 14 }),
+        (0xdf02, 0, DecodedInsn::Swi(2)),
+    ] {
+        assert_eq!(
+            crate::decode_thumb(encoding, address),
+            (Condition::Al, Ok(expected)),
+            "{:#06x}",
+            encoding
+        );
+    }
+
+    assert_eq!(
+        crate::decode_thumb(0xd1fe, 0x20),
+        (
+            Condition::Ne,
+            Ok(DecodedInsn::Branch {
+                link: false,
+                target: 0x20,
+            })
+        )
+    );
+    assert_eq!(crate::thumb_size(0xf808_f001), 4);
+    assert_eq!(crate::thumb_size(0x0000_f001), 2);
+    assert!(crate::decode_thumb(0x0000_f001, 0).1.is_err());
+}
+
+#[test]
+#[ignore = "needs the GNU assembler"]
+fn test_thumb_mapping_symbols() {
+    let assembler = gnu_assembler();
+    let cs = new_capstone();
+    let thumb_cs = crate::new_thumb_capstone();
+    let mut input_file: NamedTempFile = NamedTempFile::new().unwrap();
+    write!(
+        input_file,
+        "
+            .arm
+            adr r0, thumb + 1
+            bx r0
+
+            .thumb
+        thumb:
+            movs r0, #7
+            swi 2
+        "
+    )
+    .unwrap();
+    let input_path = input_file.path().as_os_str().to_owned();
+    let (memory, _, debug_info) = crate::disassemble(&cs, input_path, &assembler).unwrap();
+    let symbols = &debug_info.symbols;
+
+    assert_eq!(symbols.mapping(4), crate::Mapping::Arm);
+    assert_eq!(symbols.mapping(8), crate::Mapping::Thumb);
+    let disasm = crate::disassemble_mapped(&cs, &thumb_cs, &memory, symbols)
+        .unwrap()
+        .iter()
+        .flat_map(|instrs| instrs.iter())
+        .map(|insn| crate::symbolic_disasm(insn, &memory, symbols))
+        .collect::<Vec<String>>();
+    assert_eq!(disasm[2..], ["movs r0, #7", "svc #2"]);
+
+    let mut machine = Machine::new(&cs, memory).unwrap();
+    let result = machine.run_until(&HashSet::new(), &|| '\0', &mut |_| {}, || false);
+    assert_eq!(result, Ok(StopReason::Halted));
+    assert_eq!(machine.regs.r0, 7);
+}
+
+#[test]
+fn test_banked_registers() {
+    let mut machine = mock_machine(
+        "
+        mov sp, #0x8000
+        mov lr, #1
+        msr cpsr_c, #0x12 @ IRQ mode
+        mov sp, #0x4000
+        mov r8, #8
+        msr cpsr_c, #0x11 @ FIQ mode banks r8-r12 as well
+        mov r8, #88
+        mov r0, sp
+        msr cpsr_c, #0x1f @ System mode shares the User mode registers
+        mov r1, sp
+        mov r2, r8
+        msr cpsr_c, #0x13
+        mov r3, sp
+        mov r4, lr
+        msr cpsr_c, #0x10
+        msr cpsr_c, #0x13 @ User mode cannot change mode
+        mov r5, sp
+        mrs r6, cpsr
+        ",
+    );
+    machine.regs.switch_mode(ProcessorMode::Supervisor);
+    machine
+        .run_until(&HashSet::new(), &|| '\0', &mut |_| {}, || false)
+        .unwrap();
+
+    let regs = &machine.regs;
+    assert_eq!(regs.r0, crate::STACK_TOP as i32);
+    assert_eq!(regs.r1, crate::STACK_TOP as i32);
+    assert_eq!(regs.r2, 8);
+    assert_eq!(regs.r3, 0x8000);
+    assert_eq!(regs.r4, 1);
+    assert_eq!(regs.r5, crate::STACK_TOP as i32);
+    assert_eq!(regs.r6, 0x10);
+    assert_eq!(regs.mode(), ProcessorMode::User);
+}
+
+#[test]
+fn test_msr_field_masks() {
+    let regs = mock_program(
+        "
+        mov r0, #0xf0000000
+        msr cpsr_f, r0
+        mrs r1, cpsr
+        mov r0, #0
+        msr cpsr_fsxc, r0 @ only the flags can be written in User mode
+        mrs r2, cpsr
+        ",
+    );
+    assert_eq!(regs.r1, 0xf000_0010_u32 as i32);
+    assert_eq!(regs.r2, 0x10);
+}
+
+#[test]
+fn test_spsr() {
+    let mut machine = mock_machine(
+        "
+        mov r0, #0x60000000
+        orr r0, r0, #0x10
+        msr spsr_fsxc, r0
+        mrs r1, spsr
+        mov r0, #0xd3
+        msr spsr_c, r0
+        mrs r2, spsr
+        msr cpsr_c, #0x1b @ Undefined mode has its own SPSR
+        mrs r3, spsr
+        ",
+    );
+    machine.regs.switch_mode(ProcessorMode::Supervisor);
+    machine
+        .run_until(&HashSet::new(), &|| '\0', &mut |_| {}, || false)
+        .unwrap();
+
+    assert_eq!(machine.regs.r1, 0x6000_0010);
+    assert_eq!(machine.regs.r2, 0x6000_00d3);
+    assert_eq!(machine.regs.r3, 0);
+    assert_eq!(machine.regs.spsr(), Some(0));
+
+    let (_, err) = mock_program_error("mrs r0, spsr");
+    assert_eq!(err.fault, Fault::NoSpsr);
+    assert_eq!(err.address, 0);
+}
+
+fn mock_bare_metal(buf: &'static str) -> Machine {
+    let mut machine = mock_machine(buf);
+    machine.bare_metal = true;
+    machine.reset();
+    machine
+}
+
+#[test]
+fn test_exception_vectors() {
+    let mut machine = mock_bare_metal(
+        "
+            b reset
+            b undefined
+            b swi
+            b prefetch_abort
+            b data_abort
+            nop
+            b irq
+            b irq
+
+        undefined:
+            mov r5, lr
+            movs pc, lr
+        swi:
+            ldr r1, [lr, #-4]
+            bic r1, r1, #0xff000000
+            mrs r2, spsr
+            movs pc, lr
+        prefetch_abort:
+            mov r10, lr
+            mrs r11, cpsr
+            b done
+        data_abort:
+            mov r7, lr
+            subs pc, lr, #4
+        irq:
+            subs pc, lr, #4
+
+        reset:
+            mrs r12, cpsr
+            msr cpsr_c, #0x10
+            mov r0, #1
+            swi 0x12
+            mov r3, #3
+            .word 0xe7f000f0
+        after_undefined:
+            mov r6, #6
+            mov r0, #0x80000000
+        aborted:
+            ldr r4, [r0], #4
+            mov r8, r0
+            mrs r9, cpsr
+            mov pc, #0x80000000
+        done:
+        ",
+    );
+    assert_eq!(machine.pc(), 0);
+    let result = machine.run_until(&HashSet::new(), &|| '\0', &mut |_| {}, || false);
+    assert_eq!(result, Ok(StopReason::EndOfProgram));
+
+    let regs = &machine.regs;
+    // reset enters Supervisor mode with interrupts disabled
+    assert_eq!(regs.r12, 0xd3);
+    assert_eq!(regs.r1, 0x12);
+    assert_eq!(regs.r2, 0x10);
+    assert_eq!(regs.r3, 3);
+    assert_eq!(regs.r5, 4 * 26);
+    assert_eq!(regs.r6, 6);
+    // the aborted load changes neither `r4` nor the base register
+    assert_eq!(regs.r4, 0);
+    assert_eq!(regs.r7, 4 * 28 + 8);
+    assert_eq!(regs.r8, 0x80000000_u32 as i32);
+    assert_eq!(regs.r9, 0x10);
+    assert_eq!(regs.r10, 0x80000004_u32 as i32);
+    assert_eq!(regs.r11, 0x97);
+    assert_eq!(regs.spsr(), Some(0x10));
+}
+
+#[test]
+fn test_interrupts() {
+    let mut machine = mock_bare_metal(
+        "
+            b reset
+            .word 0, 0, 0, 0, 0
+            b irq
+            b fiq
+
+        irq:
+        fiq:
+            subs pc, lr, #4
+
+        reset:
+            mov r0, #1
+            msr cpsr_c, #0x10
+            mov r1, #2
+            mov r2, #3
+        ",
+    );
+    let step = |machine: &mut Machine| machine.step(&|| '\0', &mut |_| {});
+
+    // masked until the reset handler enables interrupts
+    machine.irq = true;
+    step(&mut machine).unwrap();
+    step(&mut machine).unwrap();
+    assert_eq!(machine.regs.r0, 1);
+    step(&mut machine).unwrap();
+    assert_eq!(machine.pc(), 4 * 11);
+
+    step(&mut machine).unwrap();
+    assert_eq!(machine.pc(), 0x18);
+    assert_eq!(machine.regs.mode(), ProcessorMode::Irq);
+    assert_eq!(machine.regs.r14_lr, 4 * 11 + 4);
+    assert_eq!(machine.regs.spsr(), Some(0x10));
+    assert_eq!(machine.regs.apsr, 0x92);
+
+    machine.irq = false;
+    step(&mut machine).unwrap();
+    step(&mut machine).unwrap();
+    assert_eq!(machine.pc(), 4 * 11);
+    assert_eq!(machine.regs.mode(), ProcessorMode::User);
+    step(&mut machine).unwrap();
+    assert_eq!(machine.regs.r1, 2);
+
+    machine.fiq = true;
+    step(&mut machine).unwrap();
+    assert_eq!(machine.pc(), 0x1c);
+    assert_eq!(machine.regs.apsr, 0xd1);
+    machine.fiq = false;
+    step(&mut machine).unwrap();
+    step(&mut machine).unwrap();
+    step(&mut machine).unwrap();
+    assert_eq!(machine.regs.r2, 3);
+    assert_eq!(machine.regs.mode(), ProcessorMode::User);
+}
+
+fn mock_board(machine: &mut Machine) -> Board {
+    let board = Board::new();
+    for (base, device) in board.devices() {
+        machine.map_peripheral(base, device);
+    }
+    board
+}
+
+#[test]
+fn test_uart() {
+    let mut machine = mock_machine(
+        "
+        mov r1, #0x10000000
+        mov r0, #'h'
+        strb r0, [r1]
+        mov r0, #'i'
+        str r0, [r1]
+        ldr r2, [r1, #4]
+        ldrb r3, [r1]
+        ldr r4, [r1, #4]
+        ",
+    );
+    let board = mock_board(&mut machine);
+    board.uart.lock().unwrap().receive(b'x');
+
+    let mut output = String::new();
+    machine
+        .run_until(&HashSet::new(), &|| '\0', &mut |s| output += &s, || false)
+        .unwrap();
+    assert_eq!(output, "hi");
+    assert_eq!(machine.regs.r2, 0b11);
+    assert_eq!(machine.regs.r3, 'x' as i32);
+    assert_eq!(machine.regs.r4, 0b10);
+}
+
+#[test]
+fn test_gpio() {
+    let mut machine = mock_machine(
+        "
+        mov r1, #0x10000000
+        orr r1, r1, #0x2000
+        ldr r0, [r1, #4]
+        mov r2, #0xa5
+        orr r2, r2, #0x100 @ there are only 8 LEDs
+        str r2, [r1]
+        ldrb r3, [r1]
+        ",
+    );
+    let board = mock_board(&mut machine);
+    board.gpio.lock().unwrap().set_button(2, true);
+
+    machine
+        .run_until(&HashSet::new(), &|| '\0', &mut |_| {}, || false)
+        .unwrap();
+    assert_eq!(machine.regs.r0, 0b100);
+    assert_eq!(machine.regs.r3, 0xa5);
+    assert_eq!(board.gpio.lock().unwrap().leds(), 0xa5);
+
+    machine.reset();
+    assert_eq!(board.gpio.lock().unwrap().leds(), 0);
+}
+
+#[test]
+fn test_peripheral_lanes() {
+    let mut machine = mock_machine(
+        "
+        mov r1, #0x10000000
+        orr r1, r1, #0x2000
+        mov r0, #0xa5
+        str r0, [r1]
+        mov r0, #0xff
+        strb r0, [r1, #1]
+        sub r1, r1, #0x1000
+        mov r0, #0x12000000
+        orr r0, r0, #0x56
+        str r0, [r1, #4]
+        mov r0, #0xab00
+        strh r0, [r1, #6]
+        mov r0, #0x78
+        strb r0, [r1, #4]
+        ldr r2, [r1, #4]
+        ",
+    );
+    let board = mock_board(&mut machine);
+
+    machine
+        .run_until(&HashSet::new(), &|| '\0', &mut |_| {}, || false)
+        .unwrap();
+    assert_eq!(board.gpio.lock().unwrap().leds(), 0xa5);
+    assert_eq!(machine.regs.r2 as u32, 0xab000078);
+}
+
+#[test]
+fn test_timer_interrupt() {
+    let mut machine = mock_bare_metal(
+        "
+            b reset
+            .word 0, 0, 0, 0, 0
+            b irq
+            .word 0
+
+        irq:
+            add r5, r5, #1
+            cmp r5, #3
+            beq done
+            mov r0, #1
+            str r0, [r1, #12] @ clear the match
+            ldr r0, [r1]
+            add r0, r0, #20
+            str r0, [r1, #4]
+            subs pc, lr, #4
+
+        reset:
+            mov r1, #0x10000000
+            orr r1, r1, #0x1000
+            mov r0, #20
+            str r0, [r1, #4]
+            mov r0, #1
+            str r0, [r1, #8]
+            msr cpsr_c, #0x13
+        loop:
+            add r6, r6, #1
+            b loop
+        done:
+        ",
+    );
+    mock_board(&mut machine);
+
+    let steps = std::cell::Cell::new(0);
+    let result = machine.run_until(&HashSet::new(), &|| '\0', &mut |_| {}, || {
+        steps.set(steps.get() + 1);
+        steps.get() > 1000
+    });
+    assert_eq!(result, Ok(StopReason::EndOfProgram));
+    assert_eq!(machine.regs.r5, 3);
+    assert_eq!(machine.regs.mode(), ProcessorMode::Irq);
+    assert!(machine.regs.r6 > 10);
+}
+
+#[test]
+fn test_cycles() {
+    let mut machine = mock_machine(
+        "
+            mov r0, #1             @ 1S
+            add r0, r0, r0, lsl r0 @ 1S + 1I
+            mov r4, #0x10000       @ 1S
+            mul r1, r0, r4         @ 1S + 3I, the multiplier has 3 significant bytes
+            mvn r6, #0             @ 1S
+            smull r2, r3, r0, r6   @ 1S + 2I, -1 has 1 significant byte when signed
+            umull r2, r3, r0, r6   @ 1S + 5I
+            ldr r2, [sp, #-4]      @ 1S + 1N + 1I
+            str r2, [sp, #-4]      @ 2N
+            b next                 @ 2S + 1N
+        next:
+            moveq r3, #1           @ 1S, not executed
+        ",
+    );
+    machine
+        .run_until(&HashSet::new(), &|| '\0', &mut |_| {}, || false)
+        .unwrap();
+    assert_eq!(machine.cycles, Cycles::new(11, 4, 12));
+    assert_eq!(machine.cycles.total(), 27);
+    assert_eq!(machine.cycles.to_string(), "27 cycles (11 S, 4 N, 12 I)");
+
+    machine.reset();
+    assert_eq!(machine.cycles, Cycles::default());
+}
+
+#[test]
+fn test_trace() {
+    let mut machine = mock_machine(
+        "
+            mov r0, #3
+            str r0, [sp, #-4]!
+            ldrb r1, [sp]
+            cmp r0, #3
+            movne r2, #1
+            b end
+            mov r3, #1
+        end:
+        ",
+    );
+    machine.trace = Some(Trace::new());
+    machine
+        .run_until(&HashSet::new(), &|| '\0', &mut |_| {}, || false)
+        .unwrap();
+
+    let entries = machine.trace.as_ref().unwrap().entries();
+    assert_eq!(entries.len(), 6);
+    assert_eq!(entries[0].address, 0);
+    assert_eq!(entries[0].encoding, 0xe3a00003);
+    assert_eq!(entries[0].disassembly, "mov r0, #3");
+    assert_eq!(entries[0].register_writes, vec![(0, 3)]);
+
+    let sp = crate::STACK_TOP - 4;
+    assert_eq!(entries[1].register_writes, vec![(13, sp)]);
+    assert_eq!(
+        entries[1].memory,
+        vec![MemoryAccess {
+            address: sp,
+            size: 4,
+            value: 3,
+            write: true
+        }]
+    );
+    assert_eq!(
+        entries[2].memory,
+        vec![MemoryAccess {
+            address: sp,
+            size: 1,
+            value: 3,
+            write: false
+        }]
+    );
+
+    assert_eq!(entries[3].register_writes, vec![]);
+    assert_eq!(entries[3].cpsr, Some((0x10, 0x6000_0010)));
+    assert!(!entries[4].executed);
+    assert_eq!(entries[4].register_writes, vec![]);
+    // only a branch shows `pc`
+    assert_eq!(entries[5].register_writes, vec![(15, 0x1c)]);
+
+    machine.reset();
+    assert!(machine.trace.as_ref().unwrap().entries().is_empty());
+}
+
+#[test]
+fn test_trace_formats() {
+    let mut machine = mock_machine(
+        "
+            mov r0, #1
+            str r0, [sp, #-4]
+            cmp r0, #2
+            moveq r0, #2
+        ",
+    );
+    machine.trace = Some(Trace::new());
+    machine
+        .run_until(&HashSet::new(), &|| '\0', &mut |_| {}, || false)
+        .unwrap();
+    let trace = machine.trace.unwrap();
+
+    let write = |format| {
+        let mut out = Vec::new();
+        trace.write(format, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+    assert_eq!(
+        write(TraceFormat::Text),
+        "0x00000000  e3a00001  mov r0, #1                    r0=0x00000001
+0x00000004  e50d0004  str r0, [sp, #-4]             [0x000ffffc]<-0x1
+0x00000008  e3500002  cmp r0, #2                    cpsr=0x80000010
+0x0000000c  03a00002  moveq r0, #2                  (not executed)
+"
+    );
+    assert_eq!(
+        write(TraceFormat::JsonLines).lines().nth(1),
+        Some(
+            r#"{"address":4,"encoding":3842834436,"disassembly":"str r0, [sp, #-4]","executed":true,"registers":{},"cpsr":null,"memory":[{"address":1048572,"size":4,"value":1,"write":true}]}"#
+        )
+    );
+    assert_eq!(
+        write(TraceFormat::JsonLines).lines().nth(2),
+        Some(
+            r#"{"address":8,"encoding":3813670914,"disassembly":"cmp r0, #2","executed":true,"registers":{},"cpsr":[16,2147483664],"memory":[]}"#
+        )
+    );
+    assert_eq!(
+        write(TraceFormat::Csv).lines().collect::<Vec<_>>(),
+        vec![
+            "address,encoding,disassembly,executed,effects",
+            "0x00000000,0xe3a00001,\"mov r0, #1\",true,r0=0x00000001",
+            "0x00000004,0xe50d0004,\"str r0, [sp, #-4]\",true,[0x000ffffc]<-0x1",
+            "0x00000008,0xe3500002,\"cmp r0, #2\",true,cpsr=0x80000010",
+            "0x0000000c,0x03a00002,\"moveq r0, #2\",false,",
+        ]
+    );
+
+    assert_eq!(
+        TraceFormat::from_path(std::path::Path::new("run.jsonl")),
+        TraceFormat::JsonLines
+    );
+    assert_eq!(
+        TraceFormat::from_path(std::path::Path::new("run.csv")),
+        TraceFormat::Csv
+    );
+    assert_eq!(
+        TraceFormat::from_path(std::path::Path::new("run.log")),
+        TraceFormat::Text
+    );
+}
+
+#[test]
+fn test_trace_limit() {
+    let mut machine = mock_machine(
+        "
+            mov r0, #1
+            mov r1, #2
+            mov r2, #3
+        ",
+    );
+    machine.trace = Some(Trace::with_limit(2));
+    machine
+        .run_until(&HashSet::new(), &|| '\0', &mut |_| {}, || false)
+        .unwrap();
+
+    let trace = machine.trace.as_ref().unwrap();
+    assert_eq!(trace.dropped(), 1);
+    assert_eq!(
+        trace
+            .entries()
+            .iter()
+            .map(|e| e.address)
+            .collect::<Vec<_>>(),
+        vec![4, 8]
+    );
+    let mut out = Vec::new();
+    trace.write_text(&mut out).unwrap();
+    assert!(
+        String::from_utf8(out)
+            .unwrap()
+            .starts_with("... 1 earlier instructions\n")
+    );
+}
+
+#[test]
+fn test_step_back() {
+    let mut machine = mock_machine(
+        "
+            mov r0, #5
+            mov r1, #7
+            push {r0, r1}
+            strb r1, [sp, #1]
+            add r0, r0, r1
+        ",
+    );
+    machine.history = Some(History::new(100));
+    assert!(!machine.step_back());
+
+    let initial = (machine.regs.clone(), machine.cycles);
+    let mut states = Vec::new();
+    while machine.step(&|| '\0', &mut |_| {}).unwrap() == StopReason::Step {
+        states.push((
+            machine.regs.clone(),
+            machine.cycles,
+            machine
+                .memory
+                .read_bytes(crate::STACK_TOP - 8, 8)
+                .unwrap()
+                .to_vec(),
+        ));
+    }
+    // running off the end is not a step
+    assert_eq!(machine.history.as_ref().unwrap().len(), 5);
+    assert_eq!(machine.regs.r0, 12);
+
+    states.pop();
+    while let Some((regs, cycles, stack)) = states.pop() {
+        assert!(machine.step_back());
+        assert_eq!(machine.regs, regs);
+        assert_eq!(machine.cycles, cycles);
+        assert_eq!(
+            machine.memory.read_bytes(crate::STACK_TOP - 8, 8).unwrap(),
+            stack
+        );
+    }
+    assert!(machine.step_back());
+    assert_eq!((machine.regs.clone(), machine.cycles), initial);
+    assert_eq!(
+        machine.memory.read_bytes(crate::STACK_TOP - 8, 8).unwrap(),
+        [0; 8]
+    );
+    assert!(!machine.step_back());
+}
+
+#[test]
+fn test_reverse_until() {
+    let mut machine = mock_machine(
+        "
+            mov r0, #0
+        loop:
+            add r0, r0, #1
+            cmp r0, #3
+            bne loop
+            mov r1, #1
+        ",
+    );
+    machine.history = Some(History::new(100));
+    machine
+        .run_until(&HashSet::new(), &|| '\0', &mut |_| {}, || false)
+        .unwrap();
+    assert_eq!((machine.regs.r0, machine.regs.r1), (3, 1));
+
+    // back to the start of the last time round the loop
+    let breakpoints = HashSet::from([4]);
+    assert_eq!(
+        machine.reverse_until(&breakpoints, || false),
+        StopReason::Breakpoint(4)
+    );
+    assert_eq!((machine.regs.r0, machine.regs.r1), (2, 0));
+    assert_eq!(
+        machine.reverse_until(&breakpoints, || false),
+        StopReason::Breakpoint(4)
+    );
+    assert_eq!(machine.regs.r0, 1);
+
+    assert_eq!(
+        machine.reverse_until(&breakpoints, || true),
+        StopReason::Stopped
+    );
+    assert_eq!(
+        machine.reverse_until(&breakpoints, || false),
+        StopReason::Breakpoint(4)
+    );
+    assert_eq!(machine.regs.r0, 0);
+    assert_eq!(
+        machine.reverse_until(&breakpoints, || false),
+        StopReason::StartOfHistory
+    );
+    assert_eq!((machine.pc(), machine.regs.r0), (0, 0));
+}
+
+#[test]
+fn test_history_limit() {
+    let mut machine = mock_machine(
+        "
+            mov r0, #1
+            mov r0, #2
+            mov r0, #3
+        ",
+    );
+    machine.history = Some(History::new(2));
+    machine
+        .run_until(&HashSet::new(), &|| '\0', &mut |_| {}, || false)
+        .unwrap();
+
+    assert_eq!(
+        machine.reverse_until(&HashSet::new(), || false),
+        StopReason::StartOfHistory
+    );
+    // the first step was forgotten
+    assert_eq!((machine.pc(), machine.regs.r0), (4, 1));
+
+    machine.history = Some(History::new(0));
+    machine.step(&|| '\0', &mut |_| {}).unwrap();
+    assert!(!machine.step_back());
+}
+
+#[test]
+fn test_parse_watch() {
+    let parse = |s: &str| s.parse::<Watch>();
+    assert_eq!(parse("r4"), Ok(Watch::Register(4)));
+    assert_eq!(parse("R12"), Ok(Watch::Register(12)));
+    assert_eq!(parse("sp"), Ok(Watch::Register(13)));
+    assert_eq!(parse("cpsr"), Ok(Watch::Register(16)));
+    assert_eq!(parse("Z"), Ok(Watch::Flag(Flag::Zero)));
+    assert_eq!(parse("c"), Ok(Watch::Flag(Flag::Carry)));
+    assert_eq!(
+        parse("0x1000"),
+        Ok(Watch::Memory {
+            range: 0x1000..0x1001,
+            access: Access::ReadWrite
+        })
+    );
+    assert_eq!(
+        parse("0x1000..0x1010:w"),
+        Ok(Watch::Memory {
+            range: 0x1000..0x1010,
+            access: Access::Write
+        })
+    );
+    assert_eq!(
+        parse("4096:r"),
+        Ok(Watch::Memory {
+            range: 0x1000..0x1001,
+            access: Access::Read
+        })
+    );
+    assert!(parse("r16").is_err());
+    assert!(parse("0x1010..0x1000").is_err());
+    assert!(parse("0x1000:x").is_err());
+    assert!(parse("q").is_err());
+
+    for s in [
+        "r4",
+        "sp",
+        "cpsr",
+        "N",
+        "0x00001000",
+        "0x00001000..0x00001010:w",
+    ] {
+        assert_eq!(parse(s).unwrap().to_string(), s);
+    }
+}
+
+#[test]
+fn test_watch_register_and_flag() {
+    let mut machine = mock_machine(
+        "
+            mov r0, #1
+            mov r4, #2
+            mov r4, #2
+            subs r1, r0, #1
+            mov r4, #3
+        ",
+    );
+    machine.watches = vec![Watch::Register(4), Watch::Flag(Flag::Zero)];
+    let run = |machine: &mut Machine| {
+        machine
+            .run_until(&HashSet::new(), &|| '\0', &mut |_| {}, || false)
+            .unwrap()
+    };
+
+    assert_eq!(
+        run(&mut machine),
+        StopReason::Watchpoint {
+            index: 0,
+            address: 4
+        }
+    );
+    assert_eq!(machine.pc(), 8);
+    // writing the same value is not a change
+    assert_eq!(
+        run(&mut machine),
+        StopReason::Watchpoint {
+            index: 1,
+            address: 12
+        }
+    );
+    assert_eq!(
+        run(&mut machine),
+        StopReason::Watchpoint {
+            index: 0,
+            address: 16
+        }
+    );
+    assert_eq!(run(&mut machine), StopReason::EndOfProgram);
+}
+
+#[test]
+fn test_watch_memory() {
+    let mut machine = mock_machine(
+        "
+            ldr r1, =data
+            ldr r0, [r1]
+            strb r0, [r1, #6]
+            ldrh r2, [r1, #4]
+            str r0, [r1, #8]
+            b skip
+        data:
+            .word 5, 0, 0
+        skip:
+        ",
+    );
+    machine.watches = vec!["0x1c..0x20:w".parse().unwrap(), "0x1d:r".parse().unwrap()];
+    let run = |machine: &mut Machine| {
+        machine
+            .run_until(&HashSet::new(), &|| '\0', &mut |_| {}, || false)
+            .unwrap()
+    };
+
+    // the literal pool load and `ldr r0, [r1]` are not at the watched addresses
+    assert_eq!(
+        run(&mut machine),
+        StopReason::Watchpoint {
+            index: 0,
+            address: 8
+        }
+    );
+    assert_eq!(
+        run(&mut machine),
+        StopReason::Watchpoint {
+            index: 1,
+            address: 12
+        }
+    );
+    assert_eq!(run(&mut machine), StopReason::EndOfProgram);
+
+    // fetching a watched instruction is not a load
+    machine.reset();
+    machine.watches = vec!["0x0..0x18".parse().unwrap()];
+    assert_eq!(run(&mut machine), StopReason::EndOfProgram);
+}
@@ -0,0 +1,198 @@
+use std::{
+    env,
+    ffi::{OsStr, OsString},
+    fmt,
+    path::{Path, PathBuf},
+    process,
+};
+
+/// GNU assemblers for 32 bit ARM, in the order they are searched for on `PATH`
+pub const CANDIDATES: [&str; 3] = [
+    "arm-none-eabi-as",
+    "arm-linux-gnueabi-as",
+    "arm-linux-gnu-as",
+];
+
+/// Environment variable with the path, or command name, of the assembler to use
+pub const TOOLCHAIN_ENV: &str = "KOMODO_AS";
+
+/// Where a [`Toolchain`] was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToolchainSource {
+    /// Passed by the user, e.g. with `--as` or a setting
+    Configured,
+    /// The [`TOOLCHAIN_ENV`] environment variable
+    Environment,
+    /// One of the [`CANDIDATES`] on `PATH`
+    Path,
+}
+
+/// A GNU assembler used to build programs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Toolchain {
+    /// Path to the `as` executable
+    pub assembler: PathBuf,
+    pub source: ToolchainSource,
+}
+
+/// Why no assembler could be used
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ToolchainError {
+    /// A configured assembler, from `source`, is not an executable file
+    NotExecutable {
+        assembler: PathBuf,
+        source: ToolchainSource,
+    },
+    /// None of the [`CANDIDATES`] are on `PATH`
+    NotFound,
+}
+
+impl fmt::Display for ToolchainSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ToolchainSource::Configured => write!(f, "configured"),
+            ToolchainSource::Environment => write!(f, "from ${}", TOOLCHAIN_ENV),
+            ToolchainSource::Path => write!(f, "found on PATH"),
+        }
+    }
+}
+
+impl fmt::Display for Toolchain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.assembler.display(), self.source)
+    }
+}
+
+impl fmt::Display for ToolchainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ToolchainError::NotExecutable { assembler, source } => write!(
+                f,
+                "Assembler `{}` ({}) is not an executable file",
+                assembler.display(),
+                source
+            ),
+            ToolchainError::NotFound => {
+                write!(
+                    f,
+                    "Cannot find a GNU assembler for ARM, looked for {} on PATH",
+                    CANDIDATES.map(|name| format!("`{}`", name)).join(", ")
+                )?;
+                match install_hint() {
+                    Some(command) => write!(f, ", try running:\n\t{}", command)?,
+                    None => write!(f, ", install GNU Binutils for ARM")?,
+                }
+                write!(f, "\nor set ${} to the path of `as`", TOOLCHAIN_ENV)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ToolchainError {}
+
+impl Toolchain {
+    /// Finds an assembler, `configured` takes priority over [`TOOLCHAIN_ENV`]
+    /// which takes priority over searching `PATH` for the [`CANDIDATES`]
+    pub fn find(configured: Option<&Path>) -> Result<Toolchain, ToolchainError> {
+        Toolchain::search(
+            configured,
+            env::var_os(TOOLCHAIN_ENV).as_deref(),
+            env::var_os("PATH").as_deref(),
+        )
+    }
+
+    /// [`Toolchain::find`] with the environment variables passed in
+    pub(crate) fn search(
+        configured: Option<&Path>,
+        environment: Option<&OsStr>,
+        path: Option<&OsStr>,
+    ) -> Result<Toolchain, ToolchainError> {
+        let user = configured
+            .map(|assembler| (assembler.as_os_str(), ToolchainSource::Configured))
+            .or(environment
+                .filter(|assembler| !assembler.is_empty())
+                .map(|assembler| (assembler, ToolchainSource::Environment)));
+
+        let dirs: Vec<PathBuf> = path
+            .map(|path| env::split_paths(path).collect())
+            .unwrap_or_default();
+
+        if let Some((assembler, source)) = user {
+            let assembler = Path::new(assembler);
+            // a bare command name is looked up on `PATH`
+            let found = match assembler.components().count() {
+                1 => dirs
+                    .iter()
+                    .map(|dir| dir.join(assembler))
+                    .find(|candidate| is_executable(candidate)),
+                _ => Some(assembler.to_path_buf()).filter(|path| is_executable(path)),
+            };
+            return found
+                .map(|assembler| Toolchain { assembler, source })
+                .ok_or(ToolchainError::NotExecutable {
+                    assembler: assembler.to_path_buf(),
+                    source,
+                });
+        }
+
+        CANDIDATES
+            .iter()
+            .flat_map(|name| dirs.iter().map(move |dir| dir.join(name)))
+            .find(|candidate| is_executable(candidate))
+            .map(|assembler| Toolchain {
+                assembler,
+                source: ToolchainSource::Path,
+            })
+            .ok_or(ToolchainError::NotFound)
+    }
+
+    /// Assembles `input_path` into an object file at `output_path` for ARMv4,
+    /// with line number information. Errors are the messages `as` printed.
+    pub fn assemble(&self, input_path: OsString, output_path: OsString) -> Result<(), String> {
+        let output = process::Command::new(&self.assembler)
+            .arg("-march=armv4t")
+            // line number information for the debugger
+            .arg("-g")
+            .arg(input_path)
+            .arg("-o")
+            .arg(output_path)
+            .output()
+            .map_err(|e| format!("Cannot run `{}`: {}", self.assembler.display(), e))?;
+
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(String::from_utf8_lossy(&output.stderr).to_string())
+        }
+    }
+}
+
+fn is_executable(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        path.metadata()
+            .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+    }
+    #[cfg(not(unix))]
+    {
+        path.is_file()
+    }
+}
+
+/// Command that installs an assembler on this OS, if known
+fn install_hint() -> Option<&'static str> {
+    use os_info::Type::*;
+    match os_info::get().os_type() {
+        Debian | Ubuntu | Mint | Pop | Raspbian => {
+            Some("sudo apt install binutils-arm-linux-gnueabi")
+        }
+        Fedora | CentOS | RedHatEnterprise | AlmaLinux | RockyLinux => {
+            Some("sudo dnf install binutils-arm-linux-gnu")
+        }
+        Arch | Manjaro | EndeavourOS => Some("sudo pacman -S arm-none-eabi-binutils"),
+        NixOS => Some("nix-shell -p pkgsCross.arm-embedded.buildPackages.binutils"),
+        Macos => Some("brew install arm-none-eabi-binutils"),
+        _ => None,
+    }
+}
@@ -0,0 +1,281 @@
+use crate::{Decoded, Registers, StatusFlags, decode::RegNum, status_flags::THUMB_BIT};
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+    path::Path,
+};
+
+/// A load or store made by an instruction, `value` is zero extended for bytes
+/// and halfwords
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub address: u32,
+    /// 1, 2 or 4 bytes
+    pub size: u32,
+    pub value: u32,
+    pub write: bool,
+}
+
+/// What an instruction did when it was executed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub address: u32,
+    /// A halfword for 16 bit Thumb instructions
+    pub encoding: u32,
+    pub disassembly: String,
+    /// `false` if its condition failed
+    pub executed: bool,
+    /// Registers with a new value, `pc` only if it did not move on to the
+    /// next instruction
+    pub register_writes: Vec<(RegNum, u32)>,
+    /// The CPSR before and after, if it changed
+    pub cpsr: Option<(u32, u32)>,
+    pub memory: Vec<MemoryAccess>,
+}
+
+impl TraceEntry {
+    /// Compares the registers `before` and `after` executing `decoded`
+    pub(crate) fn new(
+        decoded: &Decoded,
+        (before, after): (&Registers, &Registers),
+        memory: Vec<MemoryAccess>,
+    ) -> TraceEntry {
+        let next = decoded.address.wrapping_add(decoded.size);
+        let register_writes = (0..16)
+            .filter(|&reg| match reg {
+                15 => after[reg] as u32 != next,
+                _ => after[reg] != before[reg],
+            })
+            .map(|reg| (reg, after[reg] as u32))
+            .collect();
+
+        TraceEntry {
+            address: decoded.address,
+            // Thumb encodings hold the halfword after them, 32 bit ones first halfword first
+            encoding: match (before.apsr & THUMB_BIT != 0, decoded.size) {
+                (true, 2) => decoded.encoding & 0xffff,
+                (true, _) => decoded.encoding.rotate_left(16),
+                (false, _) => decoded.encoding,
+            },
+            disassembly: decoded.text.clone(),
+            executed: decoded.condition.passes(&StatusFlags::from(before.apsr)),
+            register_writes,
+            cpsr: (before.apsr != after.apsr).then_some((before.apsr as u32, after.apsr as u32)),
+            memory,
+        }
+    }
+
+    /// `r0=0x00000001 cpsr=0x60000010 [0x00001000]->0x00000005`, the
+    /// arrow points the way the data went
+    fn effects(&self) -> String {
+        let registers = self
+            .register_writes
+            .iter()
+            .map(|(reg, value)| format!("{}={:#010x}", reg_name(*reg), value));
+        let cpsr = self
+            .cpsr
+            .iter()
+            .map(|(_, after)| format!("cpsr={:#010x}", after));
+        let memory = self.memory.iter().map(|access| match access.write {
+            true => format!("[{:#010x}]<-{:#x}", access.address, access.value),
+            false => format!("[{:#010x}]->{:#x}", access.address, access.value),
+        });
+        registers
+            .chain(cpsr)
+            .chain(memory)
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+}
+
+/// `r0`-`r12`, `sp`, `lr`, `pc` and `cpsr` for 16
+pub(crate) fn reg_name(reg: RegNum) -> String {
+    match reg {
+        13 => "sp".to_string(),
+        14 => "lr".to_string(),
+        15 => "pc".to_string(),
+        16 => "cpsr".to_string(),
+        _ => format!("r{}", reg),
+    }
+}
+
+/// How a [`Trace`] is written out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// One line per instruction, for reading
+    Text,
+    /// One JSON object per line
+    JsonLines,
+    Csv,
+}
+
+impl TraceFormat {
+    /// Picked by the extension of `path`, `.jsonl` or `.json` and `.csv`,
+    /// anything else is text
+    pub fn from_path(path: &Path) -> TraceFormat {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("jsonl" | "json") => TraceFormat::JsonLines,
+            Some("csv") => TraceFormat::Csv,
+            _ => TraceFormat::Text,
+        }
+    }
+}
+
+/// Instructions the CLI and the app keep in a trace, older ones are dropped
+pub const TRACE_LIMIT: usize = 1_000_000;
+
+/// The instructions executed by a [`crate::Machine`] while it has a trace,
+/// oldest first
+#[derive(Debug, Clone, Default)]
+pub struct Trace {
+    entries: VecDeque<TraceEntry>,
+    /// Older entries are dropped beyond this many
+    limit: Option<usize>,
+    /// Entries dropped to stay within `limit`
+    dropped: u64,
+}
+
+impl Trace {
+    /// Keeps every instruction
+    pub fn new() -> Trace {
+        Trace::default()
+    }
+
+    /// Keeps the last `limit` instructions
+    pub fn with_limit(limit: usize) -> Trace {
+        Trace {
+            limit: Some(limit),
+            ..Trace::default()
+        }
+    }
+
+    pub fn entries(&self) -> &VecDeque<TraceEntry> {
+        &self.entries
+    }
+
+    /// Instructions executed before the oldest entry kept
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Forgets every entry, keeping the limit
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.dropped = 0;
+    }
+
+    pub(crate) fn push(&mut self, entry: TraceEntry) {
+        if self.limit.is_some_and(|limit| self.entries.len() >= limit) {
+            self.entries.pop_front();
+            self.dropped += 1;
+        }
+        self.entries.push_back(entry);
+    }
+
+    pub fn write(&self, format: TraceFormat, out: &mut impl Write) -> io::Result<()> {
+        match format {
+            TraceFormat::Text => self.write_text(out),
+            TraceFormat::JsonLines => self.write_json_lines(out),
+            TraceFormat::Csv => self.write_csv(out),
+        }
+    }
+
+    /// `0x00000008  e0810002  add r0, r1, r2  r0=0x00000003`
+    pub fn write_text(&self, out: &mut impl Write) -> io::Result<()> {
+        if self.dropped > 0 {
+            writeln!(out, "... {} earlier instructions", self.dropped)?;
+        }
+        for entry in &self.entries {
+            let effects = match entry.executed {
+                true => entry.effects(),
+                false => "(not executed)".to_string(),
+            };
+            let line = format!(
+                "{:#010x}  {:08x}  {:<28}  {}",
+                entry.address, entry.encoding, entry.disassembly, effects
+            );
+            writeln!(out, "{}", line.trim_end())?;
+        }
+        Ok(())
+    }
+
+    /// `{"address":8,"encoding":3766550530,"disassembly":"add r0, r1, r2",
+    /// "executed":true,"registers":{"r0":3},"cpsr":null,"memory":[]}`
+    pub fn write_json_lines(&self, out: &mut impl Write) -> io::Result<()> {
+        for entry in &self.entries {
+            let registers = entry
+                .register_writes
+                .iter()
+                .map(|(reg, value)| format!("\"{}\":{}", reg_name(*reg), value))
+                .collect::<Vec<String>>()
+                .join(",");
+            let cpsr = match entry.cpsr {
+                Some((before, after)) => format!("[{},{}]", before, after),
+                None => "null".to_string(),
+            };
+            let memory = entry
+                .memory
+                .iter()
+                .map(|access| {
+                    format!(
+                        "{{\"address\":{},\"size\":{},\"value\":{},\"write\":{}}}",
+                        access.address, access.size, access.value, access.write
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join(",");
+            writeln!(
+                out,
+                "{{\"address\":{},\"encoding\":{},\"disassembly\":{},\"executed\":{},\"registers\":{{{}}},\"cpsr\":{},\"memory\":[{}]}}",
+                entry.address,
+                entry.encoding,
+                json_string(&entry.disassembly),
+                entry.executed,
+                registers,
+                cpsr,
+                memory
+            )?;
+        }
+        Ok(())
+    }
+
+    /// A header row then one row per instruction, the effects in a single
+    /// column as in the text listing
+    pub fn write_csv(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "address,encoding,disassembly,executed,effects")?;
+        for entry in &self.entries {
+            writeln!(
+                out,
+                "{:#010x},{:#010x},{},{},{}",
+                entry.address,
+                entry.encoding,
+                csv_field(&entry.disassembly),
+                entry.executed,
+                csv_field(&entry.effects())
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Quoted if it holds a comma or a quote, `ldr r0, [r1]` becomes `"ldr r0, [r1]"`
+fn csv_field(s: &str) -> String {
+    match s.contains([',', '"', '\n']) {
+        true => format!("\"{}\"", s.replace('"', "\"\"")),
+        false => s.to_string(),
+    }
+}
@@ -0,0 +1,174 @@
+use crate::{MemoryAccess, Registers, StatusFlags, decode::RegNum, trace::reg_name};
+use std::{fmt, ops::Range, str::FromStr};
+
+/// A flag of the CPSR
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flag {
+    Negative,
+    Zero,
+    Carry,
+    Overflow,
+    Thumb,
+}
+
+impl Flag {
+    /// `N`, `Z`, `C`, `V` or `T`
+    fn letter(self) -> char {
+        match self {
+            Flag::Negative => 'N',
+            Flag::Zero => 'Z',
+            Flag::Carry => 'C',
+            Flag::Overflow => 'V',
+            Flag::Thumb => 'T',
+        }
+    }
+
+    fn is_set(self, cpsr: i32) -> bool {
+        let flags = StatusFlags::from(cpsr);
+        match self {
+            Flag::Negative => flags.negative,
+            Flag::Zero => flags.zero,
+            Flag::Carry => flags.carry,
+            Flag::Overflow => flags.overflow,
+            Flag::Thumb => flags.thumb,
+        }
+    }
+}
+
+/// The loads or stores a memory watch is met by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+/// A condition that stops execution once an instruction meets it, see
+/// [`crate::Machine::watches`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Watch {
+    /// `r0`-`r15` change value, or the CPSR for 16
+    Register(RegNum),
+    /// The flag is set or cleared
+    Flag(Flag),
+    /// A load or store touches a byte of `range`
+    Memory { range: Range<u32>, access: Access },
+}
+
+impl Watch {
+    /// `true` if the instruction that changed the registers from `before` to
+    /// `after`, making `accesses`, meets the watch
+    pub(crate) fn is_met(
+        &self,
+        before: &Registers,
+        after: &Registers,
+        accesses: &[MemoryAccess],
+    ) -> bool {
+        match self {
+            Watch::Register(reg) => before[*reg] != after[*reg],
+            Watch::Flag(flag) => flag.is_set(before.apsr) != flag.is_set(after.apsr),
+            Watch::Memory { range, access } => accesses.iter().any(|a| {
+                let end = a.address as u64 + a.size as u64;
+                let kind = match *access {
+                    Access::Read => !a.write,
+                    Access::Write => a.write,
+                    Access::ReadWrite => true,
+                };
+                kind && a.address < range.end && end > range.start as u64
+            }),
+        }
+    }
+}
+
+/// Register numbers by name, `cpsr` is 16
+fn reg_number(name: &str) -> Option<RegNum> {
+    match name {
+        "sp" => Some(13),
+        "lr" => Some(14),
+        "pc" => Some(15),
+        "cpsr" | "apsr" => Some(16),
+        _ => name.strip_prefix('r')?.parse().ok().filter(|&reg| reg < 16),
+    }
+}
+
+/// Hexadecimal with a `0x` prefix, decimal otherwise
+fn parse_address(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// `r4`, `sp` or `cpsr` for registers, `N`, `Z`, `C`, `V` or `T` for flags, and
+/// `0x1000` or `0x1000..0x1010` for the bytes of memory up to the end of the
+/// range, followed by `:r` or `:w` to watch only loads or stores
+impl FromStr for Watch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Watch, String> {
+        let invalid = || {
+            format!(
+                "Invalid watch `{}`, expected a register such as `r4`, a flag such as `Z` \
+                 or an address range such as `0x1000..0x1010:w`",
+                s
+            )
+        };
+        let name = s.trim().to_lowercase();
+
+        if let Some(reg) = reg_number(&name) {
+            return Ok(Watch::Register(reg));
+        }
+        let flag = match name.as_str() {
+            "n" => Some(Flag::Negative),
+            "z" => Some(Flag::Zero),
+            "c" => Some(Flag::Carry),
+            "v" => Some(Flag::Overflow),
+            "t" => Some(Flag::Thumb),
+            _ => None,
+        };
+        if let Some(flag) = flag {
+            return Ok(Watch::Flag(flag));
+        }
+
+        let (range, access) = match name.rsplit_once(':') {
+            Some((range, "r")) => (range, Access::Read),
+            Some((range, "w")) => (range, Access::Write),
+            Some(_) => return Err(invalid()),
+            None => (name.as_str(), Access::ReadWrite),
+        };
+        let range = match range.split_once("..") {
+            Some((start, end)) => {
+                let start = parse_address(start).ok_or_else(invalid)?;
+                start..parse_address(end).ok_or_else(invalid)?
+            }
+            None => {
+                let address = parse_address(range).ok_or_else(invalid)?;
+                address..address.saturating_add(1)
+            }
+        };
+        match range.is_empty() {
+            true => Err(invalid()),
+            false => Ok(Watch::Memory { range, access }),
+        }
+    }
+}
+
+impl fmt::Display for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Watch::Register(reg) => write!(f, "{}", reg_name(*reg)),
+            Watch::Flag(flag) => write!(f, "{}", flag.letter()),
+            Watch::Memory { range, access } => {
+                write!(f, "{:#010x}", range.start)?;
+                if range.len() > 1 {
+                    write!(f, "..{:#010x}", range.end)?;
+                }
+                match access {
+                    Access::Read => write!(f, ":r"),
+                    Access::Write => write!(f, ":w"),
+                    Access::ReadWrite => Ok(()),
+                }
+            }
+        }
+    }
+}